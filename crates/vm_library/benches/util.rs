@@ -14,7 +14,8 @@ use serde::{de::Visitor, ser::SerializeTuple, Deserialize, Serialize};
 
 use vm_library::{
     outgoing::{InitVec, SetOwned},
-    ticket_table::TicketId,
+    ticket_table::{TicketEvent, TicketId},
+    vm_server::ContractEvent,
 };
 #[derive(Serialize, Deserialize)]
 pub struct TicketDeposit {
@@ -25,10 +26,12 @@ pub struct TicketDeposit {
 pub enum ServerMessage {
     Init(InitVec),
     Stop,
-    Set(SetOwned),
+    Set(Box<SetOwned>),
     TakeTickets(String),
     DepositTickets(TicketDeposit),
     Error(String),
+    Receipt(Vec<TicketEvent>),
+    Events(Vec<ContractEvent>),
 }
 impl Serialize for ServerMessage {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -71,6 +74,18 @@ impl Serialize for ServerMessage {
                 seq.serialize_element(s)?;
                 seq.end()
             }
+            ServerMessage::Receipt(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Receipt")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::Events(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Events")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
         }
     }
 }
@@ -144,6 +159,30 @@ impl<'de> Visitor<'de> for ServerVisitor {
                         |x| Ok(ServerMessage::DepositTickets(x)),
                     )
                 }
+                "Receipt" => {
+                    let elem = seq.next_element::<Vec<TicketEvent>>()?;
+                    elem.map_or_else(
+                        || {
+                            Err(serde::de::Error::invalid_type(
+                                serde::de::Unexpected::Str("unexpected sequence"),
+                                &"value",
+                            ))
+                        },
+                        |x| Ok(ServerMessage::Receipt(x)),
+                    )
+                }
+                "Events" => {
+                    let elem = seq.next_element::<Vec<ContractEvent>>()?;
+                    elem.map_or_else(
+                        || {
+                            Err(serde::de::Error::invalid_type(
+                                serde::de::Unexpected::Str("unexpected sequence"),
+                                &"value",
+                            ))
+                        },
+                        |x| Ok(ServerMessage::Events(x)),
+                    )
+                }
                 "Stop" => Ok(ServerMessage::Stop),
                 _ => Err(serde::de::Error::invalid_type(
                     serde::de::Unexpected::Str("unexpected sequence"),
@@ -273,6 +312,9 @@ pub fn originate(operation: String) -> impl FnMut(&mut IO) {
         operation,
         operation_raw_hash: "test".to_string(),
         tickets: vec![],
+        protocol_version: None,
+        trace: false,
+        profile: false,
     };
     let msg =
         serde_json::to_string(&ClientMessage::Transaction(t)).expect("Failed to write to pipe");
@@ -282,7 +324,7 @@ pub fn originate(operation: String) -> impl FnMut(&mut IO) {
         loop {
             match io.read() {
                 ServerMessage::DepositTickets(_) => continue,
-                ServerMessage::Set(SetOwned { key: _, value: _ }) => {
+                ServerMessage::Set(_) => {
                     continue;
                 }
                 ServerMessage::Stop => break,
@@ -301,6 +343,9 @@ pub fn invoke(operation: String) -> impl FnMut(&mut IO) {
         operation,
         operation_raw_hash: "test".to_string(),
         tickets: vec![],
+        protocol_version: None,
+        trace: false,
+        profile: false,
     };
     let msg =
         serde_json::to_string(&ClientMessage::Transaction(t)).expect("Failed to write to pipe");
@@ -312,7 +357,7 @@ pub fn invoke(operation: String) -> impl FnMut(&mut IO) {
         loop {
             match io.read() {
                 ServerMessage::DepositTickets(_) => continue,
-                ServerMessage::Set(SetOwned { key: _, value: _ }) => (),
+                ServerMessage::Set(_) => (),
                 ServerMessage::Stop => break,
                 ServerMessage::Error(_) => {
                     todo!()
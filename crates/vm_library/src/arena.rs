@@ -2,13 +2,17 @@ use fnv::FnvHashMap;
 use im_rc::{ordmap, ordset, vector};
 use slotmap::{DefaultKey, SlotMap};
 
-use crate::{managed::value::Value, ticket_table::Ticket};
-use once_cell::unsync::Lazy;
+use crate::managed::value::Value;
 
-pub static mut ARENA: Lazy<SlotMap<DefaultKey, Value>> = Lazy::new(|| SlotMap::with_capacity(2000));
+/// Values handed to a contract instance, addressed by the keys it passes back
+/// to host functions.
+pub type Arena = SlotMap<DefaultKey, Value>;
 
-use std::collections::BTreeMap;
-pub static mut PREDEF: Lazy<FnvHashMap<String, Value>> = Lazy::new(|| {
+pub fn new_arena() -> Arena {
+    SlotMap::with_capacity(2000)
+}
+
+pub fn new_predef(sender: String, self_: String, source: String) -> FnvHashMap<String, Value> {
     let mut map = FnvHashMap::with_capacity_and_hasher(16, Default::default());
     map.insert("none".to_owned(), Value::Option(None));
     map.insert("unit".to_owned(), Value::Unit);
@@ -19,22 +23,16 @@ pub static mut PREDEF: Lazy<FnvHashMap<String, Value>> = Lazy::new(|| {
     map.insert("empty_set".to_owned(), Value::Set(ordset![]));
     map.insert("empty_map".to_owned(), Value::Map(ordmap! {}));
     map.insert("zero".to_owned(), Value::Int(0.into()));
-    map
-});
-pub static mut CONSTANTS: Lazy<Vec<Value>> = Lazy::new(|| Vec::with_capacity(3000));
-pub static mut TICKETS: Lazy<BTreeMap<usize, Ticket>> = Lazy::new(BTreeMap::new);
-
-pub fn populate_predef(sender: String, self_: String, source: String) {
-    let map = unsafe { &mut PREDEF };
 
     map.insert("source".to_owned(), Value::String(source));
     map.insert("sender".to_owned(), Value::String(sender));
     map.insert("self".to_owned(), Value::String(self_));
+    map
 }
-pub fn push_constants(vec: &[(u32, Value)]) {
-    let map = unsafe { &mut CONSTANTS };
-    map.clear();
+pub fn new_constants(vec: &[(u32, Value)]) -> Vec<Value> {
+    let mut constants = Vec::with_capacity(vec.len());
     vec.iter().for_each(|(k, v)| {
-        map.insert(*k as usize, v.clone());
-    })
+        constants.insert(*k as usize, v.clone());
+    });
+    constants
 }
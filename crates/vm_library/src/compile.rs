@@ -1,14 +1,79 @@
+use std::borrow::Cow;
+
 use wasmer::{wat2wasm, Module};
 
 use crate::{
-    compile_store::new_compile_store,
+    compile_store::{compile_store_for, FeatureSet},
     errors::{vm::VmError, VMResult},
+    validate::validate_module,
 };
 
-pub fn compile_managed_module(m: &[u8]) -> VMResult<Module> {
-    wasmer::Module::new(
-        &new_compile_store(),
-        wat2wasm(m).map_err(|_| VmError::CompileErr("failed to compile module".to_owned()))?,
-    )
-    .map_err(std::convert::Into::into)
+const WASM_MAGIC: &[u8] = b"\0asm";
+const HEX_MAGIC: &str = "0061736d";
+const BASE64_MAGIC: &str = "AGFzbQ";
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let chunks = s.as_bytes().chunks_exact(2);
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+    chunks
+        .map(|byte| u8::from_str_radix(std::str::from_utf8(byte).ok()?, 16).ok())
+        .collect()
+}
+
+/// Returns the binary form of `m`, which is either raw wasm, hex or base64
+/// encoded wasm, or WAT text. Encoded modules are recognised by their magic.
+pub fn decode_module(m: &[u8]) -> VMResult<Cow<'_, [u8]>> {
+    if m.starts_with(WASM_MAGIC) {
+        return Ok(Cow::Borrowed(m));
+    }
+    let text = std::str::from_utf8(m).map(str::trim);
+    let decoded = match text {
+        Ok(s) if s.starts_with(HEX_MAGIC) => decode_hex(s),
+        Ok(s) if s.starts_with(BASE64_MAGIC) => base64::decode(s).ok(),
+        _ => {
+            return wat2wasm(m)
+                .map_err(|_| VmError::CompileErr("failed to compile module".to_owned()))
+        }
+    };
+    decoded
+        .filter(|x| x.starts_with(WASM_MAGIC))
+        .map(Cow::Owned)
+        .ok_or_else(|| VmError::CompileErr("failed to decode binary module".to_owned()))
+}
+
+pub fn compile_managed_module(m: &[u8], features: &FeatureSet) -> VMResult<Module> {
+    let wasm = decode_module(m)?;
+    validate_module(&wasm, features)?;
+    wasmer::Module::new(&compile_store_for(features), wasm).map_err(std::convert::Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::wat2wasm;
+
+    use super::{compile_managed_module, decode_module};
+    use crate::{compile_store::FeatureSet, fixtures::unit_contract};
+
+    #[test]
+    fn accepts_encoded_binary_modules() {
+        let module = unit_contract();
+        let wasm = wat2wasm(module.as_bytes()).unwrap().into_owned();
+        let hex: String = wasm.iter().map(|b| format!("{:02x}", b)).collect();
+        let b64 = base64::encode(&wasm);
+        assert_eq!(decode_module(module.as_bytes()).unwrap(), wasm);
+        assert_eq!(decode_module(&wasm).unwrap(), wasm);
+        assert_eq!(decode_module(hex.as_bytes()).unwrap(), wasm);
+        assert_eq!(decode_module(b64.as_bytes()).unwrap(), wasm);
+        assert!(compile_managed_module(b64.as_bytes(), &FeatureSet::default()).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_imports() {
+        let module = unit_contract().replace(r#""env" "unit""#, r#""env" "launch_missiles""#);
+        assert!(compile_managed_module(module.as_bytes(), &FeatureSet::default()).is_err());
+        let module = unit_contract().replace(r#""env" "unit""#, r#""wasi" "unit""#);
+        assert!(compile_managed_module(module.as_bytes(), &FeatureSet::default()).is_err());
+    }
 }
@@ -1,6 +1,12 @@
-use std::sync::Arc;
+use std::{ptr::NonNull, sync::Arc};
 
-use wasmer::{wasmparser::Operator, CompilerConfig, Features, Store, Universal};
+use loupe::MemoryUsage;
+use wasmer::{
+    vm::{self, MemoryError, MemoryStyle, TableStyle, VMMemoryDefinition, VMTableDefinition},
+    wasmparser::Operator,
+    BaseTunables, CompilerConfig, Features, MemoryType, Pages, Store, TableType, Target, Tunables,
+    Universal,
+};
 use wasmer_compiler_singlepass::Singlepass;
 use wasmer_middlewares::Metering;
 
@@ -9,7 +15,47 @@ const fn cost_fn(_operator: &Operator) -> u64 {
     // TODO: find good cost based on benchmarking
     100
 }
+/// Wasm features and limits contracts may use under a protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureSet {
+    pub floats: bool,
+    pub simd: bool,
+    pub bulk_memory: bool,
+    pub reference_types: bool,
+    pub max_memory_pages: u32,
+}
+pub const CURRENT_PROTOCOL: u32 = 1;
+impl FeatureSet {
+    /// Version 0 is the original permissive store, later versions only allow
+    /// features whose results do not depend on the host.
+    pub const fn for_protocol(version: u32) -> Self {
+        match version {
+            0 => Self {
+                floats: true,
+                simd: true,
+                bulk_memory: true,
+                reference_types: true,
+                max_memory_pages: 65536,
+            },
+            _ => Self {
+                floats: false,
+                simd: false,
+                bulk_memory: true,
+                reference_types: true,
+                max_memory_pages: 256,
+            },
+        }
+    }
+}
+impl Default for FeatureSet {
+    fn default() -> Self {
+        Self::for_protocol(CURRENT_PROTOCOL)
+    }
+}
 pub fn new_compile_store() -> Store {
+    compile_store_for(&FeatureSet::default())
+}
+pub fn compile_store_for(feature_set: &FeatureSet) -> Store {
     let metering = Arc::new(Metering::new(0, cost_fn));
     let mut compiler_config = Singlepass::default();
     compiler_config.push_middleware(metering);
@@ -17,13 +63,16 @@ pub fn new_compile_store() -> Store {
     let mut features = Features::default();
     // //features.module_linking(true);
     features.multi_value(false);
-    features.reference_types(true);
-    features.bulk_memory(true);
-    features.simd(true);
+    features.reference_types(feature_set.reference_types);
+    features.bulk_memory(feature_set.bulk_memory);
+    features.simd(feature_set.simd);
     let runtime = runtime.features(features);
-    Store::new(&runtime.engine())
+    Store::new_with_tunables(&runtime.engine(), LimitingTunables::new(feature_set))
 }
 pub fn new_headless() -> Store {
+    headless_for(&FeatureSet::default())
+}
+pub fn headless_for(feature_set: &FeatureSet) -> Store {
     let runtime = Universal::headless();
     let mut features = Features::default();
     // //features.module_linking(true);
@@ -31,5 +80,80 @@ pub fn new_headless() -> Store {
     features.reference_types(true);
     features.simd(true);
     let runtime = runtime.features(features);
-    Store::new(&runtime.engine())
+    Store::new_with_tunables(&runtime.engine(), LimitingTunables::new(feature_set))
+}
+
+/// Caps the linear memory of every instance at `max_memory_pages`, so that
+/// `memory.grow` fails inside the contract instead of exhausting the host.
+#[derive(MemoryUsage)]
+pub struct LimitingTunables {
+    limit: Pages,
+    base: BaseTunables,
+}
+impl LimitingTunables {
+    pub fn new(feature_set: &FeatureSet) -> Self {
+        Self {
+            limit: Pages(feature_set.max_memory_pages),
+            base: BaseTunables::for_target(&Target::default()),
+        }
+    }
+    fn adjust_memory(&self, requested: &MemoryType) -> MemoryType {
+        let mut adjusted = *requested;
+        if requested.maximum.is_none_or(|max| max > self.limit) {
+            adjusted.maximum = Some(self.limit);
+        }
+        adjusted
+    }
+    fn validate_memory(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        if ty.minimum > self.limit {
+            return Err(MemoryError::Generic(format!(
+                "minimum of {} pages exceeds the limit of {}",
+                ty.minimum.0, self.limit.0
+            )));
+        }
+        Ok(())
+    }
+}
+impl Tunables for LimitingTunables {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(&self.adjust_memory(memory))
+    }
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<Arc<dyn vm::Memory>, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        self.base.create_host_memory(&adjusted, style)
+    }
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<Arc<dyn vm::Memory>, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        self.base
+            .create_vm_memory(&adjusted, style, vm_definition_location)
+    }
+    fn create_host_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+    ) -> Result<Arc<dyn vm::Table>, String> {
+        self.base.create_host_table(ty, style)
+    }
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<Arc<dyn vm::Table>, String> {
+        self.base.create_vm_table(ty, style, vm_definition_location)
+    }
 }
@@ -78,4 +78,30 @@ impl ContractAddress {
             entrypoint: None,
         }
     }
+    /// The address of the `nonce`-th contract originated by an operation.
+    /// The first one keeps the address top-level originations always had.
+    pub fn originated(operation_hash: &[u8], nonce: u32) -> Self {
+        if nonce == 0 {
+            return Self::new(operation_hash);
+        }
+        let mut s = operation_hash.to_vec();
+        s.extend_from_slice(&nonce.to_be_bytes());
+        Self::new(&s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContractAddress;
+
+    #[test]
+    fn originated_addresses_depend_on_nonce() {
+        let hash = b"opCAkifFMh1Ya2J4WhRHskaXc297ELtx32wnc2WzeNtdQHp7DW4";
+        let first = ContractAddress::originated(hash, 0);
+        let second = ContractAddress::originated(hash, 1);
+        assert_ne!(first, second);
+        assert_eq!(first, ContractAddress::originated(hash, 0));
+        assert!(first.address.starts_with("DK1"));
+        assert_eq!(first, ContractAddress::new(hash));
+    }
 }
@@ -1,17 +1,47 @@
 use crate::{
-    arena::ARENA,
+    arena::{new_arena, Arena},
+    contract_address::ContractAddress,
     errors::{vm::VmError, VMResult},
     managed::value::Value,
+    profile::ProfileSink,
     ticket_table::TicketTable,
+    trace::TraceSink,
+    view::Views,
 };
+use fnv::FnvHashMap;
 use slotmap::{DefaultKey, Key};
-use std::{cell::RefCell, ptr::NonNull, rc::Rc};
-use wasmer::{HostEnvInitError, Instance, WasmerEnv};
+use std::{
+    cell::{Cell, RefCell},
+    ptr::NonNull,
+    rc::Rc,
+};
+use wasmer::{HostEnvInitError, Instance, RuntimeError, WasmerEnv};
 use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
 
+/// How deeply contract code may re-enter itself through host functions such
+/// as `exec` before the invocation fails with `VmError::StackOverflow`.
+pub const MAX_CALL_DEPTH: u32 = 256;
+
+/// The state of an invocation, kept by the thread running it.
+#[derive(Clone)]
+struct Shared {
+    table: Rc<RefCell<TicketTable>>,
+    inner: Rc<RefCell<Inner>>,
+}
+
+thread_local! {
+    static CONTEXTS: RefCell<FnvHashMap<u64, Shared>> = RefCell::default();
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// The host environment of an instance. Wasmer 2 requires it to be `Send`
+/// and `Sync`, so it only carries an id: the state it stands for stays with
+/// the thread that made it, and cannot be reached from any other. The
+/// context returned by `Context::new` owns that state and drops it with
+/// itself; its clones, one per import, only refer to it.
 pub struct Context {
-    pub table: Rc<RefCell<TicketTable>>,
-    pub inner: Rc<RefCell<Inner>>,
+    id: u64,
+    owner: bool,
 }
 
 pub struct Inner {
@@ -20,13 +50,56 @@ pub struct Inner {
     pub gas_limit: u64,
     pub call_unit: Option<NonNull<wasmer::NativeFunc<(i64, i32), ()>>>,
     pub call: Option<NonNull<wasmer::NativeFunc<(i64, i32), i64>>>,
+    pub operation_hash: Vec<u8>,
+    pub origination_nonce: u32,
+    pub call_depth: u32,
+    pub views: Option<Views>,
+    pub trace: Option<TraceSink>,
+    pub profile: Option<ProfileSink>,
+    pub arena: Arena,
+    pub predef: FnvHashMap<String, Value>,
+    pub constants: Vec<Value>,
+}
+
+impl Inner {
+    pub fn new(
+        operation_hash: Vec<u8>,
+        origination_nonce: u32,
+        predef: FnvHashMap<String, Value>,
+        constants: Vec<Value>,
+    ) -> Self {
+        Self {
+            instance: None,
+            pusher: None,
+            gas_limit: 10000,
+            call_unit: None,
+            call: None,
+            operation_hash,
+            origination_nonce,
+            call_depth: 0,
+            views: None,
+            trace: None,
+            profile: None,
+            arena: new_arena(),
+            predef,
+            constants,
+        }
+    }
 }
 
 impl Clone for Context {
     fn clone(&self) -> Self {
         Context {
-            table: Rc::clone(&self.table),
-            inner: Rc::clone(&self.inner),
+            id: self.id,
+            owner: false,
+        }
+    }
+}
+impl Drop for Context {
+    fn drop(&mut self) {
+        if self.owner {
+            // The thread may be tearing its locals down already.
+            let _ = CONTEXTS.try_with(|contexts| contexts.borrow_mut().remove(&self.id));
         }
     }
 }
@@ -35,16 +108,44 @@ impl WasmerEnv for Context {
         Ok(())
     }
 }
-unsafe impl Send for Context {}
-
-unsafe impl Sync for Context {}
-
 impl Context {
+    pub fn new(table: Rc<RefCell<TicketTable>>, inner: Inner) -> Self {
+        let id = NEXT_ID.with(|next| {
+            let id = next.get();
+            next.set(id + 1);
+            id
+        });
+        let shared = Shared {
+            table,
+            inner: Rc::new(RefCell::new(inner)),
+        };
+        CONTEXTS.with(|contexts| contexts.borrow_mut().insert(id, shared));
+        Self { id, owner: true }
+    }
+    fn shared(&self) -> Option<Shared> {
+        CONTEXTS
+            .try_with(|contexts| contexts.borrow().get(&self.id).cloned())
+            .ok()
+            .flatten()
+    }
+    /// The state of the invocation. Only the thread that made the context
+    /// can reach it, and only while the context returned by `new` lives.
+    pub fn inner(&self) -> Rc<RefCell<Inner>> {
+        self.shared()
+            .expect("context used outside its invocation")
+            .inner
+    }
+    pub fn table(&self) -> Rc<RefCell<TicketTable>> {
+        self.shared()
+            .expect("context used outside its invocation")
+            .table
+    }
     pub fn with_instance<C, R>(&self, callback: C) -> VMResult<R>
     where
         C: FnOnce(&Instance) -> VMResult<R>,
     {
-        match self.inner.as_ref().borrow().instance {
+        let instance = self.inner().borrow().instance;
+        match instance {
             Some(instance_ptr) => {
                 let instance_ref = unsafe { instance_ptr.as_ref() };
                 callback(instance_ref)
@@ -55,17 +156,17 @@ impl Context {
         }
     }
     pub fn set_instance(&self, wasmer_instance: Option<NonNull<Instance>>) {
-        self.inner.as_ref().borrow_mut().instance = wasmer_instance;
+        self.inner().borrow_mut().instance = wasmer_instance;
     }
     pub fn set_pusher(&self, pusher: Option<NonNull<wasmer::NativeFunc<i64, ()>>>) {
-        self.inner.as_ref().borrow_mut().pusher = pusher;
+        self.inner().borrow_mut().pusher = pusher;
     }
 
     pub fn set_call_unit(&self, f: Option<NonNull<wasmer::NativeFunc<(i64, i32), ()>>>) {
-        self.inner.as_ref().borrow_mut().call_unit = f;
+        self.inner().borrow_mut().call_unit = f;
     }
     pub fn set_call(&self, f: Option<NonNull<wasmer::NativeFunc<(i64, i32), i64>>>) {
-        self.inner.as_ref().borrow_mut().call = f;
+        self.inner().borrow_mut().call = f;
     }
     pub fn get_gas_left(&self) -> u64 {
         self.with_instance(|instance| {
@@ -97,7 +198,8 @@ impl Context {
         }
     }
     pub fn push_value(&self, value: i64) -> VMResult<()> {
-        match self.inner.as_ref().borrow().pusher {
+        let pusher = self.inner().borrow().pusher;
+        match pusher {
             Some(instance_ptr) => {
                 let func = unsafe { instance_ptr.as_ref() };
                 func.call(value)
@@ -108,12 +210,66 @@ impl Context {
             )),
         }
     }
+    /// Marks `function` as running in the trace and the profile, if any,
+    /// until `leave`.
+    pub fn enter(&self, function: impl FnOnce() -> String) {
+        let (trace, profile) = self.observers();
+        if trace.is_none() && profile.is_none() {
+            return;
+        }
+        let function = function();
+        if let Some(trace) = trace {
+            trace.borrow_mut().enter(function.clone())
+        }
+        if let Some(profile) = profile {
+            let gas = self.get_gas_left();
+            profile.borrow_mut().enter_function(function, gas)
+        }
+    }
+    pub fn leave(&self) {
+        let (trace, profile) = self.observers();
+        if let Some(trace) = trace {
+            trace.borrow_mut().leave()
+        }
+        if let Some(profile) = profile {
+            let gas = self.get_gas_left();
+            profile.borrow_mut().leave(gas)
+        }
+    }
+    pub(crate) fn observers(&self) -> (Option<TraceSink>, Option<ProfileSink>) {
+        let inner = self.inner();
+        let inner = inner.borrow();
+        (inner.trace.clone(), inner.profile.clone())
+    }
+    fn reentrant<R>(
+        &self,
+        function: impl FnOnce() -> String,
+        f: impl FnOnce() -> Result<R, RuntimeError>,
+    ) -> VMResult<R> {
+        {
+            let inner = self.inner();
+            let mut inner = inner.borrow_mut();
+            if inner.call_depth >= MAX_CALL_DEPTH {
+                return Err(VmError::StackOverflow);
+            }
+            inner.call_depth += 1;
+        }
+        self.enter(function);
+        let result = f();
+        self.leave();
+        self.inner().borrow_mut().call_depth -= 1;
+        result.map_err(|x| match x.downcast::<VmError>() {
+            Ok(VmError::StackOverflow) => VmError::StackOverflow,
+            Ok(err) => VmError::RuntimeErr(RuntimeError::from(err).to_string()),
+            Err(x) => VmError::RuntimeErr(x.to_string()),
+        })
+    }
     pub fn call(&self, value: i64, idx: i32) -> VMResult<i64> {
-        match self.inner.as_ref().borrow().call {
+        let call = self.inner().borrow().call;
+        match call {
             Some(instance_ptr) => {
                 let func = unsafe { instance_ptr.as_ref() };
-                func.call(value, idx)
-                    .map_err(|x| VmError::RuntimeErr(x.to_string()))
+                self.reentrant(|| format!("closure {}", idx), || func.call(value, idx))
             }
             None => Err(VmError::InstantiationErr(
                 "pusher missing, lifecycle error".to_owned(),
@@ -121,42 +277,116 @@ impl Context {
         }
     }
     pub fn call_unit(&self, value: i64, idx: i32) -> VMResult<()> {
-        match self.inner.as_ref().borrow().call_unit {
+        let call_unit = self.inner().borrow().call_unit;
+        match call_unit {
             Some(instance_ptr) => {
                 let func = unsafe { instance_ptr.as_ref() };
-                func.call(value, idx)
-                    .map_err(|x| VmError::RuntimeErr(x.to_string()))
+                self.reentrant(|| format!("closure {}", idx), || func.call(value, idx))
             }
             None => Err(VmError::InstantiationErr(
                 "pusher missing, lifecycle error".to_owned(),
             )),
         }
     }
+    pub fn next_contract_address(&self) -> ContractAddress {
+        let inner = self.inner();
+        let mut inner = inner.borrow_mut();
+        let address = ContractAddress::originated(&inner.operation_hash, inner.origination_nonce);
+        inner.origination_nonce += 1;
+        address
+    }
+    pub fn get_origination_nonce(&self) -> u32 {
+        self.inner().borrow().origination_nonce
+    }
     pub fn bump(&self, value: Value) -> u64 {
-        let arena = unsafe { &mut ARENA };
-
-        arena.insert(value).data().as_ffi()
+        self.bump_raw(value).data().as_ffi()
     }
     pub fn bump_raw(&self, value: Value) -> DefaultKey {
-        let arena = unsafe { &mut ARENA };
-
-        arena.insert(value)
+        self.inner().borrow_mut().arena.insert(value)
     }
     pub fn get(&self, value: DefaultKey) -> VMResult<Value> {
-        let arena = unsafe { &mut ARENA };
-
-        arena
+        self.inner()
+            .borrow_mut()
+            .arena
             .remove(value)
             .map_or_else(|| Err(VmError::RuntimeErr("Value doesnt exist".into())), Ok)
     }
-    pub fn get_ref(&self, value: DefaultKey) -> VMResult<&Value> {
-        let arena = unsafe { &mut ARENA };
-
-        arena
+    pub fn get_cloned(&self, value: DefaultKey) -> VMResult<Value> {
+        self.inner()
+            .borrow()
+            .arena
             .get(value)
+            .cloned()
             .map_or_else(|| Err(VmError::RuntimeErr("Value doesnt exist".into())), Ok)
     }
+    pub fn predef(&self, name: &str) -> VMResult<Value> {
+        self.inner()
+            .borrow()
+            .predef
+            .get(name)
+            .cloned()
+            .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)
+    }
+    pub fn constant(&self, idx: usize) -> VMResult<Value> {
+        self.inner()
+            .borrow()
+            .constants
+            .get(idx)
+            .cloned()
+            .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)
+    }
+    /// Runs a view of another contract on the gas left to this one, on
+    /// behalf of this contract.
+    pub fn run_view(&self, address: &str, name: &str, argument: Value) -> VMResult<Option<Value>> {
+        let (views, call_depth) = {
+            let inner = self.inner();
+            let inner = inner.borrow();
+            (inner.views.clone(), inner.call_depth)
+        };
+        let views = views
+            .ok_or_else(|| VmError::RuntimeErr("views cannot be called from here".to_owned()))?;
+        if call_depth >= MAX_CALL_DEPTH {
+            return Err(VmError::StackOverflow);
+        }
+        let (source, sender) = match (self.predef("source")?, self.predef("self")?) {
+            (Value::String(source), Value::String(sender)) => (source, sender),
+            _ => return Err(VmError::RuntimeErr("cant happen".to_owned())),
+        };
+        let (trace, profile) = self.observers();
+        let result = views
+            .at_depth(call_depth + 1)
+            .observed(trace, profile)
+            .run(address, name, argument, source, sender, self.get_gas_left())?;
+        Ok(result.map(|(value, remaining_gas)| {
+            self.set_gas_left(remaining_gas);
+            value
+        }))
+    }
     pub fn with_table<A>(&self, f: impl FnOnce(&mut TicketTable) -> VMResult<A>) -> VMResult<A> {
-        f(&mut self.table.as_ref().borrow_mut())
+        f(&mut self.table().borrow_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{rc::Rc, thread};
+
+    use fnv::FnvHashMap;
+
+    use super::{Context, Inner};
+
+    #[test]
+    fn state_stays_with_the_thread_and_the_owner() {
+        let env = Context::new(
+            Rc::default(),
+            Inner::new(vec![], 7, FnvHashMap::default(), vec![]),
+        );
+        let clone = env.clone();
+        assert_eq!(clone.get_origination_nonce(), 7);
+        let elsewhere = thread::spawn(move || clone.shared().is_none());
+        assert!(elsewhere.join().unwrap());
+        let clone = env.clone();
+        drop(env);
+        assert!(clone.shared().is_none());
     }
 }
@@ -1,7 +1,7 @@
 use std::fmt::Debug;
 use thiserror::Error;
 
-use crate::ticket_table;
+use crate::{ticket_table, validate::ModuleIssue};
 
 use super::ffi::FFIError;
 
@@ -40,6 +40,22 @@ pub enum VmError {
     },
     #[error("Error executing: {0}")]
     RuntimeErr(String),
+    #[error("Type error: {0}")]
+    TypeErr(String),
+    #[error("Maximum call depth exceeded")]
+    StackOverflow,
+    #[error("Invalid module: {}", join_issues(.0))]
+    InvalidModule(Vec<ModuleIssue>),
+    #[error("Error persisting state: {0}")]
+    PersistenceErr(String),
+}
+
+fn join_issues(issues: &[ModuleIssue]) -> String {
+    issues
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 impl From<wasmer::ExportError> for VmError {
@@ -50,6 +66,11 @@ impl From<wasmer::ExportError> for VmError {
 
 impl From<wasmer::RuntimeError> for VmError {
     fn from(original: wasmer::RuntimeError) -> Self {
+        let original = match original.downcast::<VmError>() {
+            Ok(VmError::StackOverflow) => return VmError::StackOverflow,
+            Ok(err) => wasmer::RuntimeError::from(err),
+            Err(original) => original,
+        };
         let message = format!("RuntimeError: {}", original.message());
         VmError::RuntimeErr(format!("Wasmer runtime error: {}", &message))
     }
@@ -4,4 +4,5 @@ pub struct ExecutionResult {
     pub new_storage: Box<Value>,
     pub ops: Box<Value>,
     pub remaining_gas: u64,
+    pub origination_nonce: u32,
 }
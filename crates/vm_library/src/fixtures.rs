@@ -0,0 +1,52 @@
+//! Contracts shared by the unit tests.
+
+use crate::{contract_address::ContractAddress, managed::value::Value, state::LigoContractState};
+
+/// A contract module declaring `header`, its imports and any memory or helper
+/// function, with a `main` running `main` on its argument `$v1` and the
+/// callbacks every contract exports. `main` is the first function defined.
+pub(crate) fn contract(header: &str, main: &str) -> String {
+    format!(
+        r#"(module
+  {}
+  (func $main (param $v1 i64) (result i64) {})
+  (func $push (param $value i64))
+  (func $call_callback (param $arg i64) (param $idx i32) (result i64) (local.get $arg))
+  (func $call_callback_unit (param $arg i64) (param $idx i32))
+  (export "push" (func $push))
+  (export "call_callback" (func $call_callback))
+  (export "call_callback_unit" (func $call_callback_unit))
+  (export "main" (func $main)))"#,
+        header, main
+    )
+}
+
+/// A contract whose `main` returns unit.
+pub(crate) fn unit_contract() -> String {
+    contract(
+        r#"(import "env" "unit" (func $unit (result i64)))"#,
+        "(call $unit)",
+    )
+}
+
+/// A contract at `address` holding `storage`, originated by a fixed implicit
+/// account, with no types, views or module and a placeholder code hash.
+pub(crate) fn contract_state(address: &str, storage: Value) -> LigoContractState {
+    LigoContractState {
+        self_: ContractAddress {
+            address: address.to_owned(),
+            entrypoint: None,
+        },
+        originated_by: "tz1gvF4cD2dDtqitL3ZTraggSR1Mju2BKFEM".to_owned(),
+        admin: None,
+        storage: Box::from(storage),
+        code_hash: "00".to_owned(),
+        constants: vec![],
+        entrypoints: None,
+        source: None,
+        parameter_type: None,
+        storage_type: None,
+        views: vec![],
+        module: None,
+    }
+}
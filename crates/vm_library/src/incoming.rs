@@ -2,7 +2,10 @@ use std::{cell::RefCell, rc::Rc};
 
 use wasmer::Module;
 
-use crate::{managed::value::Value, path::Path, ticket_table::TicketTable};
+use crate::{
+    managed::value::Value, path::Path, profile::ProfileSink, ticket_table::TicketTable,
+    trace::TraceSink, view::Views,
+};
 pub struct InvokeManaged<'a> {
     pub table: Rc<RefCell<TicketTable>>,
     pub mod_: &'a Module,
@@ -13,5 +16,27 @@ pub struct InvokeManaged<'a> {
     pub source: String,
     pub sender: String,
     pub self_addr: String,
+    pub operation_hash: String,
+    pub origination_nonce: u32,
+    pub migration: Option<i32>,
+    pub views: Option<Views>,
+    pub trace: Option<TraceSink>,
+    pub profile: Option<ProfileSink>,
+    pub gas_limit: u64,
+}
+pub struct InvokeView<'a> {
+    pub table: Rc<RefCell<TicketTable>>,
+    pub mod_: &'a Module,
+    pub name: &'a str,
+    pub arg: Value,
+    pub storage: Value,
+    pub constants: &'a [(u32, Value)],
+    pub source: String,
+    pub sender: String,
+    pub self_addr: String,
+    pub views: Option<Views>,
+    pub call_depth: u32,
+    pub trace: Option<TraceSink>,
+    pub profile: Option<ProfileSink>,
     pub gas_limit: u64,
 }
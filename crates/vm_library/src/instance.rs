@@ -1,14 +1,15 @@
-use std::{cell::RefCell, ptr::NonNull, rc::Rc};
+use std::ptr::NonNull;
 
-use slotmap::{DefaultKey, Key, KeyData};
-use wasmer::Instance;
+use im_rc::Vector;
+use slotmap::{DefaultKey, KeyData};
+use wasmer::{Instance, Module, NativeFunc};
 
 use crate::{
-    arena::{populate_predef, push_constants, ARENA},
+    arena::{new_constants, new_predef},
     env::{Context, Inner},
     errors::{vm::VmError, VMResult},
     execution_result::ExecutionResult,
-    incoming::InvokeManaged,
+    incoming::{InvokeManaged, InvokeView},
     managed::{
         imports,
         value::{Union, Value},
@@ -16,56 +17,89 @@ use crate::{
     path::Path,
 };
 
-pub fn invoke_managed(t: InvokeManaged) -> VMResult<ExecutionResult> {
-    let arena = unsafe { &mut ARENA };
-    let module = t.mod_;
-    let env = Context {
-        table: t.table,
-        inner: Rc::new(RefCell::new(Inner {
-            instance: None,
-            pusher: None,
-            gas_limit: 10000,
-            call_unit: None,
-            call: None,
-        })),
-    };
-    populate_predef(t.sender, t.self_addr, t.source);
-    push_constants(t.constants);
+/// An instance wired to its environment. The environment points to the
+/// instance and to the exports below, which must live as long as it runs.
+struct Instantiated {
+    instance: Box<Instance>,
+    _pusher: Box<NativeFunc<i64, ()>>,
+    _call_unit: Box<NativeFunc<(i64, i32), ()>>,
+    _call: Box<NativeFunc<(i64, i32), i64>>,
+}
+
+fn instantiate(module: &Module, env: &Context, gas_limit: u64) -> VMResult<Instantiated> {
     let store = module.store();
 
     let instance = Box::from(
-        Instance::new(module, &imports::make_imports(&env, store))
+        Instance::new(module, &imports::make_imports(env, store))
             .map_err(|err| VmError::RuntimeErr(format!("Failed to create instance {}", err)))?,
     );
 
-    {
-        let new = NonNull::from(instance.as_ref());
-        let pusher = Box::from(
-            instance
-                .exports
-                .get_native_function::<i64, ()>("push")
-                .map_err(|_| VmError::RuntimeErr("Miscompiled contract".to_owned()))?,
-        );
-        let call_unit = Box::from(
-            instance
-                .exports
-                .get_native_function::<(i64, i32), ()>("call_callback_unit")
-                .map_err(|_| VmError::RuntimeErr("Miscompiled contract".to_owned()))?,
-        );
-        let call = Box::from(
-            instance
-                .exports
-                .get_native_function::<(i64, i32), i64>("call_callback")
-                .map_err(|_| VmError::RuntimeErr("Miscompiled contract".to_owned()))?,
-        );
-
-        env.set_instance(Some(new));
-        env.set_pusher(Some(NonNull::from(pusher.as_ref())));
-        env.set_call_unit(Some(NonNull::from(call_unit.as_ref())));
-
-        env.set_call(Some(NonNull::from(call.as_ref())));
-
-        env.set_gas_left(t.gas_limit as u64);
+    let new = NonNull::from(instance.as_ref());
+    let pusher = Box::from(
+        instance
+            .exports
+            .get_native_function::<i64, ()>("push")
+            .map_err(|_| VmError::RuntimeErr("Miscompiled contract".to_owned()))?,
+    );
+    let call_unit = Box::from(
+        instance
+            .exports
+            .get_native_function::<(i64, i32), ()>("call_callback_unit")
+            .map_err(|_| VmError::RuntimeErr("Miscompiled contract".to_owned()))?,
+    );
+    let call = Box::from(
+        instance
+            .exports
+            .get_native_function::<(i64, i32), i64>("call_callback")
+            .map_err(|_| VmError::RuntimeErr("Miscompiled contract".to_owned()))?,
+    );
+
+    env.set_instance(Some(new));
+    env.set_pusher(Some(NonNull::from(pusher.as_ref())));
+    env.set_call_unit(Some(NonNull::from(call_unit.as_ref())));
+
+    env.set_call(Some(NonNull::from(call.as_ref())));
+
+    env.set_gas_left(gas_limit);
+    Ok(Instantiated {
+        instance,
+        _pusher: pusher,
+        _call_unit: call_unit,
+        _call: call,
+    })
+}
+
+/// Runs `main`, or a migration, of a contract. With a trace, the host calls
+/// it makes and the error it fails with are recorded there; with a profile,
+/// where its gas went.
+pub fn invoke_managed(t: InvokeManaged) -> VMResult<ExecutionResult> {
+    let trace = t.trace.clone();
+    let result = run_managed(t);
+    if let (Some(trace), Err(err)) = (trace, &result) {
+        trace.borrow_mut().error = Some(err.to_string());
+    }
+    result
+}
+
+fn run_managed(t: InvokeManaged) -> VMResult<ExecutionResult> {
+    let env = Context::new(
+        t.table,
+        Inner {
+            views: t.views,
+            trace: t.trace,
+            profile: t.profile,
+            ..Inner::new(
+                t.operation_hash.into_bytes(),
+                t.origination_nonce,
+                new_predef(t.sender, t.self_addr, t.source),
+                new_constants(t.constants),
+            )
+        },
+    );
+    let instantiated = instantiate(t.mod_, &env, t.gas_limit)?;
+    let instance = &instantiated.instance;
+    if let Some(idx) = t.migration {
+        return invoke_migration(instance, &env, *t.initial_storage, idx);
     }
     let fst = match t.entrypoint_path {
         Some(path) => {
@@ -88,17 +122,19 @@ pub fn invoke_managed(t: InvokeManaged) -> VMResult<ExecutionResult> {
     };
     let snd = t.initial_storage;
     let arg = Value::Pair { fst, snd };
-    let arg = arena.insert(arg).data().as_ffi();
+    let arg = env.bump(arg);
 
     let caller = instance
         .exports
         .get_native_function::<i64, i64>("main")
         .map_err(|_| VmError::RuntimeErr("Miscompiled contract".to_owned()))?;
 
+    env.enter(|| "main".to_owned());
     let result: VMResult<i64> = caller.call(arg as i64).map_err(Into::into);
+    env.leave();
     let result = result?;
     let key = DefaultKey::from(KeyData::from_ffi(result as u64));
-    let value = arena.remove(key);
+    let value = env.get(key).ok();
 
     value.map_or_else(
         || {
@@ -114,6 +150,7 @@ pub fn invoke_managed(t: InvokeManaged) -> VMResult<ExecutionResult> {
                     new_storage: value,
                     ops,
                     remaining_gas: env.get_gas_left(),
+                    origination_nonce: env.get_origination_nonce(),
                 })
             }
             _ => Err(VmError::RuntimeErr(
@@ -122,3 +159,64 @@ pub fn invoke_managed(t: InvokeManaged) -> VMResult<ExecutionResult> {
         },
     )
 }
+
+/// Runs the view exported as `view_<name>` on the pair of its argument and
+/// the storage, returning its result and the gas left. Its host calls and
+/// gas go to the trace and profile given, those of its caller.
+pub fn invoke_view(t: InvokeView) -> VMResult<(Value, u64)> {
+    let env = Context::new(
+        t.table,
+        Inner {
+            views: t.views,
+            call_depth: t.call_depth,
+            trace: t.trace,
+            profile: t.profile,
+            ..Inner::new(
+                vec![],
+                0,
+                new_predef(t.sender, t.self_addr, t.source),
+                new_constants(t.constants),
+            )
+        },
+    );
+    let instantiated = instantiate(t.mod_, &env, t.gas_limit)?;
+    let caller = instantiated
+        .instance
+        .exports
+        .get_native_function::<i64, i64>(&format!("view_{}", t.name))
+        .map_err(|_| VmError::RuntimeErr(format!("Miscompiled view {}", t.name)))?;
+    let arg = env.bump(Value::Pair {
+        fst: Box::from(t.arg),
+        snd: Box::from(t.storage),
+    });
+    env.enter(|| format!("view {}", t.name));
+    let result: VMResult<i64> = caller.call(arg as i64).map_err(Into::into);
+    env.leave();
+    let result = env.get(DefaultKey::from(KeyData::from_ffi(result? as u64)))?;
+    Ok((result, env.get_gas_left()))
+}
+
+fn invoke_migration(
+    instance: &Instance,
+    env: &Context,
+    storage: Value,
+    idx: i32,
+) -> VMResult<ExecutionResult> {
+    let arg = env.bump(storage);
+    let caller = instance
+        .exports
+        .get_native_function::<(i64, i32), i64>("call_callback")
+        .map_err(|_| VmError::RuntimeErr("Miscompiled contract".to_owned()))?;
+
+    env.enter(|| format!("migration {}", idx));
+    let result: VMResult<i64> = caller.call(arg as i64, idx).map_err(Into::into);
+    env.leave();
+    let result = result?;
+    let new_storage = env.get(DefaultKey::from(KeyData::from_ffi(result as u64)))?;
+    Ok(ExecutionResult {
+        new_storage: Box::from(new_storage),
+        ops: Box::from(Value::List(Vector::new(), None)),
+        remaining_gas: env.get_gas_left(),
+        origination_nonce: env.get_origination_nonce(),
+    })
+}
@@ -26,14 +26,24 @@ pub(crate) mod conversions;
 pub mod env;
 pub mod errors;
 pub mod execution_result;
+#[cfg(test)]
+mod fixtures;
 pub mod incoming;
 pub mod instance;
 pub mod managed;
+pub mod module_cache;
 pub mod outgoing;
+pub mod parallel;
 pub mod path;
+pub mod persist;
 pub mod pipe;
+pub mod profile;
 pub mod run_loop;
 pub mod state;
+pub mod state_root;
 pub mod ticket_table;
+pub mod trace;
+pub mod validate;
+pub mod view;
 pub mod vm_client;
 pub mod vm_server;
@@ -1,10 +1,24 @@
+use std::panic::AssertUnwindSafe;
+
 use mimalloc::MiMalloc;
-use vm_library::{pipe::IO, run_loop::run_loop};
+use vm_library::{
+    persist::Persistence,
+    pipe::IO,
+    run_loop::{run_loop, run_loop_persistent},
+};
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 fn main() {
     env_logger::init();
     let mut args: Vec<String> = std::env::args().collect();
-    let io = IO::new(args.remove(1));
-    std::panic::catch_unwind(|| run_loop(io));
+    let pipe = args.remove(1);
+    // An optional second argument names the directory the state is kept in.
+    let persisted = args
+        .get(1)
+        .map(|dir| Persistence::open(dir).expect("failed to restore state"));
+    let io = IO::new(pipe);
+    std::panic::catch_unwind(AssertUnwindSafe(|| match persisted {
+        Some((persistence, state)) => run_loop_persistent(io, state, persistence),
+        None => run_loop(io),
+    }));
 }
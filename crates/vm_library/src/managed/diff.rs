@@ -0,0 +1,415 @@
+use im_rc::{ordmap, ordset};
+use serde::{ser::SerializeTuple, Serialize};
+
+use super::value::{FromOcamlV, Value};
+
+/// A change to one entry of a map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapEdit {
+    Add(FromOcamlV, FromOcamlV),
+    Update(FromOcamlV, StorageDiff),
+    Remove(FromOcamlV),
+}
+
+/// What changed between two versions of a contract storage. Pairs, options
+/// and map values are descended into; lists are described as the single
+/// splice turning the old list into the new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageDiff {
+    Unchanged,
+    Replace(FromOcamlV),
+    Pair {
+        fst: Box<StorageDiff>,
+        snd: Box<StorageDiff>,
+    },
+    Some(Box<StorageDiff>),
+    Map(Vec<MapEdit>),
+    Set {
+        added: Vec<FromOcamlV>,
+        removed: Vec<FromOcamlV>,
+    },
+    List {
+        start: usize,
+        removed: usize,
+        inserted: Vec<FromOcamlV>,
+    },
+}
+
+impl Serialize for MapEdit {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            MapEdit::Add(key, value) => {
+                let mut seq = serializer.serialize_tuple(3)?;
+                seq.serialize_element("Add")?;
+                seq.serialize_element(key)?;
+                seq.serialize_element(value)?;
+                seq.end()
+            }
+            MapEdit::Update(key, diff) => {
+                let mut seq = serializer.serialize_tuple(3)?;
+                seq.serialize_element("Update")?;
+                seq.serialize_element(key)?;
+                seq.serialize_element(diff)?;
+                seq.end()
+            }
+            MapEdit::Remove(key) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Remove")?;
+                seq.serialize_element(key)?;
+                seq.end()
+            }
+        }
+    }
+}
+
+impl Serialize for StorageDiff {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            StorageDiff::Unchanged => {
+                let mut seq = serializer.serialize_tuple(1)?;
+                seq.serialize_element("Unchanged")?;
+                seq.end()
+            }
+            StorageDiff::Replace(value) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Replace")?;
+                seq.serialize_element(value)?;
+                seq.end()
+            }
+            StorageDiff::Pair { fst, snd } => {
+                let mut seq = serializer.serialize_tuple(3)?;
+                seq.serialize_element("Pair")?;
+                seq.serialize_element(fst)?;
+                seq.serialize_element(snd)?;
+                seq.end()
+            }
+            StorageDiff::Some(diff) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Some")?;
+                seq.serialize_element(diff)?;
+                seq.end()
+            }
+            StorageDiff::Map(edits) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Map")?;
+                seq.serialize_element(edits)?;
+                seq.end()
+            }
+            StorageDiff::Set { added, removed } => {
+                let mut seq = serializer.serialize_tuple(3)?;
+                seq.serialize_element("Set")?;
+                seq.serialize_element(added)?;
+                seq.serialize_element(removed)?;
+                seq.end()
+            }
+            StorageDiff::List {
+                start,
+                removed,
+                inserted,
+            } => {
+                let mut seq = serializer.serialize_tuple(4)?;
+                seq.serialize_element("List")?;
+                seq.serialize_element(start)?;
+                seq.serialize_element(removed)?;
+                seq.serialize_element(inserted)?;
+                seq.end()
+            }
+        }
+    }
+}
+
+impl StorageDiff {
+    pub fn new(old: &Value, new: &Value) -> Self {
+        match (old, new) {
+            (
+                Value::Pair { fst, snd },
+                Value::Pair {
+                    fst: fst2,
+                    snd: snd2,
+                },
+            ) => match (Self::new(fst, fst2), Self::new(snd, snd2)) {
+                (Self::Unchanged, Self::Unchanged) => Self::Unchanged,
+                (fst, snd) => Self::Pair {
+                    fst: Box::new(fst),
+                    snd: Box::new(snd),
+                },
+            },
+            (Value::Option(Some(old)), Value::Option(Some(new))) => match Self::new(old, new) {
+                Self::Unchanged => Self::Unchanged,
+                diff => Self::Some(Box::new(diff)),
+            },
+            (Value::Map(old), Value::Map(new)) => {
+                if old.ptr_eq(new) {
+                    return Self::Unchanged;
+                }
+                let edits: Vec<MapEdit> = old
+                    .diff(new)
+                    .map(|item| match item {
+                        ordmap::DiffItem::Add(key, value) => {
+                            MapEdit::Add(FromOcamlV(key.clone()), FromOcamlV(value.clone()))
+                        }
+                        ordmap::DiffItem::Update {
+                            old: (_, old),
+                            new: (key, new),
+                        } => MapEdit::Update(FromOcamlV(key.clone()), Self::new(old, new)),
+                        ordmap::DiffItem::Remove(key, _) => {
+                            MapEdit::Remove(FromOcamlV(key.clone()))
+                        }
+                    })
+                    .filter(|edit| !matches!(edit, MapEdit::Update(_, Self::Unchanged)))
+                    .collect();
+                match edits.is_empty() {
+                    true => Self::Unchanged,
+                    false => Self::Map(edits),
+                }
+            }
+            (Value::Set(old), Value::Set(new)) => {
+                if old.ptr_eq(new) {
+                    return Self::Unchanged;
+                }
+                let mut added = vec![];
+                let mut removed = vec![];
+                old.diff(new).for_each(|item| match item {
+                    ordset::DiffItem::Add(value) => added.push(FromOcamlV(value.clone())),
+                    ordset::DiffItem::Update { old, new } => {
+                        removed.push(FromOcamlV(old.clone()));
+                        added.push(FromOcamlV(new.clone()));
+                    }
+                    ordset::DiffItem::Remove(value) => removed.push(FromOcamlV(value.clone())),
+                });
+                match added.is_empty() && removed.is_empty() {
+                    true => Self::Unchanged,
+                    false => Self::Set { added, removed },
+                }
+            }
+            (Value::List(old, tag), Value::List(new, tag2)) if tag == tag2 => {
+                if old.ptr_eq(new) || old == new {
+                    return Self::Unchanged;
+                }
+                let prefix = old
+                    .iter()
+                    .zip(new.iter())
+                    .take_while(|(old, new)| old == new)
+                    .count();
+                let suffix = old
+                    .iter()
+                    .rev()
+                    .zip(new.iter().rev())
+                    .take(old.len().min(new.len()) - prefix)
+                    .take_while(|(old, new)| old == new)
+                    .count();
+                Self::List {
+                    start: prefix,
+                    removed: old.len() - prefix - suffix,
+                    inserted: new
+                        .iter()
+                        .skip(prefix)
+                        .take(new.len() - prefix - suffix)
+                        .map(|value| FromOcamlV(value.clone()))
+                        .collect(),
+                }
+            }
+            (old, new) if old == new => Self::Unchanged,
+            (_, new) => Self::Replace(FromOcamlV(new.clone())),
+        }
+    }
+
+    /// Applies the diff to the storage it was computed from.
+    pub fn apply(&self, old: &Value) -> Option<Value> {
+        match (self, old) {
+            (Self::Unchanged, old) => Some(old.clone()),
+            (Self::Replace(new), _) => Some(new.0.clone()),
+            (
+                Self::Pair {
+                    fst: dfst,
+                    snd: dsnd,
+                },
+                Value::Pair { fst, snd },
+            ) => Some(Value::Pair {
+                fst: Box::new(dfst.apply(fst)?),
+                snd: Box::new(dsnd.apply(snd)?),
+            }),
+            (Self::Some(diff), Value::Option(Some(old))) => {
+                Some(Value::Option(Some(Box::new(diff.apply(old)?))))
+            }
+            (Self::Map(edits), Value::Map(old)) => {
+                let mut map = old.clone();
+                for edit in edits {
+                    match edit {
+                        MapEdit::Add(key, value) => {
+                            map.insert(key.0.clone(), value.0.clone());
+                        }
+                        MapEdit::Update(key, diff) => {
+                            let value = diff.apply(map.get(&key.0)?)?;
+                            map.insert(key.0.clone(), value);
+                        }
+                        MapEdit::Remove(key) => {
+                            map.remove(&key.0);
+                        }
+                    }
+                }
+                Some(Value::Map(map))
+            }
+            (Self::Set { added, removed }, Value::Set(old)) => {
+                let mut set = old.clone();
+                removed.iter().for_each(|value| {
+                    set.remove(&value.0);
+                });
+                added.iter().for_each(|value| {
+                    set.insert(value.0.clone());
+                });
+                Some(Value::Set(set))
+            }
+            (
+                Self::List {
+                    start,
+                    removed,
+                    inserted,
+                },
+                Value::List(old, tag),
+            ) => {
+                let end = start
+                    .checked_add(*removed)
+                    .filter(|end| *end <= old.len())?;
+                let mut list = old.clone();
+                let tail = list.split_off(end);
+                list.truncate(*start);
+                list.extend(inserted.iter().map(|value| value.0.clone()));
+                list.append(tail);
+                Some(Value::List(list, *tag))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im_rc::{ordmap, ordset, vector};
+
+    use crate::managed::value::{FromOcamlV, Value};
+
+    use super::{MapEdit, StorageDiff};
+
+    fn int(value: i64) -> Value {
+        Value::Int(value.into())
+    }
+
+    fn roundtrip(old: &Value, new: &Value) -> StorageDiff {
+        let diff = StorageDiff::new(old, new);
+        assert_eq!(diff.apply(old).as_ref(), Some(new));
+        diff
+    }
+
+    #[test]
+    fn describes_only_what_changed() {
+        let ledger: im_rc::OrdMap<Value, Value> =
+            (0..100).map(|idx| (int(idx), int(idx))).collect();
+        let old = Value::Pair {
+            fst: Box::new(Value::Map(ledger.clone())),
+            snd: Box::new(int(100)),
+        };
+        let mut updated = ledger.clone();
+        updated.insert(int(3), int(30));
+        updated.insert(int(200), int(1));
+        updated.remove(&int(7));
+        let new = Value::Pair {
+            fst: Box::new(Value::Map(updated)),
+            snd: Box::new(int(100)),
+        };
+        match roundtrip(&old, &new) {
+            StorageDiff::Pair { fst, snd } => {
+                assert_eq!(*snd, StorageDiff::Unchanged);
+                assert_eq!(
+                    *fst,
+                    StorageDiff::Map(vec![
+                        MapEdit::Update(
+                            FromOcamlV(int(3)),
+                            StorageDiff::Replace(FromOcamlV(int(30)))
+                        ),
+                        MapEdit::Remove(FromOcamlV(int(7))),
+                        MapEdit::Add(FromOcamlV(int(200)), FromOcamlV(int(1))),
+                    ])
+                );
+            }
+            diff => panic!("expected a pair diff, got {:?}", diff),
+        }
+        assert_eq!(roundtrip(&old, &old), StorageDiff::Unchanged);
+    }
+
+    #[test]
+    fn diffs_sets_lists_and_options() {
+        let set = Value::Set(ordset![int(1), int(2), int(3)]);
+        let set2 = Value::Set(ordset![int(1), int(3), int(4)]);
+        assert_eq!(
+            roundtrip(&set, &set2),
+            StorageDiff::Set {
+                added: vec![FromOcamlV(int(4))],
+                removed: vec![FromOcamlV(int(2))],
+            }
+        );
+
+        let list = Value::List(vector![int(1), int(2), int(3)], None);
+        let consed = Value::List(vector![int(0), int(1), int(2), int(3)], None);
+        assert_eq!(
+            roundtrip(&list, &consed),
+            StorageDiff::List {
+                start: 0,
+                removed: 0,
+                inserted: vec![FromOcamlV(int(0))],
+            }
+        );
+        let replaced = Value::List(vector![int(1), int(5), int(3)], None);
+        roundtrip(&list, &replaced);
+        roundtrip(&list, &Value::List(vector![], None));
+        roundtrip(
+            &Value::List(vector![int(1), int(1)], None),
+            &Value::List(vector![int(1)], None),
+        );
+
+        let option = Value::Option(Some(Box::new(Value::Map(ordmap! {int(1) => int(1)}))));
+        let option2 = Value::Option(Some(Box::new(Value::Map(ordmap! {int(1) => int(2)}))));
+        assert!(matches!(roundtrip(&option, &option2), StorageDiff::Some(_)));
+        assert!(matches!(
+            roundtrip(&option, &Value::Option(None)),
+            StorageDiff::Replace(_)
+        ));
+    }
+
+    #[test]
+    fn serializes_as_tagged_tuples() {
+        let old = Value::Pair {
+            fst: Box::new(Value::Map(ordmap! {int(1) => int(1), int(2) => int(2)})),
+            snd: Box::new(Value::List(vector![int(1)], None)),
+        };
+        let new = Value::Pair {
+            fst: Box::new(Value::Map(ordmap! {int(1) => int(10), int(3) => int(3)})),
+            snd: Box::new(Value::List(vector![int(0), int(1)], None)),
+        };
+        assert_eq!(
+            serde_json::to_value(roundtrip(&old, &new)).unwrap(),
+            serde_json::json!([
+                "Pair",
+                [
+                    "Map",
+                    [
+                        ["Update", ["Int", "1"], ["Replace", ["Int", "10"]]],
+                        ["Remove", ["Int", "2"]],
+                        ["Add", ["Int", "3"], ["Int", "3"]]
+                    ]
+                ],
+                ["List", 0, 0, [["Int", "0"]]]
+            ])
+        );
+        assert_eq!(
+            serde_json::to_value(StorageDiff::Unchanged).unwrap(),
+            serde_json::json!(["Unchanged"])
+        );
+    }
+}
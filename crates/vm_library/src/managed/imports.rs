@@ -5,11 +5,9 @@ use rug::Integer;
 use slotmap::{DefaultKey, KeyData};
 use wasmer::{Exports, Function, ImportObject, Store};
 
-use super::value::*;
-use crate::{
-    arena::{CONSTANTS, PREDEF},
-    conversions,
-};
+use super::{types::Type, value::*};
+use crate::conversions;
+use crate::trace;
 use crate::{
     env::Context,
     errors::{ffi::FFIError, vm::VmError, VMResult},
@@ -1016,6 +1014,10 @@ pub fn make_imports(env: &Context, store: &Store) -> ImportObject {
         "sha512",
         Function::new_native_with_env(store, env.clone(), call1(sha512)),
     );
+    exports.insert(
+        "view",
+        Function::new_native_with_env(store, env.clone(), call3(view)),
+    );
     exports.insert(
         "keccak",
         Function::new_native_with_env(store, env.clone(), call1(keccak)),
@@ -1180,6 +1182,14 @@ pub fn make_imports(env: &Context, store: &Store) -> ImportObject {
         "transfer_tokens",
         Function::new_native_with_env(store, env.clone(), call3(transfer_tokens)),
     );
+    exports.insert(
+        "create_contract",
+        Function::new_native_with_env(store, env.clone(), call3(create_contract)),
+    );
+    exports.insert(
+        "emit",
+        Function::new_native_with_env(store, env.clone(), call3(emit)),
+    );
     exports.insert(
         "nil",
         Function::new_native_with_env(store, env.clone(), nil),
@@ -1297,17 +1307,18 @@ pub fn make_imports(env: &Context, store: &Store) -> ImportObject {
         "join_tickets",
         Function::new_native_with_env(store, env.clone(), call1(join_tickets)),
     );
+    let exports = if !matches!(env.observers(), (None, None)) {
+        trace::instrument(exports, store, env)
+    } else {
+        exports
+    };
     imports.register("env", exports);
     imports
 }
 fn ticket(env: &Context, payload: Value, amount: Value) -> VMResult<i64> {
     match (payload, amount) {
         (Value::Bytes(x), Value::Int(y)) => {
-            let predef = unsafe { &PREDEF };
-            if let Value::String(nil) = predef
-                .get("self")
-                .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?
-            {
+            if let Value::String(nil) = env.predef("self")? {
                 let handle = env.with_table(|table| {
                     let string = String::from_utf8_lossy(&x);
                     let handle =
@@ -1420,92 +1431,59 @@ fn read_ticket(env: &Context, payload: Value) -> VMResult<()> {
     }
 }
 fn nil(c: &Context) -> VMResult<i64> {
-    let predef = unsafe { &PREDEF };
-    let nil = predef
-        .get("nil")
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.predef("nil")?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 fn true_(c: &Context) -> VMResult<i64> {
-    let predef = unsafe { &PREDEF };
-    let nil = predef
-        .get("true")
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.predef("true")?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 fn false_(c: &Context) -> VMResult<i64> {
-    let predef = unsafe { &PREDEF };
-    let nil = predef
-        .get("false")
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.predef("false")?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 fn unit(c: &Context) -> VMResult<i64> {
-    let predef = unsafe { &PREDEF };
-    let nil = predef
-        .get("unit")
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.predef("unit")?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 fn const_(c: &Context, idx: i32) -> VMResult<i64> {
-    let predef = unsafe { &CONSTANTS };
-    let nil = predef
-        .get(idx as usize)
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.constant(idx as usize)?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 fn empty_map(c: &Context) -> VMResult<i64> {
-    let predef = unsafe { &PREDEF };
-    let nil = predef
-        .get("empty_map")
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.predef("empty_map")?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 fn empty_set(c: &Context) -> VMResult<i64> {
-    let predef = unsafe { &PREDEF };
-    let nil = predef
-        .get("empty_set")
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.predef("empty_set")?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 
 fn zero(c: &Context) -> VMResult<i64> {
-    let predef = unsafe { &PREDEF };
-    let nil = predef
-        .get("zero")
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.predef("zero")?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 fn self_(c: &Context) -> VMResult<i64> {
-    let predef = unsafe { &PREDEF };
-    let nil = predef
-        .get("self")
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.predef("self")?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 fn sender(c: &Context) -> VMResult<i64> {
-    let predef = unsafe { &PREDEF };
-    let nil = predef
-        .get("sender")
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.predef("sender")?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 fn source(c: &Context) -> VMResult<i64> {
-    let predef = unsafe { &PREDEF };
-    let nil = predef
-        .get("source")
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.predef("source")?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 fn cons(c: &Context, v1: Value, v2: Value) -> VMResult<i64> {
@@ -1521,25 +1499,65 @@ fn cons(c: &Context, v1: Value, v2: Value) -> VMResult<i64> {
     }
 }
 fn transfer_tokens(env: &Context, v1: Value, v2: Value, v3: Value) -> VMResult<i64> {
-    if let Value::Int(x) = v2 {
-        if x != Integer::ZERO {
-            return Err(VmError::RuntimeErr("illegal argument".to_owned()));
-        }
-        let fst = Box::from(v1);
-        let snd = Box::from(v3);
-        let pair = Value::Pair { fst, snd };
-        let bumped = env.bump(pair);
-        conversions::to_i64(bumped)
-    } else {
-        Err(VmError::RuntimeErr("illegal argument".to_owned()))
+    match (v2, v3) {
+        (Value::Int(x), Value::String(destination)) if x == Integer::ZERO => {
+            let op = Value::Operation(Operation::Transfer {
+                param: Box::from(v1),
+                destination,
+            });
+            let bumped = env.bump(op);
+            conversions::to_i64(bumped)
+        }
+        _ => Err(VmError::RuntimeErr("illegal argument".to_owned())),
+    }
+}
+fn create_contract(env: &Context, module: Value, storage: Value, amount: Value) -> VMResult<i64> {
+    env.update_gas(300)?;
+    match (module, amount) {
+        (Value::String(code_hash), Value::Int(x)) if x == Integer::ZERO => {
+            let address = env.next_contract_address().address;
+            let op = Value::Operation(Operation::Originate {
+                address: address.clone(),
+                code_hash,
+                storage: Box::from(storage),
+            });
+            let pair = Value::Pair {
+                fst: Box::from(op),
+                snd: Box::from(Value::String(address)),
+            };
+            let bumped = env.bump(pair);
+            conversions::to_i64(bumped)
+        }
+        _ => Err(VmError::RuntimeErr("illegal argument".to_owned())),
+    }
+}
+fn emit(env: &Context, tag: Value, ty: Value, payload: Value) -> VMResult<i64> {
+    env.update_gas(300)?;
+    match (tag, ty) {
+        (Value::String(tag), Value::String(ty)) => {
+            let ty: Type = ty.parse()?;
+            ty.typecheck(&payload)?;
+            let op = Value::Operation(Operation::Emit {
+                tag,
+                ty,
+                payload: Box::from(payload),
+            });
+            let bumped = env.bump(op);
+            conversions::to_i64(bumped)
+        }
+        (tag, ty) => Err(FFIError::ExternError {
+            value: Value::Pair {
+                fst: Box::from(tag),
+                snd: Box::from(ty),
+            },
+            msg: "type mismatch, expected a tag and a type".to_owned(),
+        }
+        .into()),
     }
 }
 fn none(c: &Context) -> VMResult<i64> {
-    let predef = unsafe { &PREDEF };
-    let nil = predef
-        .get("none")
-        .map_or_else(|| Err(VmError::RuntimeErr("cant happen".to_owned())), Ok)?;
-    let bumped = c.bump(nil.clone());
+    let nil = c.predef("none")?;
+    let bumped = c.bump(nil);
     conversions::to_i64(bumped)
 }
 fn map(env: &Context, v: Value, idx: i32) -> VMResult<i64> {
@@ -1702,8 +1720,7 @@ fn iter(env: &Context, v: Value, idx: i32) -> VMResult<()> {
 
 fn dup_host(c: &Context, v: i64) -> VMResult<()> {
     let v = DefaultKey::from(KeyData::from_ffi(v as u64));
-    let v = c.get_ref(v)?;
-    let cloned = v.clone();
+    let cloned = c.get_cloned(v)?;
     let bumped = c.bump(cloned);
     let conved = conversions::to_i64(bumped)?;
     c.push_value(conved)
@@ -1720,6 +1737,28 @@ fn int(_c: &Context, v: i64) -> VMResult<i64> {
     Ok(v)
 }
 
+fn view(env: &Context, name: Value, arg: Value, address: Value) -> VMResult<i64> {
+    env.update_gas(1000)?;
+    match (name, address) {
+        (Value::String(name), Value::String(address)) => {
+            let address = address
+                .split_once('%')
+                .map_or(address.as_str(), |(address, _)| address);
+            let result = env.run_view(address, &name, arg)?;
+            let bumped = env.bump(Value::Option(result.map(Box::from)));
+            conversions::to_i64(bumped)
+        }
+        (name, address) => Err(FFIError::ExternError {
+            value: Value::Pair {
+                fst: Box::from(name),
+                snd: Box::from(address),
+            },
+            msg: "type mismatch, expected a view name and an address".to_owned(),
+        }
+        .into()),
+    }
+}
+
 fn contract(c: &Context, v: i64) -> VMResult<i64> {
     let v = DefaultKey::from(KeyData::from_ffi(v as u64));
     let v = c.get(v)?;
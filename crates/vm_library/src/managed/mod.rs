@@ -1,2 +1,4 @@
+pub mod diff;
 pub mod imports;
+pub mod types;
 pub mod value;
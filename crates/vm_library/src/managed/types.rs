@@ -0,0 +1,338 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::value::{Union, Value};
+use crate::{
+    errors::{vm::VmError, VMResult},
+    path::Path,
+};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
+pub enum Type {
+    Unit,
+    Bool,
+    Int,
+    Nat,
+    Mutez,
+    Timestamp,
+    String,
+    Bytes,
+    Address,
+    Key,
+    KeyHash,
+    Signature,
+    ChainId,
+    Operation,
+    Option(Box<Type>),
+    Or(Box<Type>, Box<Type>),
+    Pair(Box<Type>, Box<Type>),
+    List(Box<Type>),
+    Set(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    BigMap(Box<Type>, Box<Type>),
+    Ticket(Box<Type>),
+    Lambda(Box<Type>, Box<Type>),
+    Contract(Box<Type>),
+}
+
+impl Type {
+    pub fn at_path(&self, path: &[Path]) -> Option<&Type> {
+        path.iter().try_fold(self, |ty, path| match (ty, path) {
+            (Type::Or(l, _), Path::Left) => Some(l.as_ref()),
+            (Type::Or(_, r), Path::Right) => Some(r.as_ref()),
+            _ => None,
+        })
+    }
+    /// The path through pairs to the `big_map_id`-th big_map of this type,
+    /// counting from the left.
+    pub fn big_map_path(&self, big_map_id: usize) -> Option<Vec<Path>> {
+        fn find(ty: &Type, path: &mut Vec<Path>, remaining: &mut usize) -> bool {
+            match ty {
+                Type::BigMap(..) if *remaining == 0 => true,
+                Type::BigMap(..) => {
+                    *remaining -= 1;
+                    false
+                }
+                Type::Pair(l, r) => {
+                    [(Path::Left, l), (Path::Right, r)]
+                        .into_iter()
+                        .any(|(step, ty)| {
+                            path.push(step);
+                            let found = find(ty, path, remaining);
+                            if !found {
+                                path.pop();
+                            }
+                            found
+                        })
+                }
+                _ => false,
+            }
+        }
+        let mut path = vec![];
+        find(self, &mut path, &mut { big_map_id }).then_some(path)
+    }
+    pub fn typecheck(&self, value: &Value) -> VMResult<()> {
+        let ok = match (self, value) {
+            (Type::Unit, Value::Unit) => true,
+            (Type::Bool, Value::Bool(_)) => true,
+            (Type::Int | Type::Timestamp, Value::Int(_)) => true,
+            (Type::Nat | Type::Mutez, Value::Int(x)) => *x >= 0,
+            (Type::String | Type::Address | Type::Contract(_), Value::String(_)) => true,
+            (Type::Key | Type::KeyHash | Type::Signature | Type::ChainId, Value::String(_)) => true,
+            (Type::Bytes | Type::Key | Type::Signature | Type::ChainId, Value::Bytes(_)) => true,
+            (Type::Operation, Value::Operation(_)) => true,
+            (Type::Option(_), Value::Option(None)) => true,
+            (Type::Option(t), Value::Option(Some(v))) => return t.typecheck(v),
+            (Type::Or(l, _), Value::Union(Union::Left(v))) => return l.typecheck(v),
+            (Type::Or(_, r), Value::Union(Union::Right(v))) => return r.typecheck(v),
+            (Type::Pair(l, r), Value::Pair { fst, snd }) => {
+                l.typecheck(fst)?;
+                return r.typecheck(snd);
+            }
+            (Type::List(t), Value::List(l, _)) => return l.iter().try_for_each(|v| t.typecheck(v)),
+            (Type::Set(t), Value::Set(s)) => return s.iter().try_for_each(|v| t.typecheck(v)),
+            (Type::Map(k, v) | Type::BigMap(k, v), Value::Map(m)) => {
+                return m.iter().try_for_each(|(key, value)| {
+                    k.typecheck(key)?;
+                    v.typecheck(value)
+                })
+            }
+            (Type::Ticket(_), Value::Ticket(_) | Value::RuntimeTicket(_)) => true,
+            (Type::Lambda(_, _), Value::Closure { .. }) => true,
+            _ => false,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(VmError::TypeErr(format!(
+                "expected {:?}, got {:?}",
+                self, value
+            )))
+        }
+    }
+}
+
+/// Types are written the way Michelson writes them, `or (pair int nat) unit`,
+/// annotations are accepted and dropped.
+impl FromStr for Type {
+    type Err = VmError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spaced = s.replace('(', " ( ").replace(')', " ) ");
+        let mut tokens = spaced
+            .split_whitespace()
+            .filter(|token| !token.starts_with(['%', ':', '@']))
+            .peekable();
+        let ty = parse(&mut tokens, true)?;
+        match tokens.next() {
+            None => Ok(ty),
+            Some(token) => Err(VmError::TypeErr(format!("unexpected {} in type", token))),
+        }
+    }
+}
+
+fn parse<'a>(
+    tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a str>>,
+    with_arguments: bool,
+) -> VMResult<Type> {
+    let token = tokens
+        .next()
+        .ok_or_else(|| VmError::TypeErr("unexpected end of type".to_owned()))?;
+    if token == "(" {
+        let ty = parse(tokens, true)?;
+        return match tokens.next() {
+            Some(")") => Ok(ty),
+            _ => Err(VmError::TypeErr("unclosed parenthesis in type".to_owned())),
+        };
+    }
+    let argument = |tokens: &mut std::iter::Peekable<_>| {
+        if with_arguments {
+            parse(tokens, false).map(Box::from)
+        } else {
+            Err(VmError::TypeErr(format!(
+                "{} takes arguments, it must be parenthesized",
+                token
+            )))
+        }
+    };
+    let ty = match token {
+        "unit" => Type::Unit,
+        "bool" => Type::Bool,
+        "int" => Type::Int,
+        "nat" => Type::Nat,
+        "mutez" => Type::Mutez,
+        "timestamp" => Type::Timestamp,
+        "string" => Type::String,
+        "bytes" => Type::Bytes,
+        "address" => Type::Address,
+        "key" => Type::Key,
+        "key_hash" => Type::KeyHash,
+        "signature" => Type::Signature,
+        "chain_id" => Type::ChainId,
+        "operation" => Type::Operation,
+        "option" => Type::Option(argument(tokens)?),
+        "list" => Type::List(argument(tokens)?),
+        "set" => Type::Set(argument(tokens)?),
+        "ticket" => Type::Ticket(argument(tokens)?),
+        "contract" => Type::Contract(argument(tokens)?),
+        "or" => Type::Or(argument(tokens)?, argument(tokens)?),
+        "map" => Type::Map(argument(tokens)?, argument(tokens)?),
+        "big_map" => Type::BigMap(argument(tokens)?, argument(tokens)?),
+        "lambda" => Type::Lambda(argument(tokens)?, argument(tokens)?),
+        "pair" => {
+            // `pair a b c` is a right comb, `pair a (pair b c)`.
+            let mut elements = vec![argument(tokens)?, argument(tokens)?];
+            while !matches!(tokens.peek(), None | Some(&")")) {
+                elements.push(argument(tokens)?);
+            }
+            let last = elements.pop().unwrap();
+            *elements
+                .into_iter()
+                .rfold(last, |snd, fst| Box::from(Type::Pair(fst, snd)))
+        }
+        other => return Err(VmError::TypeErr(format!("unsupported type {}", other))),
+    };
+    Ok(ty)
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, arguments): (_, Vec<&Type>) = match self {
+            Type::Unit => ("unit", vec![]),
+            Type::Bool => ("bool", vec![]),
+            Type::Int => ("int", vec![]),
+            Type::Nat => ("nat", vec![]),
+            Type::Mutez => ("mutez", vec![]),
+            Type::Timestamp => ("timestamp", vec![]),
+            Type::String => ("string", vec![]),
+            Type::Bytes => ("bytes", vec![]),
+            Type::Address => ("address", vec![]),
+            Type::Key => ("key", vec![]),
+            Type::KeyHash => ("key_hash", vec![]),
+            Type::Signature => ("signature", vec![]),
+            Type::ChainId => ("chain_id", vec![]),
+            Type::Operation => ("operation", vec![]),
+            Type::Option(t) => ("option", vec![&**t]),
+            Type::List(t) => ("list", vec![&**t]),
+            Type::Set(t) => ("set", vec![&**t]),
+            Type::Ticket(t) => ("ticket", vec![&**t]),
+            Type::Contract(t) => ("contract", vec![&**t]),
+            Type::Or(l, r) => ("or", vec![&**l, &**r]),
+            Type::Pair(l, r) => ("pair", vec![&**l, &**r]),
+            Type::Map(k, v) => ("map", vec![&**k, &**v]),
+            Type::BigMap(k, v) => ("big_map", vec![&**k, &**v]),
+            Type::Lambda(a, r) => ("lambda", vec![&**a, &**r]),
+        };
+        f.write_str(name)?;
+        arguments.iter().try_for_each(|argument| match argument {
+            Type::Option(_)
+            | Type::List(_)
+            | Type::Set(_)
+            | Type::Ticket(_)
+            | Type::Contract(_)
+            | Type::Or(..)
+            | Type::Pair(..)
+            | Type::Map(..)
+            | Type::BigMap(..)
+            | Type::Lambda(..) => write!(f, " ({})", argument),
+            _ => write!(f, " {}", argument),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im_rc::{ordmap, vector};
+
+    use super::Type;
+    use crate::{
+        managed::value::{Union, Value},
+        path::Path,
+    };
+
+    #[test]
+    fn checks_nested_values() {
+        let t = Type::Pair(
+            Box::from(Type::Map(Box::from(Type::Address), Box::from(Type::Nat))),
+            Box::from(Type::Or(
+                Box::from(Type::Unit),
+                Box::from(Type::List(Box::from(Type::Int))),
+            )),
+        );
+        let v = Value::Pair {
+            fst: Box::from(Value::Map(ordmap! {
+                Value::String("tz1gvF4cD2dDtqitL3ZTraggSR1Mju2BKFEM".to_owned()) => Value::Int(10.into())
+            })),
+            snd: Box::from(Value::Union(Union::Right(Box::from(Value::List(
+                vector![Value::Int((-1).into())],
+                None,
+            ))))),
+        };
+        assert!(t.typecheck(&v).is_ok());
+    }
+
+    #[test]
+    fn rejects_mismatches() {
+        assert!(Type::Nat.typecheck(&Value::Int((-1).into())).is_err());
+        assert!(Type::Unit.typecheck(&Value::Bool(true)).is_err());
+        let t = Type::Option(Box::from(Type::String));
+        assert!(t
+            .typecheck(&Value::Option(Some(Box::from(Value::Int(1.into())))))
+            .is_err());
+    }
+
+    #[test]
+    fn follows_entrypoint_paths() {
+        let t = Type::Or(
+            Box::from(Type::Nat),
+            Box::from(Type::Or(Box::from(Type::Unit), Box::from(Type::String))),
+        );
+        assert_eq!(t.at_path(&[Path::Right, Path::Left]), Some(&Type::Unit));
+        assert_eq!(t.at_path(&[Path::Left]), Some(&Type::Nat));
+        assert_eq!(t.at_path(&[Path::Left, Path::Left]), None);
+        assert_eq!(t.at_path(&[]), Some(&t));
+    }
+
+    #[test]
+    fn numbers_big_maps_from_the_left() {
+        let big_map = || Type::BigMap(Box::from(Type::Address), Box::from(Type::Nat));
+        let t = Type::Pair(
+            Box::from(Type::Pair(Box::from(big_map()), Box::from(Type::Nat))),
+            Box::from(Type::Pair(Box::from(Type::Unit), Box::from(big_map()))),
+        );
+        assert_eq!(t.big_map_path(0), Some(vec![Path::Left, Path::Left]));
+        assert_eq!(t.big_map_path(1), Some(vec![Path::Right, Path::Right]));
+        assert_eq!(t.big_map_path(2), None);
+        assert_eq!(big_map().big_map_path(0), Some(vec![]));
+    }
+
+    #[test]
+    fn reads_and_writes_michelson() {
+        let t: Type = "or (pair %left (int :a) (big_map address nat)) (option %right (list unit))"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            t,
+            Type::Or(
+                Box::from(Type::Pair(
+                    Box::from(Type::Int),
+                    Box::from(Type::BigMap(Box::from(Type::Address), Box::from(Type::Nat))),
+                )),
+                Box::from(Type::Option(Box::from(Type::List(Box::from(Type::Unit))))),
+            )
+        );
+        assert_eq!(
+            t.to_string(),
+            "or (pair int (big_map address nat)) (option (list unit))"
+        );
+        assert_eq!(t.to_string().parse::<Type>().unwrap(), t);
+        assert_eq!(
+            "pair int nat unit".parse::<Type>().unwrap().to_string(),
+            "pair int (pair nat unit)"
+        );
+        assert!("pair int".parse::<Type>().is_err());
+        assert!("option option int".parse::<Type>().is_err());
+        assert!("never".parse::<Type>().is_err());
+        assert!("(int".parse::<Type>().is_err());
+    }
+}
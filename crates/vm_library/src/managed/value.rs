@@ -1,5 +1,7 @@
 use crate::{
-    errors::VMResult,
+    errors::{vm::VmError, VMResult},
+    managed::types::Type,
+    path::Path,
     ticket_table::{Ticket, TicketId, TicketTable},
 };
 use im_rc::{OrdMap, OrdSet, Vector};
@@ -137,55 +139,92 @@ pub enum Value {
         opt_arg: Option<Box<Value>>,
         call: i32,
     },
+    Operation(Operation),
+}
+
+#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize, Clone)]
+pub enum Operation {
+    Transfer {
+        param: Box<Value>,
+        destination: String,
+    },
+    /// Originates a contract running the cached module with hash
+    /// `code_hash`.
+    Originate {
+        address: String,
+        code_hash: String,
+        storage: Box<Value>,
+    },
+    Emit {
+        tag: String,
+        ty: Type,
+        payload: Box<Value>,
+    },
 }
 impl Value {
-    pub fn to_runtime_ticket(self, t: &mut TicketTable) -> Self {
+    /// The value reached by following `path` through pairs and unions.
+    pub fn at_path(&self, path: &[Path]) -> Option<&Value> {
+        path.iter()
+            .try_fold(self, |value, step| match (value, step) {
+                (Value::Pair { fst, .. }, Path::Left) => Some(fst.as_ref()),
+                (Value::Pair { snd, .. }, Path::Right) => Some(snd.as_ref()),
+                (Value::Union(Union::Left(value)), Path::Left) => Some(value.as_ref()),
+                (Value::Union(Union::Right(value)), Path::Right) => Some(value.as_ref()),
+                _ => None,
+            })
+    }
+    /// Moves the tickets of the value, held by `owner`, into the table.
+    pub fn to_runtime_ticket(self, t: &mut TicketTable, owner: &str) -> Self {
         match self {
             Value::Bytes(_) => self,
             Value::String(_) => self,
             Value::Int(_) => self,
             Value::Union(l) => Value::Union(match l {
-                Union::Left(l) => Union::Left(Box::from(l.to_runtime_ticket(t))),
-                Union::Right(r) => Union::Right(Box::from(r.to_runtime_ticket(t))),
+                Union::Left(l) => Union::Left(Box::from(l.to_runtime_ticket(t, owner))),
+                Union::Right(r) => Union::Right(Box::from(r.to_runtime_ticket(t, owner))),
             }),
             Value::Pair { fst, snd } => Self::Pair {
-                fst: Box::from(fst.to_runtime_ticket(t)),
-                snd: Box::from(snd.to_runtime_ticket(t)),
+                fst: Box::from(fst.to_runtime_ticket(t, owner)),
+                snd: Box::from(snd.to_runtime_ticket(t, owner)),
             },
             Value::Bool(_) => self,
             Value::Map(x) => {
                 let res = x
                     .into_iter()
                     .map(|(k, v)| {
-                        let fst = k.to_runtime_ticket(t);
-                        let snd = v.to_runtime_ticket(t);
+                        let fst = k.to_runtime_ticket(t, owner);
+                        let snd = v.to_runtime_ticket(t, owner);
                         (fst, snd)
                     })
                     .collect::<OrdMap<Value, Value>>();
                 Self::Map(res)
             }
-            Value::Set(x) => Value::Set(x.into_iter().map(|x| x.to_runtime_ticket(t)).collect()),
-            Value::List(x, tag) => {
-                Value::List(x.into_iter().map(|x| x.to_runtime_ticket(t)).collect(), tag)
-            }
+            Value::Set(x) => Value::Set(
+                x.into_iter()
+                    .map(|x| x.to_runtime_ticket(t, owner))
+                    .collect(),
+            ),
+            Value::List(x, tag) => Value::List(
+                x.into_iter()
+                    .map(|x| x.to_runtime_ticket(t, owner))
+                    .collect(),
+                tag,
+            ),
             Value::Unit => self,
-            Value::Option(x) => Value::Option(x.map(|x| Box::from(x.to_runtime_ticket(t)))),
-            Value::Ticket(ticket) => {
-                let new = Self::RuntimeTicket(t.counter);
-                t.merge(ticket);
-                t.incr();
-                new
-            }
+            Value::Option(x) => Value::Option(x.map(|x| Box::from(x.to_runtime_ticket(t, owner)))),
+            Value::Ticket(ticket) => Self::RuntimeTicket(t.hold(ticket, owner)),
             Value::RuntimeTicket(_) => panic!("lifetime error"),
             Value::Closure {
                 opt_arg: _,
                 call: _,
             } => panic!("lifetime error"),
+            Value::Operation(_) => panic!("lifetime error"),
         }
     }
     pub fn from_runtime_ticket(
         self,
         t: &mut TicketTable,
+        destination: &str,
         to_return: &mut Vec<(TicketId, usize)>,
     ) -> VMResult<Self> {
         match self {
@@ -193,20 +232,28 @@ impl Value {
             Value::String(_) => Ok(self),
             Value::Int(_) => Ok(self),
             Value::Union(l) => Ok(Value::Union(match l {
-                Union::Left(l) => Union::Left(Box::from(l.from_runtime_ticket(t, to_return)?)),
-                Union::Right(r) => Union::Right(Box::from(r.from_runtime_ticket(t, to_return)?)),
+                Union::Left(l) => Union::Left(Box::from(l.from_runtime_ticket(
+                    t,
+                    destination,
+                    to_return,
+                )?)),
+                Union::Right(r) => Union::Right(Box::from(r.from_runtime_ticket(
+                    t,
+                    destination,
+                    to_return,
+                )?)),
             })),
             Value::Pair { fst, snd } => Ok(Self::Pair {
-                fst: Box::from(fst.from_runtime_ticket(t, to_return)?),
-                snd: Box::from(snd.from_runtime_ticket(t, to_return)?),
+                fst: Box::from(fst.from_runtime_ticket(t, destination, to_return)?),
+                snd: Box::from(snd.from_runtime_ticket(t, destination, to_return)?),
             }),
             Value::Bool(_) => Ok(self),
             Value::Map(x) => {
                 let res = x
                     .into_iter()
                     .map(|(k, v)| {
-                        let fst = k.from_runtime_ticket(t, to_return)?;
-                        let snd = v.from_runtime_ticket(t, to_return)?;
+                        let fst = k.from_runtime_ticket(t, destination, to_return)?;
+                        let snd = v.from_runtime_ticket(t, destination, to_return)?;
                         Ok((fst, snd))
                     })
                     .collect::<VMResult<Vec<(Value, Value)>>>();
@@ -216,7 +263,7 @@ impl Value {
             Value::Set(x) => {
                 let res = x
                     .into_iter()
-                    .map(|x| x.from_runtime_ticket(t, to_return))
+                    .map(|x| x.from_runtime_ticket(t, destination, to_return))
                     .collect::<VMResult<OrdSet<Value>>>();
                 let res = res?;
                 Ok(Self::Set(res))
@@ -224,7 +271,7 @@ impl Value {
             Value::List(x, tag) => {
                 let res = x
                     .into_iter()
-                    .map(|x| x.from_runtime_ticket(t, to_return))
+                    .map(|x| x.from_runtime_ticket(t, destination, to_return))
                     .collect::<VMResult<Vector<Value>>>();
                 let res = res?;
                 Ok(Self::List(res, tag))
@@ -234,7 +281,7 @@ impl Value {
                 let res = match x {
                     None => None,
                     Some(x) => {
-                        let res = x.from_runtime_ticket(t, to_return)?;
+                        let res = x.from_runtime_ticket(t, destination, to_return)?;
                         Some(Box::from(res))
                     }
                 };
@@ -242,7 +289,7 @@ impl Value {
             }
             Value::Ticket(_) => panic!("lifetime error"),
             Value::RuntimeTicket(ticket) => {
-                let ticket_id = t.extract(&ticket)?;
+                let ticket_id = t.transfer(&ticket, destination)?;
                 to_return.push((ticket_id.ticket_id.clone(), ticket_id.amount));
                 Ok(Self::Ticket(ticket_id))
             }
@@ -250,6 +297,9 @@ impl Value {
                 opt_arg: _,
                 call: _,
             } => panic!("lifetime error"),
+            Value::Operation(_) => Err(VmError::RuntimeErr(
+                "operations cannot be stored".to_owned(),
+            )),
         }
     }
 }
@@ -483,6 +533,7 @@ impl Serialize for FromOcamlV {
                 opt_arg: _,
                 call: _,
             } => Err(serde::ser::Error::custom("Cant serialize a closure")),
+            Value::Operation(_) => Err(serde::ser::Error::custom("Cant serialize an operation")),
             Int(x) => {
                 let mut seq = serializer.serialize_tuple(2)?;
                 seq.serialize_element("Int")?;
@@ -0,0 +1,212 @@
+use fnv::FnvHashMap;
+use sha2::{Digest, Sha256};
+use wasmer::Module;
+
+use crate::{
+    compile, compile_store,
+    compile_store::FeatureSet,
+    errors::{vm::VmError, VMResult},
+};
+
+const DEFAULT_CAPACITY: usize = 256 * 1024 * 1024;
+
+pub fn code_hash(code: &[u8]) -> String {
+    Sha256::digest(code)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The serialized form of a compiled module, and the features it was
+/// compiled with unless it was handed to the VM already compiled.
+#[derive(Debug)]
+struct Code {
+    serialized: Vec<u8>,
+    features: Option<FeatureSet>,
+}
+
+#[derive(Debug)]
+struct LoadedModule {
+    module: Module,
+    last_used: u64,
+}
+
+/// Compiled modules keyed by the hash of their wasm binary. Contracts only
+/// refer to their code by hash, so the serialized form of every module is
+/// kept; the loaded modules are evicted least recently used first once their
+/// serialized size exceeds `capacity`, and loaded again when needed.
+#[derive(Debug)]
+pub struct ModuleCache {
+    code: FnvHashMap<String, Code>,
+    loaded: FnvHashMap<String, LoadedModule>,
+    tick: u64,
+    size: usize,
+    capacity: usize,
+    features: FeatureSet,
+}
+impl Default for ModuleCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+impl PartialEq for ModuleCache {
+    fn eq(&self, other: &Self) -> bool {
+        self.code.len() == other.code.len() && self.code.keys().all(|k| other.code.contains_key(k))
+    }
+}
+impl Eq for ModuleCache {}
+
+impl ModuleCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            code: FnvHashMap::default(),
+            loaded: FnvHashMap::default(),
+            tick: 0,
+            size: 0,
+            capacity,
+            features: FeatureSet::default(),
+        }
+    }
+    pub const fn features(&self) -> &FeatureSet {
+        &self.features
+    }
+    /// Switches the features new modules are compiled with. Modules compiled
+    /// under other features keep running the contracts that use them, but
+    /// are compiled again when originated anew.
+    pub fn set_features(&mut self, features: FeatureSet) {
+        self.features = features;
+    }
+    pub fn contains(&self, hash: &str) -> bool {
+        self.code.contains_key(hash)
+    }
+    /// The serialized module with hash `hash`, as sent to the node.
+    pub fn serialized(&self, hash: &str) -> Option<&[u8]> {
+        self.code.get(hash).map(|code| &code.serialized[..])
+    }
+    /// Adds a module compiled elsewhere, e.g. restored from the node.
+    pub fn insert_serialized(&mut self, hash: String, serialized: Vec<u8>) {
+        if self.loaded.remove(&hash).is_some() {
+            self.size -= self.code[&hash].serialized.len();
+        }
+        self.code.insert(
+            hash,
+            Code {
+                serialized,
+                features: None,
+            },
+        );
+    }
+    /// The module with hash `hash`, loaded from its serialized form if it was
+    /// evicted.
+    pub fn get(&mut self, hash: &str) -> VMResult<Option<Module>> {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.loaded.get_mut(hash) {
+            entry.last_used = tick;
+            return Ok(Some(entry.module.clone()));
+        }
+        let serialized = match self.code.get(hash) {
+            Some(code) => &code.serialized,
+            None => return Ok(None),
+        };
+        let module = unsafe {
+            Module::deserialize(&compile_store::headless_for(&self.features), serialized)
+        }?;
+        self.load(hash.to_owned(), module.clone());
+        Ok(Some(module))
+    }
+    /// Returns the code hash and the compiled module, compiling `code` only
+    /// if no module with the same hash was compiled with the current
+    /// features. The flag tells whether the module was compiled, and so is
+    /// not known to the node yet.
+    pub fn get_or_compile(&mut self, code: &[u8]) -> VMResult<(String, Module, bool)> {
+        let wasm = compile::decode_module(code)?;
+        let hash = code_hash(&wasm);
+        let features = self.features;
+        if self
+            .code
+            .get(&hash)
+            .is_some_and(|code| code.features == Some(features))
+        {
+            if let Some(module) = self.get(&hash)? {
+                return Ok((hash, module, false));
+            }
+        }
+        let module = compile::compile_managed_module(&wasm, &features)?;
+        let serialized = module
+            .serialize()
+            .map_err(|x| VmError::CompileErr(x.to_string()))?;
+        self.insert_serialized(hash.clone(), serialized);
+        if let Some(code) = self.code.get_mut(&hash) {
+            code.features = Some(features);
+        }
+        self.load(hash.clone(), module.clone());
+        Ok((hash, module, true))
+    }
+    fn load(&mut self, hash: String, module: Module) {
+        self.tick += 1;
+        self.size += self.code[&hash].serialized.len();
+        let last_used = self.tick;
+        if self
+            .loaded
+            .insert(hash.clone(), LoadedModule { module, last_used })
+            .is_some()
+        {
+            self.size -= self.code[&hash].serialized.len();
+        }
+        self.evict();
+    }
+    fn evict(&mut self) {
+        while self.size > self.capacity && self.loaded.len() > 1 {
+            let oldest = self
+                .loaded
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(hash, _)| hash.clone());
+            match oldest.and_then(|hash| self.loaded.remove_entry(&hash)) {
+                Some((hash, _)) => self.size -= self.code[&hash].serialized.len(),
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::wat2wasm;
+
+    use super::{code_hash, ModuleCache};
+    use crate::fixtures::unit_contract;
+
+    #[test]
+    fn identical_code_is_compiled_once() {
+        let mut cache = ModuleCache::default();
+        let module = unit_contract();
+        let wasm = wat2wasm(module.as_bytes()).unwrap();
+        let (hash, _, compiled) = cache.get_or_compile(module.as_bytes()).unwrap();
+        assert_eq!(hash, code_hash(&wasm));
+        assert!(compiled);
+        let hex: String = wasm.iter().map(|b| format!("{:02x}", b)).collect();
+        let (hash2, _, compiled2) = cache.get_or_compile(hex.as_bytes()).unwrap();
+        assert_eq!(hash, hash2);
+        assert!(!compiled2);
+        assert_eq!(cache.code.len(), 1);
+    }
+
+    #[test]
+    fn evicted_modules_are_loaded_again() {
+        let mut cache = ModuleCache::default();
+        let (first, _, _) = cache.get_or_compile(unit_contract().as_bytes()).unwrap();
+        let serialized = cache.serialized(&first).unwrap().to_vec();
+        cache.capacity = serialized.len() * 2;
+        cache.insert_serialized("second".to_owned(), serialized.clone());
+        cache.insert_serialized("third".to_owned(), serialized);
+        assert!(cache.get("second").unwrap().is_some());
+        assert!(cache.get(&first).unwrap().is_some());
+        assert!(cache.get("third").unwrap().is_some());
+        assert!(!cache.loaded.contains_key("second"));
+        assert!(cache.loaded.contains_key(&first));
+        assert!(cache.get("second").unwrap().is_some());
+        assert!(cache.get("missing").unwrap().is_none());
+    }
+}
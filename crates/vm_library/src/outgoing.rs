@@ -18,6 +18,22 @@ pub struct SetBorrowed<'a> {
     #[serde(serialize_with = "json_ser")]
     pub value: &'a Cow<'a, ContractType>,
 }
+/// A compiled module, which contracts refer to by its hash. The VM sends it
+/// to the node once, and the node sends it back before the contracts using
+/// it when restoring the state.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ModuleOwned {
+    pub hash: String,
+    #[serde(with = "serde_bytes")]
+    pub module: Vec<u8>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModuleBorrowed<'a> {
+    pub hash: &'a str,
+    #[serde(with = "serde_bytes")]
+    pub module: &'a [u8],
+}
 fn json_ser<S>(t: &ContractType, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
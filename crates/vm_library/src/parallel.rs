@@ -0,0 +1,307 @@
+use std::{cell::RefCell, rc::Rc, thread};
+
+use fnv::{FnvHashMap, FnvHashSet};
+use wasmer::Module;
+
+use crate::{
+    contract_address::ContractAddress,
+    errors::{vm::VmError, VMResult},
+    execution_result::ExecutionResult,
+    incoming::InvokeManaged,
+    instance::invoke_managed,
+    managed::value::Value,
+    run_loop::resolve_entrypoint,
+    state::{ContractType, State},
+    ticket_table::TicketTable,
+    vm_client::{Operation, Transaction},
+};
+
+/// Contract addresses and ticket owners a transaction read or wrote.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AccessSet {
+    pub reads: FnvHashSet<String>,
+    pub writes: FnvHashSet<String>,
+}
+impl AccessSet {
+    pub fn read(&mut self, key: &str) {
+        self.reads.insert(key.to_owned());
+    }
+    pub fn write(&mut self, key: &str) {
+        self.writes.insert(key.to_owned());
+    }
+    fn conflicts_with(&self, written: &FnvHashSet<String>) -> bool {
+        self.reads
+            .iter()
+            .chain(self.writes.iter())
+            .any(|key| written.contains(key))
+    }
+}
+
+/// The result of running a transaction against the state as it was before
+/// the block, together with everything that result depends on.
+pub struct Speculation<R> {
+    pub access: AccessSet,
+    pub result: R,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStats {
+    pub speculated: usize,
+    pub reexecuted: usize,
+}
+
+/// Runs `speculate` for every transaction on up to `workers` threads, then
+/// calls `commit` for each transaction in block order. `commit` receives the
+/// speculative result only if nothing it read or wrote was written by an
+/// earlier transaction of the block; otherwise it must execute the
+/// transaction itself. Either way it returns what the transaction accessed,
+/// so that the outcome is the same as executing the block sequentially.
+pub fn execute_block<T, R, S, C>(
+    transactions: &[T],
+    workers: usize,
+    speculate: S,
+    mut commit: C,
+) -> BlockStats
+where
+    T: Sync,
+    R: Send,
+    S: Fn(&T) -> Option<Speculation<R>> + Sync,
+    C: FnMut(&T, Option<R>) -> AccessSet,
+{
+    let workers = workers.clamp(1, transactions.len().max(1));
+    let mut speculations: Vec<Option<Speculation<R>>> = transactions.iter().map(|_| None).collect();
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|worker| {
+                let speculate = &speculate;
+                scope.spawn(move || {
+                    (worker..transactions.len())
+                        .step_by(workers)
+                        .map(|index| (index, speculate(&transactions[index])))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|handle| {
+            let results = handle.join().expect("speculative worker panicked");
+            results
+                .into_iter()
+                .for_each(|(index, speculation)| speculations[index] = speculation);
+        });
+    });
+
+    let mut stats = BlockStats::default();
+    let mut written = FnvHashSet::default();
+    transactions
+        .iter()
+        .zip(speculations)
+        .for_each(|(transaction, speculation)| {
+            let access = match speculation {
+                Some(Speculation { access, result }) if !access.conflicts_with(&written) => {
+                    stats.speculated += 1;
+                    commit(transaction, Some(result));
+                    access
+                }
+                _ => {
+                    stats.reexecuted += 1;
+                    commit(transaction, None)
+                }
+            };
+            written.extend(access.writes);
+        });
+    stats
+}
+
+/// Contracts as they were before a block, in a form worker threads can read
+/// without sharing any reference counted value with the main thread.
+#[derive(Default)]
+pub struct Snapshot {
+    contracts: FnvHashMap<String, (Vec<u8>, Module)>,
+}
+impl Snapshot {
+    pub fn capture<'a>(
+        state: &mut State,
+        addresses: impl IntoIterator<Item = &'a str>,
+    ) -> VMResult<Self> {
+        let mut contracts = FnvHashMap::default();
+        for address in addresses {
+            if let Some(contract) = state.table.get_mut(address) {
+                contract.init(&mut state.modules)?;
+                let bytes = bincode::serialize(contract)
+                    .map_err(|err| VmError::RuntimeErr(err.to_string()))?;
+                let module = contract.module().as_ref().map(|module| (**module).clone());
+                if let Some(module) = module {
+                    contracts.insert(address.to_owned(), (bytes, module));
+                }
+            }
+        }
+        Ok(Self { contracts })
+    }
+}
+
+/// An invocation executed against a snapshot, ready to be committed. The
+/// new storage is kept serialized, values being confined to one thread.
+pub struct SpeculativeInvoke {
+    pub address: ContractAddress,
+    pub storage: Vec<u8>,
+    pub remaining_gas: u64,
+}
+impl SpeculativeInvoke {
+    pub fn storage(&self) -> VMResult<Value> {
+        bincode::deserialize(&self.storage).map_err(|err| VmError::RuntimeErr(err.to_string()))
+    }
+}
+
+/// Executes `transaction` against `snapshot` if it is an invocation that
+/// cannot observe anything but its own contract. Transactions carrying or
+/// handling tickets, emitting operations, and failing ones are left to
+/// sequential execution so that their effects and errors are reported in
+/// order.
+pub fn speculate_invoke(
+    snapshot: &Snapshot,
+    transaction: &Transaction,
+) -> Option<Speculation<SpeculativeInvoke>> {
+    if !transaction.tickets.is_empty() || transaction.trace || transaction.profile {
+        return None;
+    }
+    let (address, argument, gas_limit) = match serde_json::from_str(&transaction.operation) {
+        Ok(Operation::Invoke {
+            address,
+            argument,
+            gas_limit,
+        }) => (address, argument.0, gas_limit),
+        _ => return None,
+    };
+    let (bytes, module) = snapshot.contracts.get(&address.address)?;
+    let contract: ContractType = bincode::deserialize(bytes).ok()?;
+    let entrypoint_path = resolve_entrypoint(&contract, &address, &argument).ok()?;
+    let sender = transaction
+        .sender
+        .clone()
+        .unwrap_or_else(|| transaction.source.clone());
+    let table = Rc::new(RefCell::new(TicketTable::default()));
+    let arg = argument.to_runtime_ticket(&mut table.as_ref().borrow_mut(), &sender);
+    let storage = contract
+        .storage()
+        .clone()
+        .to_runtime_ticket(&mut table.as_ref().borrow_mut(), &address.address);
+    let ExecutionResult {
+        new_storage,
+        ops,
+        remaining_gas,
+        ..
+    } = invoke_managed(InvokeManaged {
+        table: Rc::clone(&table),
+        mod_: module,
+        arg,
+        entrypoint_path: &entrypoint_path,
+        initial_storage: Box::from(storage),
+        constants: contract.constants(),
+        source: transaction.source.clone(),
+        sender,
+        self_addr: address.address.clone(),
+        operation_hash: transaction.operation_raw_hash.clone(),
+        origination_nonce: 0,
+        migration: None,
+        views: None,
+        trace: None,
+        profile: None,
+        gas_limit,
+    })
+    .ok()?;
+    {
+        let table = table.as_ref().borrow();
+        if table.counter != 0 || !table.events.is_empty() {
+            return None;
+        }
+    }
+    match *ops {
+        Value::List(ops, _) if ops.is_empty() => (),
+        _ => return None,
+    }
+    if let Some(ty) = contract.storage_type() {
+        ty.typecheck(&new_storage).ok()?;
+    }
+    let storage = bincode::serialize(&new_storage).ok()?;
+    let mut access = AccessSet::default();
+    access.read(&address.address);
+    access.write(&address.address);
+    Some(Speculation {
+        access,
+        result: SpeculativeInvoke {
+            address,
+            storage,
+            remaining_gas,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use fnv::FnvHashMap;
+
+    use super::{execute_block, AccessSet, Speculation};
+
+    // Each transaction adds to one counter, reading a second one if present.
+    type Tx = (&'static str, Option<&'static str>, i64);
+
+    fn apply(state: &mut FnvHashMap<&'static str, i64>, (key, read, amount): &Tx) -> i64 {
+        let base = read.map_or(0, |read| state.get(read).copied().unwrap_or_default());
+        let value = state.get(key).copied().unwrap_or_default() + base + amount;
+        state.insert(key, value);
+        value
+    }
+
+    fn access((key, read, _): &Tx) -> AccessSet {
+        let mut access = AccessSet::default();
+        access.write(key);
+        access.read(key);
+        if let Some(read) = read {
+            access.read(read);
+        }
+        access
+    }
+
+    #[test]
+    fn matches_sequential_execution() {
+        let block: Vec<Tx> = vec![
+            ("a", None, 1),
+            ("b", None, 2),
+            ("a", None, 3),
+            ("c", Some("b"), 4),
+            ("d", None, 5),
+            ("b", Some("d"), 6),
+        ];
+        let mut sequential = FnvHashMap::default();
+        let expected: Vec<i64> = block.iter().map(|tx| apply(&mut sequential, tx)).collect();
+
+        let snapshot = FnvHashMap::default();
+        let mut state = FnvHashMap::default();
+        let mut results = vec![];
+        let stats = execute_block(
+            &block,
+            4,
+            |tx| {
+                let mut copy = snapshot.clone();
+                Some(Speculation {
+                    access: access(tx),
+                    result: (tx.0, apply(&mut copy, tx)),
+                })
+            },
+            |tx, speculated| {
+                match speculated {
+                    Some((key, value)) => {
+                        state.insert(key, value);
+                        results.push(value);
+                    }
+                    None => results.push(apply(&mut state, tx)),
+                }
+                access(tx)
+            },
+        );
+        assert_eq!(results, expected);
+        assert_eq!(state, sequential);
+        assert_eq!(stats.speculated, 3);
+        assert_eq!(stats.reexecuted, 3);
+    }
+}
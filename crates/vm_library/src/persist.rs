@@ -0,0 +1,396 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use fnv::FnvHashSet;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{vm::VmError, VMResult},
+    state::{ContractType, Holdings, State},
+};
+
+const SNAPSHOT: &str = "state.snapshot";
+const WAL: &str = "state.wal";
+pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1000;
+
+fn persistence_err(err: impl ToString) -> VmError {
+    VmError::PersistenceErr(err.to_string())
+}
+
+/// The contracts and tickets one applied transaction (or block) left behind,
+/// `None` standing for a contract that no longer exists or an address that
+/// holds no tickets, and the modules they use that were not persisted before.
+#[derive(Serialize, Deserialize)]
+struct WalRecord {
+    label: String,
+    contracts: Vec<(String, Option<ContractType>)>,
+    tickets: Vec<(String, Option<Holdings>)>,
+    modules: Vec<(String, Vec<u8>)>,
+}
+
+/// Keeps a copy of `State` in a directory: a snapshot of every contract, of
+/// the tickets held and of the serialized modules the contracts use, so that restoring does not recompile,
+/// and a write-ahead log of the changes applied since. Each module is stored
+/// once, however many contracts use it. The log is folded into a new
+/// snapshot every `snapshot_interval` records.
+pub struct Persistence {
+    dir: PathBuf,
+    wal: File,
+    records: u64,
+    snapshot_interval: u64,
+    /// Hashes of the modules in the snapshot or the log.
+    modules: FnvHashSet<String>,
+}
+impl Persistence {
+    /// Opens `dir`, creating it if needed, and returns the state it holds.
+    /// A record cut short by a crash at the end of the log is ignored, one
+    /// that is complete but cannot be decoded is an error.
+    pub fn open(dir: impl AsRef<Path>) -> VMResult<(Self, State)> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(persistence_err)?;
+        let mut state = State::default();
+        let mut modules = FnvHashSet::default();
+        let mut restore_modules = |state: &mut State, restored: Vec<(String, Vec<u8>)>| {
+            restored.into_iter().for_each(|(hash, module)| {
+                modules.insert(hash.clone());
+                state.modules.insert_serialized(hash, module);
+            })
+        };
+        let snapshot = dir.join(SNAPSHOT);
+        if snapshot.exists() {
+            let bytes = fs::read(&snapshot).map_err(persistence_err)?;
+            let (contracts, tickets, snapshot_modules): SnapshotFile =
+                bincode::deserialize(&bytes).map_err(persistence_err)?;
+            restore_modules(&mut state, snapshot_modules);
+            state.table.extend(contracts);
+            state.tickets.extend(tickets);
+        }
+        let wal_path = dir.join(WAL);
+        let mut records = 0;
+        if wal_path.exists() {
+            let bytes = fs::read(&wal_path).map_err(persistence_err)?;
+            let mut rest = &bytes[..];
+            while let Some((record, remaining)) = next_record(rest)? {
+                restore_modules(&mut state, record.modules);
+                record.contracts.into_iter().for_each(|(key, contract)| {
+                    match contract {
+                        Some(contract) => state.table.insert(key, contract),
+                        None => state.table.remove(&key),
+                    };
+                });
+                record.tickets.into_iter().for_each(|(key, tickets)| {
+                    state.set_tickets(key, tickets.unwrap_or_default());
+                });
+                records += 1;
+                rest = remaining;
+            }
+            if !rest.is_empty() {
+                log::warn!("Ignoring {} bytes at the end of the state log", rest.len());
+                let valid = (bytes.len() - rest.len()) as u64;
+                let file = OpenOptions::new()
+                    .write(true)
+                    .open(&wal_path)
+                    .map_err(persistence_err)?;
+                file.set_len(valid).map_err(persistence_err)?;
+            }
+        }
+        let wal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&wal_path)
+            .map_err(persistence_err)?;
+        log::info!(
+            "Restored {} contracts from {} ({} logged changes)",
+            state.table.len(),
+            dir.display(),
+            records
+        );
+        Ok((
+            Self {
+                dir,
+                wal,
+                records,
+                snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+                modules,
+            },
+            state,
+        ))
+    }
+    pub fn with_snapshot_interval(mut self, snapshot_interval: u64) -> Self {
+        self.snapshot_interval = snapshot_interval.max(1);
+        self
+    }
+    /// Logs the current value of the contracts and tickets under `keys`. If
+    /// that fails the log is left as it was.
+    pub fn record(&mut self, state: &State, label: &str, keys: &[String]) -> VMResult<()> {
+        let mut keys: Vec<&String> = keys.iter().collect();
+        keys.sort();
+        keys.dedup();
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let contracts: Vec<(String, Option<ContractType>)> = keys
+            .iter()
+            .map(|key| ((*key).clone(), state.table.get(*key).cloned()))
+            .collect();
+        let tickets: Vec<(String, Option<Holdings>)> = keys
+            .into_iter()
+            .map(|key| (key.clone(), state.tickets.get(key).cloned()))
+            .collect();
+        let modules: Vec<(String, Vec<u8>)> = state
+            .modules_of(
+                contracts
+                    .iter()
+                    .filter_map(|(_, contract)| contract.as_ref()),
+            )
+            .into_iter()
+            .filter(|(hash, _)| !self.modules.contains(*hash))
+            .map(|(hash, module)| (hash.to_owned(), module.to_vec()))
+            .collect();
+        let record = WalRecord {
+            label: label.to_owned(),
+            contracts,
+            tickets,
+            modules,
+        };
+        let bytes = bincode::serialize(&record).map_err(persistence_err)?;
+        let mut frame = (bytes.len() as u64).to_le_bytes().to_vec();
+        frame.extend(bytes);
+        let start = self.wal.metadata().map_err(persistence_err)?.len();
+        if let Err(err) = self
+            .wal
+            .write_all(&frame)
+            .and_then(|()| self.wal.sync_data())
+        {
+            let _ = self.wal.set_len(start);
+            return Err(persistence_err(err));
+        }
+        self.modules
+            .extend(record.modules.into_iter().map(|(hash, _)| hash));
+        self.records += 1;
+        // The record is safe in the log, a snapshot that fails is tried
+        // again after the next one.
+        if self.records >= self.snapshot_interval {
+            if let Err(err) = self.snapshot(state) {
+                log::warn!("Failed to snapshot state: {}", err);
+            }
+        }
+        Ok(())
+    }
+    /// Writes every contract and ticket of `state` to a new snapshot and
+    /// empties the log.
+    pub fn snapshot(&mut self, state: &State) -> VMResult<()> {
+        let contracts: Vec<(&String, &ContractType)> = state.table.iter().collect();
+        let tickets: Vec<(&String, &Holdings)> = state.tickets.iter().collect();
+        let modules = state.modules_of(state.table.values());
+        let bytes =
+            bincode::serialize(&(&contracts, &tickets, &modules)).map_err(persistence_err)?;
+        let temporary = self.dir.join(format!("{}.tmp", SNAPSHOT));
+        let mut file = File::create(&temporary).map_err(persistence_err)?;
+        file.write_all(&bytes)
+            .and_then(|()| file.sync_all())
+            .map_err(persistence_err)?;
+        fs::rename(&temporary, self.dir.join(SNAPSHOT)).map_err(persistence_err)?;
+        self.wal.set_len(0).map_err(persistence_err)?;
+        self.records = 0;
+        self.modules = modules
+            .into_iter()
+            .map(|(hash, _)| hash.to_owned())
+            .collect();
+        Ok(())
+    }
+}
+
+type SnapshotFile = (
+    Vec<(String, ContractType)>,
+    Vec<(String, Holdings)>,
+    Vec<(String, Vec<u8>)>,
+);
+
+/// The record at the start of `bytes` and the bytes after it, or `None` if
+/// they end before the record does.
+fn next_record(bytes: &[u8]) -> VMResult<Option<(WalRecord, &[u8])>> {
+    let Some((len, rest)) = bytes.split_at_checked(8) else {
+        return Ok(None);
+    };
+    let len = u64::from_le_bytes(len.try_into().expect("length is 8 bytes"));
+    let Some((record, rest)) = usize::try_from(len)
+        .ok()
+        .and_then(|len| rest.split_at_checked(len))
+    else {
+        return Ok(None);
+    };
+    let record = bincode::deserialize(record).map_err(persistence_err)?;
+    Ok(Some((record, rest)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write, path::PathBuf};
+
+    use crate::{
+        fixtures::contract_state,
+        managed::value::Value,
+        state::{ContractType, State},
+        ticket_table::{Ticket, TicketId},
+    };
+
+    use super::{next_record, Persistence, WAL};
+
+    fn contract(address: &str, storage: i64) -> ContractType {
+        ContractType::LigoContract(contract_state(address, Value::Int(storage.into())))
+    }
+
+    fn directory(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vm_persist_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn storages(state: &State) -> Vec<(String, Value)> {
+        let mut storages: Vec<_> = state
+            .table
+            .iter()
+            .map(|(key, contract)| (key.clone(), contract.storage().clone()))
+            .collect();
+        storages.sort();
+        storages
+    }
+
+    #[test]
+    fn restores_snapshot_and_log() {
+        let dir = directory("restore");
+        let (persistence, mut state) = Persistence::open(&dir).unwrap();
+        let mut persistence = persistence.with_snapshot_interval(3);
+        assert!(state.table.is_empty());
+        state
+            .modules
+            .insert_serialized("00".to_owned(), vec![1, 2, 3]);
+        for (idx, address) in ["DK1a", "DK1b", "DK1a", "DK1c"].iter().enumerate() {
+            state.set(address.to_string(), contract(address, idx as i64));
+            persistence
+                .record(&state, "op", &[address.to_string()])
+                .unwrap();
+        }
+        state.table.remove("DK1b");
+        persistence
+            .record(&state, "op", &["DK1b".to_owned()])
+            .unwrap();
+        drop(persistence);
+
+        let (_, restored) = Persistence::open(&dir).unwrap();
+        assert_eq!(storages(&restored), storages(&state));
+        assert_eq!(restored.modules.serialized("00"), Some(&[1, 2, 3][..]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn logs_each_module_once() {
+        let dir = directory("modules");
+        let (mut persistence, mut state) = Persistence::open(&dir).unwrap();
+        state
+            .modules
+            .insert_serialized("00".to_owned(), vec![1, 2, 3]);
+        for address in ["DK1a", "DK1b"] {
+            state.set(address.to_owned(), contract(address, 1));
+            persistence
+                .record(&state, "op", &[address.to_owned()])
+                .unwrap();
+        }
+        drop(persistence);
+
+        let bytes = std::fs::read(dir.join(WAL)).unwrap();
+        let (first, rest) = next_record(&bytes).unwrap().unwrap();
+        let (second, rest) = next_record(rest).unwrap().unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(first.modules, vec![("00".to_owned(), vec![1, 2, 3])]);
+        assert!(second.modules.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn restores_tickets_in_storage() {
+        let dir = directory("tickets");
+        let (mut persistence, mut state) = Persistence::open(&dir).unwrap();
+        let holding_a_ticket = |address: &str| {
+            let ticket = Ticket::new(TicketId::new(address.to_owned(), "data".to_owned()), 5);
+            ContractType::LigoContract(contract_state(address, Value::Ticket(ticket)))
+        };
+        // The first contract ends up in the snapshot, the second in the log.
+        state.set("DK1a".to_owned(), holding_a_ticket("DK1a"));
+        persistence.snapshot(&state).unwrap();
+        state.set("DK1b".to_owned(), holding_a_ticket("DK1b"));
+        persistence
+            .record(&state, "op", &["DK1b".to_owned()])
+            .unwrap();
+        drop(persistence);
+
+        let (_, restored) = Persistence::open(&dir).unwrap();
+        assert_eq!(storages(&restored), storages(&state));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn restores_the_tickets_held() {
+        let dir = directory("holdings");
+        let (mut persistence, mut state) = Persistence::open(&dir).unwrap();
+        let ticket = TicketId::new("DK1a".to_owned(), "data".to_owned());
+        state.deposit_tickets("tz1a", &[(ticket.clone(), 5)]);
+        state.deposit_tickets("tz1b", &[(ticket.clone(), 1)]);
+        persistence.snapshot(&state).unwrap();
+        state.lend_tickets("tz1a");
+        state.deposit_tickets("tz1b", &[(ticket, 2)]);
+        persistence
+            .record(&state, "op", &["tz1a".to_owned(), "tz1b".to_owned()])
+            .unwrap();
+        drop(persistence);
+
+        let (_, restored) = Persistence::open(&dir).unwrap();
+        assert_eq!(restored.tickets, state.tickets);
+        assert_eq!(restored.tickets.len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ignores_a_torn_record() {
+        let dir = directory("torn");
+        let (mut persistence, mut state) = Persistence::open(&dir).unwrap();
+        state.set("DK1a".to_owned(), contract("DK1a", 1));
+        persistence
+            .record(&state, "op", &["DK1a".to_owned()])
+            .unwrap();
+        drop(persistence);
+        let mut wal = OpenOptions::new().append(true).open(dir.join(WAL)).unwrap();
+        wal.write_all(&100u64.to_le_bytes()).unwrap();
+        wal.write_all(&[0; 10]).unwrap();
+        drop(wal);
+
+        let (mut persistence, mut restored) = Persistence::open(&dir).unwrap();
+        assert_eq!(storages(&restored), storages(&state));
+        restored.set("DK1b".to_owned(), contract("DK1b", 2));
+        persistence
+            .record(&restored, "op", &["DK1b".to_owned()])
+            .unwrap();
+        drop(persistence);
+        let (_, restored_again) = Persistence::open(&dir).unwrap();
+        assert_eq!(storages(&restored_again), storages(&restored));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_corrupt_record() {
+        let dir = directory("corrupt");
+        let (persistence, _) = Persistence::open(&dir).unwrap();
+        drop(persistence);
+        let mut wal = OpenOptions::new().append(true).open(dir.join(WAL)).unwrap();
+        wal.write_all(&3u64.to_le_bytes()).unwrap();
+        wal.write_all(&[0xff; 3]).unwrap();
+        drop(wal);
+
+        assert!(Persistence::open(&dir).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
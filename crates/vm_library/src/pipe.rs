@@ -50,19 +50,30 @@ impl IO {
         }
     }
 
-    pub fn read(&mut self) -> ClientMessage {
+    /// Talks over already opened files instead of the named pipes.
+    pub fn from_files(reader: File, writer: File) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            writer: BufWriter::new(writer),
+        }
+    }
+
+    /// The next message of the node, `None` once it closed its end.
+    pub fn read(&mut self) -> Option<ClientMessage> {
         let mut len_bytes = [0u8; std::mem::size_of::<usize>()];
 
-        self.reader
-            .read_exact(&mut len_bytes)
-            .expect("failed to parse client_message size");
+        match self.reader.read_exact(&mut len_bytes) {
+            Ok(()) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(err) => panic!("failed to parse client_message size: {}", err),
+        }
         let len = usize::from_ne_bytes(len_bytes);
 
         let mut buf = vec![0; len];
         self.reader
             .read_exact(&mut buf[..])
             .expect("failed to read client_message");
-        serde_json::from_slice(&buf[..]).expect("failed to parse client_message")
+        Some(serde_json::from_slice(&buf[..]).expect("failed to parse client_message"))
     }
     pub fn write(&mut self, msg: &ServerMessage) {
         let msg = serde_json::to_string(msg).expect("Failed to write to pipe");
@@ -0,0 +1,169 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::Write,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use serde::{Serialize, Serializer};
+
+/// What running some code cost, exclusive of the code it called.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Cost {
+    pub calls: u64,
+    pub gas: u64,
+    pub nanos: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Frame {
+    /// A wasm function entered from the host: `main`, a migration, a closure
+    /// or a view. The functions it calls within its module are charged to
+    /// it.
+    Function(String),
+    Import(String),
+}
+impl Frame {
+    fn name(&self) -> &str {
+        match self {
+            Frame::Function(name) | Frame::Import(name) => name,
+        }
+    }
+}
+
+/// Where the gas and time of an invocation went, charged to the stack of
+/// wasm functions and host imports running when it was spent.
+#[derive(Debug, Default)]
+pub struct Profile {
+    stacks: BTreeMap<Vec<Frame>, Cost>,
+    stack: Vec<Frame>,
+    gas: u64,
+    time: Option<Instant>,
+}
+
+pub type ProfileSink = Rc<RefCell<Profile>>;
+
+impl Profile {
+    pub(crate) fn enter_function(&mut self, name: String, gas: u64) {
+        self.push(Frame::Function(name), gas)
+    }
+    pub(crate) fn enter_import(&mut self, name: &str, gas: u64) {
+        self.push(Frame::Import(name.to_owned()), gas)
+    }
+    pub(crate) fn leave(&mut self, gas: u64) {
+        self.charge(gas);
+        self.stack.pop();
+    }
+    fn push(&mut self, frame: Frame, gas: u64) {
+        self.charge(gas);
+        self.stack.push(frame);
+        self.stacks.entry(self.stack.clone()).or_default().calls += 1;
+    }
+    // Charges what was spent since the last push or pop to the stack as it
+    // was in between.
+    fn charge(&mut self, gas: u64) {
+        let now = Instant::now();
+        let elapsed = self.time.map_or(Duration::ZERO, |time| now - time);
+        if !self.stack.is_empty() {
+            let cost = self.stacks.entry(self.stack.clone()).or_default();
+            cost.gas += self.gas.saturating_sub(gas);
+            cost.nanos += elapsed.as_nanos() as u64;
+        }
+        self.gas = gas;
+        self.time = Some(now);
+    }
+    fn by(&self, function: bool) -> BTreeMap<&str, Cost> {
+        let mut totals = BTreeMap::<&str, Cost>::new();
+        self.stacks
+            .iter()
+            .for_each(|(stack, cost)| match stack.last() {
+                Some(frame) if matches!(frame, Frame::Function(_)) == function => {
+                    let total = totals.entry(frame.name()).or_default();
+                    total.calls += cost.calls;
+                    total.gas += cost.gas;
+                    total.nanos += cost.nanos;
+                }
+                _ => (),
+            });
+        totals
+    }
+    /// Costs of each host import, summed over every call.
+    pub fn imports(&self) -> BTreeMap<&str, Cost> {
+        self.by(false)
+    }
+    /// Costs of each wasm function entered from the host, summed over every
+    /// call. Functions only called from within their module are not told
+    /// apart from their caller.
+    pub fn functions(&self) -> BTreeMap<&str, Cost> {
+        self.by(true)
+    }
+    /// The gas spent in each stack, one `frame;frame;... gas` line per stack
+    /// as flamegraph tools expect.
+    pub fn collapsed(&self) -> String {
+        let mut collapsed = String::new();
+        self.stacks
+            .iter()
+            .filter(|(_, cost)| cost.gas > 0)
+            .for_each(|(stack, cost)| {
+                let stack: Vec<_> = stack.iter().map(Frame::name).collect();
+                let _ = writeln!(collapsed, "{} {}", stack.join(";"), cost.gas);
+            });
+        collapsed
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    imports: BTreeMap<&'a str, Cost>,
+    functions: BTreeMap<&'a str, Cost>,
+    collapsed: String,
+}
+impl Serialize for Profile {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Report {
+            imports: self.imports(),
+            functions: self.functions(),
+            collapsed: self.collapsed(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charges_gas_to_the_running_stack() {
+        let mut profile = Profile::default();
+        profile.enter_function("main".to_owned(), 1000);
+        profile.enter_import("exec", 900);
+        profile.enter_function("closure 0".to_owned(), 850);
+        profile.enter_import("pair", 800);
+        profile.leave(700);
+        profile.leave(650);
+        profile.leave(600);
+        profile.enter_import("pair", 600);
+        profile.leave(500);
+        profile.leave(450);
+
+        let imports = profile.imports();
+        assert_eq!((imports["pair"].calls, imports["pair"].gas), (2, 200));
+        assert_eq!((imports["exec"].calls, imports["exec"].gas), (1, 100));
+        let functions = profile.functions();
+        assert_eq!((functions["main"].calls, functions["main"].gas), (1, 150));
+        assert_eq!(functions["closure 0"].gas, 100);
+        assert_eq!(
+            profile.collapsed(),
+            "main 150\n\
+             main;exec 100\n\
+             main;exec;closure 0 100\n\
+             main;exec;closure 0;pair 100\n\
+             main;pair 100\n"
+        );
+    }
+}
@@ -3,69 +3,464 @@ use std::{borrow::Cow, cell::RefCell, rc::Rc};
 use fnv::{FnvHashMap, FnvHashSet};
 
 use crate::{
-    arena::ARENA,
-    compile,
+    compile_store::FeatureSet,
     contract_address::ContractAddress,
     errors::{vm::VmError, VMResult},
     execution_result::ExecutionResult,
     incoming::InvokeManaged,
     instance::invoke_managed,
-    managed::value::{FromOcamlV, Value},
-    outgoing::{InitVec, SetBorrowed},
+    managed::{
+        diff::StorageDiff,
+        types::Type,
+        value::{FromOcamlV, Operation as ManagedOperation, Value},
+    },
+    outgoing::{ModuleBorrowed, ModuleOwned, SetBorrowed, SetOwned},
+    parallel::{execute_block, speculate_invoke, AccessSet, Snapshot, SpeculativeInvoke},
     path::Path,
+    persist::Persistence,
     pipe::IO,
-    state::{ContractType, LigoCode, LigoContractState, State},
-    ticket_table::{Ticket, TicketTable},
-    vm_client::{ClientMessage, Operation, Transaction},
-    vm_server::{ServerMessage, TicketDeposit},
+    profile::{Profile, ProfileSink},
+    state::{ContractType, Holdings, LigoContractState, State},
+    state_root::{to_hex, StateTree},
+    ticket_table::{Ticket, TicketId, TicketTable},
+    trace::{Trace, TraceSink},
+    view::{is_portable, run_view, Views},
+    vm_client::{
+        BigMapQuery, Block, ClientMessage, ExportCursor, Operation, Originate, StorageQuery,
+        Transaction, Upgrade, ViewQuery,
+    },
+    vm_server::{
+        BlockReceipt, ContractEvent, ServerMessage, StateChunk, StorageChange, StorageUpdate,
+        TicketDeposit, TransactionReceipt, ViewResult,
+    },
 };
+/// How to undo one change a transaction made to the state.
+enum Undo {
+    /// Puts back the storage of a contract, or removes a contract that did
+    /// not exist.
+    Storage(String, Option<Value>),
+    /// Puts back a contract replaced by an upgrade.
+    Contract(String, Box<ContractType>),
+    /// Puts back the tickets an address held.
+    Tickets(String, Option<Holdings>),
+    /// Goes back to the features of the previous protocol.
+    Features(FeatureSet),
+}
+impl Undo {
+    fn address(&self) -> Option<&str> {
+        match self {
+            Undo::Storage(address, _) | Undo::Contract(address, _) | Undo::Tickets(address, _) => {
+                Some(address)
+            }
+            Undo::Features(_) => None,
+        }
+    }
+}
 struct ExecutionState {
     pub state: State,
-    pub to_revert: Vec<(String, Option<Value>)>,
+    /// The changes of the current transaction, in the order they were made.
+    pub undo: Vec<Undo>,
     pub io: IO,
     pub ticket_table: Rc<RefCell<TicketTable>>,
+    pub events: Vec<ContractEvent>,
+    pub origination_nonce: u32,
+    pub block: Option<BlockJournal>,
+    pub persistence: Option<Persistence>,
 }
+impl ExecutionState {
+    fn written(&self) -> Vec<String> {
+        self.undo
+            .iter()
+            .filter_map(|undo| undo.address().map(str::to_owned))
+            .collect()
+    }
+    fn persist(&mut self, label: &str, keys: Vec<String>) -> VMResult<()> {
+        match &mut self.persistence {
+            Some(persistence) => persistence.record(&self.state, label, &keys),
+            None => Ok(()),
+        }
+    }
+    fn snapshot(&mut self) -> VMResult<()> {
+        match &mut self.persistence {
+            Some(persistence) => persistence.snapshot(&self.state),
+            None => Ok(()),
+        }
+    }
+    /// Compiles modules for protocol `version` from the current transaction
+    /// on, unless it fails.
+    fn switch_protocol(&mut self, version: Option<u32>) {
+        if let Some(version) = version {
+            let previous = *self.state.modules.features();
+            self.state
+                .modules
+                .set_features(FeatureSet::for_protocol(version));
+            self.undo.push(Undo::Features(previous));
+        }
+    }
+    fn take_tickets(&mut self, address: &str) -> Vec<(TicketId, usize)> {
+        match &mut self.block {
+            Some(block) => block.take(address),
+            None => {
+                let previous = self.state.lend_tickets(address);
+                self.undo.push(Undo::Tickets(address.to_owned(), previous));
+                take_tickets(&mut self.io, address)
+            }
+        }
+    }
+    fn deposit_tickets(&mut self, address: &str, tickets: &[(TicketId, usize)]) -> VMResult<()> {
+        match &mut self.block {
+            Some(block) => {
+                block.deposit(address, tickets);
+                Ok(())
+            }
+            None => {
+                let previous = self.state.deposit_tickets(address, tickets);
+                self.undo.push(Undo::Tickets(address.to_owned(), previous));
+                self.io
+                    .write_with_fail(&ServerMessage::DepositTickets(TicketDeposit {
+                        address,
+                        tickets,
+                    }))
+                    .map_err(|err| VmError::RuntimeErr(err.to_string()))
+            }
+        }
+    }
+    fn set_contract(&mut self, key: &str, value: &ContractType) -> VMResult<()> {
+        if let Some(block) = &mut self.block {
+            block.contracts_set.push(key.to_owned());
+            return Ok(());
+        }
+        let msg = SetBorrowed {
+            key,
+            value: &Cow::Borrowed(value),
+        };
+        match self.io.write_with_fail(&ServerMessage::Set(msg)) {
+            Ok(()) => Ok(()),
+            Err(_) => {
+                self.io
+                    .write(&ServerMessage::Error("failed to set".to_owned()));
+                Err(VmError::RuntimeErr("cant talk to host".to_owned()))
+            }
+        }
+    }
+    /// Sends the node a module it has not seen yet.
+    fn set_module(&mut self, hash: &str) -> VMResult<()> {
+        if let Some(block) = &mut self.block {
+            block.modules.insert(hash.to_owned());
+            return Ok(());
+        }
+        let module = match self.state.modules.serialized(hash) {
+            Some(module) => module,
+            None => return Ok(()),
+        };
+        self.io
+            .write_with_fail(&ServerMessage::SetModule(ModuleBorrowed { hash, module }))
+            .map_err(|_| {
+                self.io
+                    .write(&ServerMessage::Error("failed to set".to_owned()));
+                VmError::RuntimeErr("cant talk to host".to_owned())
+            })
+    }
+    /// Tells the node about a new storage only, as a diff from the previous
+    /// one unless the whole value was replaced.
+    fn update_storage(&mut self, key: &str, old: &Value, new: &Value) -> VMResult<()> {
+        if let Some(block) = &mut self.block {
+            block.contracts_set.push(key.to_owned());
+            return Ok(());
+        }
+        let change = match StorageDiff::new(old, new) {
+            StorageDiff::Replace(storage) => StorageChange::Storage(storage),
+            diff => StorageChange::StorageDiff(diff),
+        };
+        self.io
+            .write_with_fail(&ServerMessage::StorageUpdate(StorageUpdate {
+                address: key,
+                change,
+            }))
+            .map_err(|_| {
+                self.io
+                    .write(&ServerMessage::Error("failed to set".to_owned()));
+                VmError::RuntimeErr("cant talk to host".to_owned())
+            })
+    }
+    fn report(&mut self, error: String) {
+        match &mut self.block {
+            Some(block) => block.errors.push(error),
+            None => self.io.write(&ServerMessage::Error(error)),
+        }
+    }
+    fn trace(&mut self, trace: Trace) {
+        match &mut self.block {
+            Some(block) => block.traces.push(trace),
+            None => self.io.write(&ServerMessage::Trace(&trace)),
+        }
+    }
+    fn profile(&mut self, profile: Profile) {
+        match &mut self.block {
+            Some(block) => block.profiles.push(profile),
+            None => self.io.write(&ServerMessage::Profile(&profile)),
+        }
+    }
+    fn revert(&mut self) {
+        let state = &mut self.state;
+        self.undo.drain(0..).rev().for_each(|undo| match undo {
+            Undo::Storage(addr, storage) => state.reset(addr, storage),
+            Undo::Contract(addr, contract_type) => {
+                state.set(addr, *contract_type);
+            }
+            Undo::Tickets(addr, tickets) => {
+                state.set_tickets(addr, tickets.unwrap_or_default());
+            }
+            Undo::Features(features) => state.modules.set_features(features),
+        });
+    }
+}
+/// Stands in for the node while a block executes: tickets are taken from
+/// and deposited into a local ledger, and what would have been written to
+/// the pipe is kept for the block receipt.
+struct BlockJournal {
+    tickets: FnvHashMap<String, Holdings>,
+    tickets_undo: Vec<(String, Option<Holdings>)>,
+    ticket_holders: FnvHashSet<String>,
+    contracts: FnvHashSet<String>,
+    contracts_set: Vec<String>,
+    modules: FnvHashSet<String>,
+    errors: Vec<String>,
+    traces: Vec<Trace>,
+    profiles: Vec<Profile>,
+}
+impl BlockJournal {
+    fn new(tickets: Vec<(String, Holdings)>) -> Self {
+        Self {
+            tickets: tickets.into_iter().collect(),
+            tickets_undo: vec![],
+            ticket_holders: FnvHashSet::default(),
+            contracts: FnvHashSet::default(),
+            contracts_set: vec![],
+            modules: FnvHashSet::default(),
+            errors: vec![],
+            traces: vec![],
+            profiles: vec![],
+        }
+    }
+    fn holdings(&mut self, address: &str) -> &mut Holdings {
+        self.tickets_undo
+            .push((address.to_owned(), self.tickets.get(address).cloned()));
+        self.tickets.entry(address.to_owned()).or_default()
+    }
+    fn holds_tickets(&self, address: &str) -> bool {
+        self.tickets
+            .get(address)
+            .is_some_and(|tickets| !tickets.is_empty())
+    }
+    fn take(&mut self, address: &str) -> Vec<(TicketId, usize)> {
+        std::mem::take(self.holdings(address))
+    }
+    fn deposit(&mut self, address: &str, tickets: &[(TicketId, usize)]) {
+        self.holdings(address).extend_from_slice(tickets)
+    }
+    /// Addresses whose contract or tickets the current transaction changed.
+    fn written(&self) -> impl Iterator<Item = &str> {
+        self.tickets_undo
+            .iter()
+            .map(|(address, _)| address.as_str())
+            .chain(self.contracts_set.iter().map(String::as_str))
+    }
+    fn commit(&mut self) {
+        self.ticket_holders
+            .extend(self.tickets_undo.drain(0..).map(|(address, _)| address));
+        self.contracts.extend(self.contracts_set.drain(0..));
+        self.errors.clear();
+    }
+    fn rollback(&mut self) {
+        let tickets = &mut self.tickets;
+        self.tickets_undo
+            .drain(0..)
+            .rev()
+            .for_each(|(address, previous)| match previous {
+                Some(previous) => {
+                    tickets.insert(address, previous);
+                }
+                None => {
+                    tickets.remove(&address);
+                }
+            });
+        self.contracts_set.clear();
+        self.errors.clear();
+    }
+}
+/// Serves the node over `io` until it closes its end.
 pub fn run_loop(io: IO) {
-    let state = State::default();
+    run_loop_with(io, State::default(), None)
+}
+/// Runs starting from `state`, keeping `persistence` up to date with every
+/// change applied to it.
+pub fn run_loop_persistent(io: IO, state: State, persistence: Persistence) {
+    run_loop_with(io, state, Some(persistence))
+}
+fn run_loop_with(io: IO, state: State, persistence: Option<Persistence>) {
     let table = TicketTable::default();
-    let to_revert: Vec<(String, Option<Value>)> = Vec::with_capacity(100);
     let mut context = ExecutionState {
         state,
-        to_revert,
+        undo: Vec::with_capacity(100),
         io,
         ticket_table: Rc::new(RefCell::new(table)),
+        events: vec![],
+        origination_nonce: 0,
+        block: None,
+        persistence,
     };
     loop {
-        {
-            let mut mutable = context.ticket_table.as_ref().borrow_mut();
-            mutable.table.clear();
-            mutable.counter = 0;
-        };
-        context
-            .to_revert
-            .drain(0..)
-            .for_each(|(addr, contract_type)| {
-                context.state.reset(addr, contract_type);
-            });
-        let arena = unsafe { &mut ARENA };
-
-        arena.clear();
+        context.ticket_table.as_ref().borrow_mut().reset();
+        context.revert();
         'inner: loop {
-            let msg = context.io.read();
+            let msg = match context.io.read() {
+                Some(msg) => msg,
+                None => return,
+            };
             match msg {
                 ClientMessage::SetInitialState(x) => {
                     State::from_init(&mut context.state, x).expect("failed to init_state");
+                    context
+                        .snapshot()
+                        .expect("failed to persist the initial state");
+                }
+                ClientMessage::GetInitialState => match context.state.to_init() {
+                    Ok(init) => context.io.write(&ServerMessage::Init(init)),
+                    Err(err) => context.io.write(&ServerMessage::Error(err.to_string())),
+                },
+                ClientMessage::Get(address) => {
+                    let contract = context.state.table.get(&address.address).map(Cow::Borrowed);
+                    let msg = contract.as_ref().map(|value| SetBorrowed {
+                        key: &address.address,
+                        value,
+                    });
+                    context.io.write(&ServerMessage::Contract(msg))
                 }
-                ClientMessage::GetInitialState => {
-                    context.io.write(&ServerMessage::Init(InitVec(vec![])))
+                ClientMessage::SetModule(ModuleOwned { hash, module }) => {
+                    context.state.modules.insert_serialized(hash, module)
+                }
+                ClientMessage::Set(set) => {
+                    let SetOwned { key, value } = *set;
+                    context.state.set(key.clone(), value);
+                    context
+                        .persist("set", vec![key])
+                        .expect("failed to persist a contract");
+                }
+                ClientMessage::ExportState(ExportCursor { after, limit }) => {
+                    let (chunk, next) = context.state.export_chunk(after.as_deref(), limit);
+                    let values: Vec<(&str, Cow<ContractType>)> = chunk
+                        .into_iter()
+                        .map(|(key, value)| (key, Cow::Borrowed(value)))
+                        .collect();
+                    let contracts = values
+                        .iter()
+                        .map(|(key, value)| SetBorrowed { key, value })
+                        .collect();
+                    let modules = context
+                        .state
+                        .modules_of(values.iter().map(|(_, value)| value.as_ref()))
+                        .into_iter()
+                        .map(|(hash, module)| ModuleBorrowed { hash, module })
+                        .collect();
+                    context.io.write(&ServerMessage::StateChunk(StateChunk {
+                        contracts,
+                        modules,
+                        next,
+                    }))
                 }
                 ClientMessage::Transaction(transaction) => {
                     log::info!("Received transaction {:?}", transaction);
-                    match handle_transaction(&mut context, transaction, 0) {
-                        Ok(_) => context.io.write(&ServerMessage::Stop),
+                    context.events.clear();
+                    context.origination_nonce = 0;
+                    context.switch_protocol(transaction.protocol_version);
+                    let operation_hash = transaction.operation_raw_hash.clone();
+                    // A transaction that cannot be persisted fails.
+                    let result =
+                        handle_transaction(&mut context, transaction, u64::MAX).and_then(|_| {
+                            let written = context.written();
+                            context
+                                .persist(&operation_hash, written)
+                                .inspect_err(|err| {
+                                    context.report(err.to_string());
+                                })
+                        });
+                    match result {
+                        Ok(()) => {
+                            let receipt = context.ticket_table.as_ref().borrow_mut().take_receipt();
+                            context.io.write(&ServerMessage::Receipt(&receipt));
+                            context.io.write(&ServerMessage::Events(&context.events));
+                            context.io.write(&ServerMessage::Stop)
+                        }
                         Err(_) => break 'inner,
                     }
                 }
+                ClientMessage::GetStateRoot => match StateTree::new(&context.state) {
+                    Ok(tree) => context
+                        .io
+                        .write(&ServerMessage::StateRoot(to_hex(&tree.root()))),
+                    Err(err) => context.io.write(&ServerMessage::Error(err.to_string())),
+                },
+                ClientMessage::GetStorageProof(address) => match StateTree::new(&context.state) {
+                    Ok(tree) => context
+                        .io
+                        .write(&ServerMessage::StorageProof(tree.prove(&address))),
+                    Err(err) => context.io.write(&ServerMessage::Error(err.to_string())),
+                },
+                ClientMessage::GetStorage(StorageQuery { address, path }) => {
+                    match context.state.storage_at(&address, &path) {
+                        Ok(storage) => context
+                            .io
+                            .write(&ServerMessage::Storage(FromOcamlV(storage.clone()))),
+                        Err(err) => context.io.write(&ServerMessage::Error(err.to_string())),
+                    }
+                }
+                ClientMessage::GetBigMapValue(query) => {
+                    let BigMapQuery {
+                        address,
+                        big_map_id,
+                        key,
+                    } = *query;
+                    match context.state.big_map_value(&address, big_map_id, &key.0) {
+                        Ok(value) => context.io.write(&ServerMessage::BigMapValue(
+                            value.map(|value| FromOcamlV(value.clone())),
+                        )),
+                        Err(err) => context.io.write(&ServerMessage::Error(err.to_string())),
+                    }
+                }
+                ClientMessage::RunView(query) => {
+                    let ViewQuery {
+                        address,
+                        view,
+                        argument,
+                        source,
+                        gas_limit,
+                    } = *query;
+                    let source = source.unwrap_or_else(|| address.clone());
+                    match run_view(
+                        &mut context.state,
+                        &address,
+                        &view,
+                        argument.0,
+                        source,
+                        gas_limit,
+                    ) {
+                        Ok((value, remaining_gas)) => {
+                            context.io.write(&ServerMessage::ViewResult(ViewResult {
+                                value: FromOcamlV(value),
+                                remaining_gas,
+                            }))
+                        }
+                        Err(err) => context.io.write(&ServerMessage::Error(err.to_string())),
+                    }
+                }
+                ClientMessage::Block(block) => {
+                    log::info!(
+                        "Received block of {} transactions",
+                        block.transactions.len()
+                    );
+                    handle_block(&mut context, *block);
+                }
                 ClientMessage::NoopTransaction => {
                     log::info!("Received Noop transaction");
                     context.io.write(&ServerMessage::Stop);
@@ -73,17 +468,241 @@ pub fn run_loop(io: IO) {
                 }
                 x => panic!("run_loop not supported, {:?}", x),
             }
-            context.to_revert.clear();
+            context.undo.clear();
         }
     }
 }
 
+fn handle_block(context: &mut ExecutionState, block: Block) {
+    let Block {
+        transactions,
+        context: block_context,
+    } = block;
+    if let Some(version) = block_context.protocol_version {
+        context
+            .state
+            .modules
+            .set_features(FeatureSet::for_protocol(version));
+    }
+    context.block = Some(BlockJournal::new(block_context.tickets));
+    // A transaction switching protocols changes how later contracts compile,
+    // which a snapshot taken before the block cannot follow.
+    let snapshot = if transactions
+        .iter()
+        .all(|transaction| transaction.protocol_version.is_none())
+    {
+        let addresses: FnvHashSet<String> = transactions
+            .iter()
+            .filter_map(
+                |transaction| match serde_json::from_str(&transaction.operation) {
+                    Ok(Operation::Invoke { address, .. }) => Some(address.address),
+                    _ => None,
+                },
+            )
+            .collect();
+        Snapshot::capture(&mut context.state, addresses.iter().map(String::as_str))
+            .unwrap_or_default()
+    } else {
+        Snapshot::default()
+    };
+    let workers = std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get);
+    let mut receipts = Vec::with_capacity(transactions.len());
+    let stats = execute_block(
+        &transactions,
+        workers,
+        |transaction| speculate_invoke(&snapshot, transaction),
+        |transaction, speculated| {
+            let (receipt, access) = handle_block_transaction(context, transaction, speculated);
+            receipts.push(receipt);
+            access
+        },
+    );
+    log::info!(
+        "Block executed, {} speculatively and {} sequentially",
+        stats.speculated,
+        stats.reexecuted
+    );
+    let block = context.block.take().expect("block journal is set");
+    block.ticket_holders.iter().for_each(|address| {
+        let tickets = block.tickets.get(address).cloned().unwrap_or_default();
+        context.state.set_tickets(address.clone(), tickets);
+    });
+    // The transactions of the block are committed and cannot be reverted.
+    context
+        .persist(
+            "block",
+            block
+                .contracts
+                .union(&block.ticket_holders)
+                .cloned()
+                .collect(),
+        )
+        .expect("failed to persist a block");
+    let mut contracts: Vec<&String> = block.contracts.iter().collect();
+    contracts.sort();
+    let contracts: Vec<(&String, Cow<ContractType>)> = contracts
+        .into_iter()
+        .filter_map(|key| {
+            let contract = context.state.table.get(key)?;
+            Some((key, Cow::Borrowed(contract)))
+        })
+        .collect();
+    let state = contracts
+        .iter()
+        .map(|(key, value)| SetBorrowed { key, value })
+        .collect();
+    let modules = context
+        .state
+        .modules_of(contracts.iter().map(|(_, value)| value.as_ref()))
+        .into_iter()
+        .filter(|(hash, _)| block.modules.contains(*hash))
+        .map(|(hash, module)| ModuleBorrowed { hash, module })
+        .collect();
+    let mut holders: Vec<&String> = block.ticket_holders.iter().collect();
+    holders.sort();
+    let tickets = holders
+        .into_iter()
+        .map(|address| {
+            let tickets = block.tickets.get(address).map_or(&[][..], Vec::as_slice);
+            (address.as_str(), tickets)
+        })
+        .collect();
+    context.io.write(&ServerMessage::Block(BlockReceipt {
+        receipts,
+        state,
+        modules,
+        tickets,
+    }));
+}
+fn handle_block_transaction(
+    context: &mut ExecutionState,
+    transaction: &Transaction,
+    speculated: Option<SpeculativeInvoke>,
+) -> (TransactionReceipt, AccessSet) {
+    context.events.clear();
+    context.origination_nonce = 0;
+    context.switch_protocol(transaction.protocol_version);
+    let holds_tickets = |context: &ExecutionState, address: &str| {
+        context
+            .block
+            .as_ref()
+            .is_some_and(|block| block.holds_tickets(address))
+    };
+    let result = match speculated {
+        Some(invoke) if !holds_tickets(context, &invoke.address.address) => {
+            commit_speculative_invoke(context, invoke)
+        }
+        _ => handle_transaction(context, transaction.clone(), u64::MAX).map(|_| ()),
+    };
+    let mut access = AccessSet::default();
+    context
+        .written()
+        .iter()
+        .for_each(|address| access.write(address));
+    let block = context.block.as_mut().expect("block journal is set");
+    block.written().for_each(|address| access.write(address));
+    let traces = std::mem::take(&mut block.traces);
+    let profiles = std::mem::take(&mut block.profiles);
+    let receipt = match result {
+        Ok(()) => {
+            block.commit();
+            context.undo.clear();
+            TransactionReceipt {
+                tickets: context.ticket_table.as_ref().borrow_mut().take_receipt(),
+                events: std::mem::take(&mut context.events),
+                error: None,
+                traces,
+                profiles,
+            }
+        }
+        Err(err) => {
+            let error = match block.errors.is_empty() {
+                true => err.to_string(),
+                false => block.errors.join("; "),
+            };
+            block.rollback();
+            context.ticket_table.as_ref().borrow_mut().reset();
+            context.revert();
+            TransactionReceipt {
+                tickets: vec![],
+                events: vec![],
+                error: Some(error),
+                traces,
+                profiles,
+            }
+        }
+    };
+    (receipt, access)
+}
+/// Applies an invocation computed by a worker thread with the same effects
+/// `handle_invoke` would have had.
+fn commit_speculative_invoke(
+    context: &mut ExecutionState,
+    invoke: SpeculativeInvoke,
+) -> VMResult<()> {
+    let address = invoke.address.address.clone();
+    context.take_tickets(&address);
+    let mut contract = context
+        .state
+        .get(&address)
+        .ok_or_else(|| VmError::RuntimeErr(format!("contract doesnt exist {}", address)))?;
+    context.undo.push(Undo::Storage(
+        address.clone(),
+        Some(contract.storage().clone()),
+    ));
+    let result = context.deposit_tickets(&address, &[]).and_then(|()| {
+        let storage = invoke.storage()?;
+        context.update_storage(&address, contract.storage(), &storage)?;
+        contract.set_storage(Box::from(storage));
+        Ok(())
+    });
+    context.state.set(address, contract);
+    result
+}
+fn take_tickets(io: &mut IO, address: &str) -> Vec<(TicketId, usize)> {
+    io.write(&ServerMessage::TakeTickets(address));
+    loop {
+        match io.read() {
+            Some(ClientMessage::GiveTickets(ticket)) => break ticket,
+            Some(ClientMessage::NoopTransaction) => (),
+            _ => panic!("bad format"),
+        }
+    }
+}
+
+/// The tickets attached to `transaction` and those held by `address`, taken
+/// from the node for the contract about to run there.
+fn owned_tickets(
+    context: &mut ExecutionState,
+    transaction: &Transaction,
+    address: &str,
+) -> FnvHashSet<Ticket> {
+    let mut tickets: FnvHashSet<Ticket> = transaction
+        .tickets
+        .iter()
+        .cloned()
+        .map(|(x, y)| Ticket::new(x, y))
+        .collect();
+    let taken = context.take_tickets(address);
+    tickets.extend(taken.into_iter().map(|(x, y)| Ticket::new(x, y)));
+    tickets
+}
+/// Fails if the tickets loaded for a contract about to run are not among
+/// `tickets`, the ones it owns.
+fn check_ticket_ownership(context: &ExecutionState, tickets: &FnvHashSet<Ticket>) -> VMResult<()> {
+    let table = context.ticket_table.as_ref().borrow();
+    let loaded = FnvHashSet::from_iter(table.table.iter().cloned());
+    let check: FnvHashSet<_> = tickets.union(&loaded).collect();
+    if tickets.len() > table.counter && check.len() != tickets.len() {
+        return Err(VmError::RuntimeErr("Ticket ownership error".to_owned()));
+    }
+    Ok(())
+}
 fn handle_transaction(
     context: &mut ExecutionState,
     transaction: Transaction,
     mut gas_limit: u64,
 ) -> VMResult<u64> {
-    let io = &mut context.io;
     if let Ok(op) = serde_json::from_str(&transaction.operation) {
         match op {
             Operation::Invoke {
@@ -91,339 +710,609 @@ fn handle_transaction(
                 argument,
                 gas_limit: op_gas_limit,
             } => {
-                gas_limit = op_gas_limit;
-                let mut tickets2: FnvHashSet<Ticket> = transaction
-                    .tickets
-                    .clone()
-                    .into_iter()
-                    .map(|(x, y)| Ticket::new(x, y))
-                    .collect();
-                context
-                    .io
-                    .write(&ServerMessage::TakeTickets(&address.address));
-                'd: loop {
-                    match context.io.read() {
-                        ClientMessage::GiveTickets(ticket) => {
-                            tickets2.extend(ticket.into_iter().map(|(x, y)| Ticket::new(x, y)));
-                            break 'd;
-                        }
-                        ClientMessage::NoopTransaction => (),
-                        _ => panic!("bad format"),
-                    }
-                }
-
+                gas_limit = op_gas_limit.min(gas_limit);
+                let tickets = owned_tickets(context, &transaction, &address.address);
                 let new_limit = handle_invoke(
                     context,
                     transaction,
                     address,
                     argument.0,
                     gas_limit,
-                    tickets2,
+                    tickets,
                 )?;
                 gas_limit = new_limit;
                 Ok(())
             }
-            Operation::Originate {
-                module_,
-                constants,
-                initial_storage,
-                entrypoints,
-                source,
-            } => {
+            Operation::Originate(originate) => {
                 let addres = handle_originate(
                     context,
-                    module_,
-                    constants.into_iter().map(|(x, y)| (x, y.0)).collect(),
-                    initial_storage.0,
+                    originate,
                     transaction.operation_raw_hash.as_bytes().to_vec(),
                     transaction.source,
-                    entrypoints,
-                    source,
                 )?;
                 let address = contract_addr_to_string(&addres);
-                context
-                    .io
-                    .write_with_fail(&ServerMessage::DepositTickets(TicketDeposit {
-                        address: &address,
-                        tickets: &transaction.tickets,
-                    }))
-                    .map_err(|err| VmError::RuntimeErr(err.to_string()))
+                context.deposit_tickets(&address, &transaction.tickets)
+            }
+            Operation::Upgrade(upgrade) => {
+                // Only a migration runs code on the storage and its tickets.
+                let tickets = match upgrade.migration {
+                    Some(_) => owned_tickets(context, &transaction, &upgrade.address),
+                    None => FnvHashSet::default(),
+                };
+                gas_limit = handle_upgrade(context, &transaction, upgrade, gas_limit, tickets)?;
+                Ok(())
+            }
+            Operation::Transfer { address, tickets } => context.deposit_tickets(&address, &tickets),
+            Operation::Batch {
+                operations,
+                gas_limit: batch_gas_limit,
+            } => {
+                if !transaction.tickets.is_empty() {
+                    context.report("tickets cannot be attached to a batch".to_owned());
+                    return Err(VmError::RuntimeErr("Error_ocured".to_owned()));
+                }
+                gas_limit = batch_gas_limit.min(gas_limit);
+                operations.into_iter().try_for_each(|operation| {
+                    let operation = serde_json::to_string(&operation)
+                        .map_err(|err| VmError::RuntimeErr(err.to_string()))?;
+                    let transaction = Transaction {
+                        source: transaction.source.clone(),
+                        sender: transaction.sender.clone(),
+                        operation,
+                        operation_raw_hash: transaction.operation_raw_hash.clone(),
+                        tickets: vec![],
+                        protocol_version: None,
+                        trace: transaction.trace,
+                        profile: transaction.profile,
+                    };
+                    gas_limit = handle_transaction(context, transaction, gas_limit)?;
+                    Ok(())
+                })
             }
-            Operation::Transfer { address, tickets } => context
-                .io
-                .write_with_fail(&ServerMessage::DepositTickets(TicketDeposit {
-                    address: &address,
-                    tickets: &tickets,
-                }))
-                .map_err(|err| VmError::RuntimeErr(err.to_string())),
         }?;
         Ok::<u64, VmError>(gas_limit)
     } else {
-        io.write(&ServerMessage::Error(format!(
+        context.report(format!(
             "bad operation, failed to parse operation, {}",
             &transaction.operation
-        )));
+        ));
         Err(VmError::DeserializeErr("Bad transaction".to_owned()))
     }
 }
 fn handle_originate(
     context: &mut ExecutionState,
-    module: String,
-    constants: Vec<(u32, Value)>,
-    initial_storage: Value,
+    originate: Originate,
     operation_hash: Vec<u8>,
     originated_by: String,
-    entrypoints: Option<FnvHashMap<String, Vec<Path>>>,
-    source: Option<LigoCode>,
 ) -> VMResult<ContractAddress> {
-    let module = compile::compile_managed_module(module.as_bytes())?;
-    let serialized = module
-        .serialize()
-        .map_err(|x| VmError::CompileErr(x.to_string()))?;
-    let addr = ContractAddress::new(&operation_hash);
+    let Originate {
+        module_: module,
+        constants,
+        initial_storage,
+        entrypoints,
+        source,
+        admin,
+        parameter_type,
+        storage_type,
+        views,
+    } = originate;
+    let constants = constants.into_iter().map(|(x, y)| (x, y.0)).collect();
+    let initial_storage = initial_storage.0;
+    let addr = ContractAddress::originated(&operation_hash, context.origination_nonce);
+    context.origination_nonce += 1;
+    check_address_free(context, &addr.address)?;
+    typecheck(context, &storage_type, &initial_storage)?;
+    let (code_hash, module, compiled) = context
+        .state
+        .modules
+        .get_or_compile(module.as_bytes())
+        .inspect_err(|err| context.report(err.to_string()))?;
+    if compiled {
+        context.set_module(&code_hash)?;
+    }
     let contract_type = ContractType::LigoContract(LigoContractState {
         self_: addr.clone(),
         originated_by,
+        admin,
         storage: Box::from(initial_storage),
         module: Some(Box::from(module)),
-        serialized_module: serialized,
+        code_hash,
         constants,
         entrypoints,
         source,
+        parameter_type,
+        storage_type,
+        views,
     });
 
-    let msg = SetBorrowed {
-        key: &addr.address,
-        value: &Cow::Borrowed(&contract_type),
-    };
-    match context.io.write_with_fail(&ServerMessage::Set(msg)) {
-        Ok(()) => Ok(()),
-        Err(_) => {
-            context
-                .io
-                .write(&ServerMessage::Error("failed to set".to_owned()));
-            Err(VmError::RuntimeErr("cant talk to host".to_owned()))
-        }
-    }?;
+    context.set_contract(&addr.address, &contract_type)?;
     context.state.set(addr.address.clone(), contract_type);
-    context.to_revert.push((addr.address.clone(), None));
+    context.undo.push(Undo::Storage(addr.address.clone(), None));
     Ok(addr)
 }
-pub fn contract_addr_to_string(c: &ContractAddress) -> String {
-    c.address.clone()
-}
-fn handle_invoke(
+fn handle_upgrade(
     context: &mut ExecutionState,
-    transaction: Transaction,
-    address: ContractAddress,
-    argument: Value,
-    mut gas_limit: u64,
+    transaction: &Transaction,
+    upgrade: Upgrade,
+    gas_limit: u64,
     tickets: FnvHashSet<Ticket>,
 ) -> VMResult<u64> {
-    match context.state.get(&address.address) {
-        Some(contract) => {
-            let arg = argument.to_runtime_ticket(&mut context.ticket_table.as_ref().borrow_mut());
-
-            let storage = Box::from(
-                contract
-                    .storage()
-                    .clone()
-                    .to_runtime_ticket(&mut context.ticket_table.as_ref().borrow_mut()),
-            );
-            let mut contract = contract;
-            let initial_len = tickets.len();
-            let check = FnvHashSet::from_iter(
-                context
-                    .ticket_table
-                    .as_ref()
-                    .borrow()
-                    .table
-                    .clone()
-                    .into_iter(),
-            );
-            let check: FnvHashSet<_> = tickets.union(&check).collect();
-            if initial_len > context.ticket_table.as_ref().borrow().counter
-                && check.len() != initial_len
-            {
-                return Err(VmError::RuntimeErr("Ticket ownership error".to_owned()));
-            }
-            contract.init()?;
-            context
-                .to_revert
-                .push((address.address.clone(), Some(contract.storage().clone())));
+    let Upgrade {
+        address,
+        module_: module,
+        constants,
+        entrypoints,
+        source,
+        parameter_type,
+        storage_type,
+        views,
+        migration,
+        gas_limit: op_gas_limit,
+    } = upgrade;
+    let mut gas_limit = op_gas_limit.min(gas_limit);
+    let constants: Vec<(u32, Value)> = constants.into_iter().map(|(x, y)| (x, y.0)).collect();
+    let sender = transaction
+        .sender
+        .clone()
+        .unwrap_or_else(|| transaction.source.clone());
+    let previous = match context.state.table.get(&address) {
+        Some(ContractType::LigoContract(previous)) => previous.clone(),
+        None => {
+            context.report(format!("contract doesnt exist {}", address));
+            return Err(VmError::RuntimeErr("Error_ocured".to_owned()));
+        }
+    };
+    if previous.admin.as_ref() != Some(&sender) {
+        context.report(format!("{} is not allowed to upgrade {}", sender, address));
+        return Err(VmError::RuntimeErr("Error_ocured".to_owned()));
+    }
+    let (code_hash, module, compiled) = context
+        .state
+        .modules
+        .get_or_compile(module.as_bytes())
+        .inspect_err(|err| context.report(err.to_string()))?;
+    if compiled {
+        context.set_module(&code_hash)?;
+    }
+    let storage = match migration {
+        None => previous.storage.clone(),
+        Some(idx) => {
+            let storage = previous
+                .storage
+                .as_ref()
+                .clone()
+                .to_runtime_ticket(&mut context.ticket_table.as_ref().borrow_mut(), &address);
+            check_ticket_ownership(context, &tickets)?;
+            let trace = transaction.trace.then(TraceSink::default);
+            let profile = transaction.profile.then(ProfileSink::default);
             let invoke_payload = InvokeManaged {
                 table: Rc::clone(&context.ticket_table),
-                mod_: contract.module().as_ref().unwrap().as_ref(),
-                arg,
-                entrypoint_path: &address.entrypoint.as_ref().map_or_else(
-                    || None,
-                    |x| {
-                        let map = contract.entrypoints().as_ref()?;
-                        map.get(&format!("%{}", x)).cloned()
-                    },
-                ),
-                initial_storage: storage,
-                constants: contract.constants(),
+                mod_: &module,
+                arg: Value::Unit,
+                entrypoint_path: &None,
+                initial_storage: Box::from(storage),
+                constants: &constants,
                 source: transaction.source.clone(),
-                sender: transaction
-                    .sender
-                    .unwrap_or_else(|| transaction.source.clone()),
-                self_addr: address.address.clone(),
+                sender,
+                self_addr: address.clone(),
+                operation_hash: transaction.operation_raw_hash.clone(),
+                origination_nonce: context.origination_nonce,
+                migration: Some(idx),
+                views: Some(unsafe { Views::new(&mut context.state) }),
+                trace: trace.clone(),
+                profile: profile.clone(),
                 gas_limit,
             };
-            let self_addr = address.clone();
-            match invoke_managed(invoke_payload) {
+            let result = invoke_managed(invoke_payload);
+            if let Some(trace) = trace {
+                context.trace(trace.take());
+            }
+            if let Some(profile) = profile {
+                context.profile(profile.take());
+            }
+            match result {
                 Ok(ExecutionResult {
                     new_storage,
-                    ops,
                     remaining_gas,
+                    origination_nonce,
+                    ..
                 }) => {
                     gas_limit = remaining_gas;
+                    context.origination_nonce = origination_nonce;
                     let mut to_return = vec![];
-                    let serialized_storage = Box::from(new_storage.from_runtime_ticket(
+                    let new_storage = new_storage.from_runtime_ticket(
                         &mut context.ticket_table.as_ref().borrow_mut(),
+                        &address,
                         &mut to_return,
-                    )?);
-                    {
-                        let address = contract_addr_to_string(&address);
-                        context
-                            .io
-                            .write_with_fail(&ServerMessage::DepositTickets(TicketDeposit {
-                                address: &address,
-                                tickets: &to_return,
-                            }))
-                            .map_err(|x| VmError::RuntimeErr(x.to_string()))?;
-                        to_return.clear();
-                    };
-                    contract.set_storage(serialized_storage);
-                    let msg = SetBorrowed {
-                        key: &address.address,
-                        value: &Cow::Borrowed(&contract),
-                    };
-                    let msg = &ServerMessage::Set(msg);
-                    match context.io.write_with_fail(msg) {
-                        Ok(()) => (),
-                        Err(_) => {
-                            context
-                                .io
-                                .write(&ServerMessage::Error("failed to set".to_owned()));
-                            return Err(VmError::RuntimeErr("cant talk to host".to_owned()));
-                        }
-                    };
-                    context.state.set(address.address.clone(), contract);
-                    match *ops {
-                        Value::List(l, _) if !l.is_empty() => {
-                            let res: VMResult<Vec<Transaction>> = l
-                                .into_iter()
-                                .map(|trans| match trans {
-                                    Value::Pair { fst, snd } => {
-                                        let self_addr = self_addr.clone();
-
-                                        let address = *fst;
-                                        let contract_addr = match address {
-                                            Value::String(s) => Ok(s),
-                                            _ => Err(VmError::RuntimeErr(
-                                                "bad transaction format from additional operations"
-                                                    .to_owned(),
-                                            )),
-                                        }?;
-
-                                        let content = snd.from_runtime_ticket(
-                                            &mut context.ticket_table.as_ref().borrow_mut(),
-                                            &mut to_return,
-                                        )?;
-
-                                        match contract_addr.starts_with("DK1") {
-                                            true => {
-                                                let extra =
-                                                    contract_addr.split_once('%').map_or_else(
-                                                        || ContractAddress {
-                                                            address: contract_addr.clone(),
-                                                            entrypoint: None,
-                                                        },
-                                                        |(address, entrypoint)| ContractAddress {
-                                                            address: address.to_owned(),
-                                                            entrypoint: Some(entrypoint.to_owned()),
-                                                        },
-                                                    );
-                                                let operation = Operation::Invoke {
-                                                    address: extra,
-                                                    argument: FromOcamlV(content),
-                                                    gas_limit: remaining_gas,
-                                                };
-                                                let operation = serde_json::to_string(&operation)
-                                                    .map_err(|err| {
-                                                    VmError::RuntimeErr(err.to_string())
-                                                })?;
-                                                let transaction = Transaction {
-                                                    source: transaction.source.clone(),
-                                                    sender: Some(self_addr.address),
-                                                    operation,
-                                                    operation_raw_hash: transaction
-                                                        .operation_raw_hash
-                                                        .clone(),
-                                                    tickets: to_return.clone(),
-                                                };
-                                                to_return.clear();
-                                                Ok(transaction)
-                                            }
-                                            false => {
-                                                serde_json::to_string(&content).map_err(|err| {
-                                                    VmError::RuntimeErr(err.to_string())
-                                                })?;
-                                                let operation = Operation::Transfer {
-                                                    address: contract_addr,
-                                                    tickets: to_return.clone(),
-                                                };
-                                                let operation = serde_json::to_string(&operation)
-                                                    .map_err(|err| {
-                                                    VmError::RuntimeErr(err.to_string())
-                                                })?;
-                                                let transaction = Transaction {
-                                                    source: transaction.source.clone(),
-                                                    sender: Some(self_addr.address),
-                                                    operation,
-                                                    operation_raw_hash: transaction
-                                                        .operation_raw_hash
-                                                        .clone(),
-                                                    tickets: to_return.clone(),
-                                                };
-                                                to_return.clear();
-                                                Ok(transaction)
-                                            }
-                                        }
-                                    }
-                                    _ => Err(VmError::RuntimeErr(
-                                        "bad transaction format from additional operations"
-                                            .to_owned(),
-                                    )),
-                                })
-                                .collect();
-                            let res = res?;
-                            res.into_iter().try_for_each(|x| {
-                                let transaction = x;
-                                let new_gas =
-                                    handle_transaction(context, transaction, remaining_gas)?;
-                                gas_limit = new_gas;
-                                Ok::<(), VmError>(())
-                            })
-                        }
-                        _ => Ok(()),
-                    }?;
-                    Ok(gas_limit)
+                    )?;
+                    context.deposit_tickets(&address, &to_return)?;
+                    Box::from(new_storage)
                 }
                 Err(x) => {
-                    context.io.write(&ServerMessage::Error(x.to_string()));
-                    Err(VmError::RuntimeErr("Error_ocured".to_owned()))
+                    context.report(x.to_string());
+                    return Err(VmError::RuntimeErr("Error_ocured".to_owned()));
                 }
             }
         }
+    };
+    let parameter_type = parameter_type.or_else(|| previous.parameter_type.clone());
+    let storage_type = storage_type.or_else(|| previous.storage_type.clone());
+    typecheck(context, &storage_type, &storage)?;
+    let contract_type = ContractType::LigoContract(LigoContractState {
+        storage,
+        module: Some(Box::from(module)),
+        code_hash,
+        constants,
+        entrypoints,
+        source,
+        parameter_type,
+        storage_type,
+        views,
+        ..previous.clone()
+    });
+    context.set_contract(&address, &contract_type)?;
+    context.undo.push(Undo::Contract(
+        address.clone(),
+        Box::new(ContractType::LigoContract(previous)),
+    ));
+    context.state.set(address, contract_type);
+    Ok(gas_limit)
+}
+fn typecheck(context: &mut ExecutionState, ty: &Option<Type>, value: &Value) -> VMResult<()> {
+    match ty.as_ref().map(|ty| ty.typecheck(value)) {
+        Some(Err(err)) => {
+            context.report(err.to_string());
+            Err(err)
+        }
+        _ => Ok(()),
+    }
+}
+fn check_address_free(context: &mut ExecutionState, address: &str) -> VMResult<()> {
+    if context.state.contains(address) {
+        context.report(format!("contract already exists {}", address));
+        return Err(VmError::RuntimeErr("Error_ocured".to_owned()));
+    }
+    Ok(())
+}
+enum Pending {
+    Transaction(Transaction),
+    Originate {
+        address: String,
+        code_hash: String,
+        storage: Value,
+        tickets: Vec<(TicketId, usize)>,
+    },
+}
+fn make_pending(
+    op: ManagedOperation,
+    ticket_table: &mut TicketTable,
+    events: &mut Vec<ContractEvent>,
+    source: &str,
+    operation_raw_hash: &str,
+    self_addr: &str,
+    remaining_gas: u64,
+) -> VMResult<Option<Pending>> {
+    let mut to_return = vec![];
+    match op {
+        ManagedOperation::Transfer { param, destination } => {
+            let content = param.from_runtime_ticket(ticket_table, &destination, &mut to_return)?;
+            let operation = match destination.starts_with("DK1") {
+                true => {
+                    let address = destination.split_once('%').map_or_else(
+                        || ContractAddress {
+                            address: destination.clone(),
+                            entrypoint: None,
+                        },
+                        |(address, entrypoint)| ContractAddress {
+                            address: address.to_owned(),
+                            entrypoint: Some(entrypoint.to_owned()),
+                        },
+                    );
+                    Operation::Invoke {
+                        address,
+                        argument: FromOcamlV(content),
+                        gas_limit: remaining_gas,
+                    }
+                }
+                false => Operation::Transfer {
+                    address: destination,
+                    tickets: to_return.clone(),
+                },
+            };
+            let operation = serde_json::to_string(&operation)
+                .map_err(|err| VmError::RuntimeErr(err.to_string()))?;
+            Ok(Some(Pending::Transaction(Transaction {
+                source: source.to_owned(),
+                sender: Some(self_addr.to_owned()),
+                operation,
+                operation_raw_hash: operation_raw_hash.to_owned(),
+                tickets: to_return,
+                protocol_version: None,
+                trace: false,
+                profile: false,
+            })))
+        }
+        ManagedOperation::Originate {
+            address,
+            code_hash,
+            storage,
+        } => {
+            let storage = storage.from_runtime_ticket(ticket_table, &address, &mut to_return)?;
+            Ok(Some(Pending::Originate {
+                address,
+                code_hash,
+                storage,
+                tickets: to_return,
+            }))
+        }
+        ManagedOperation::Emit { tag, ty, payload } => {
+            // Checked before the tickets are moved out of the table, which
+            // records them as transferred.
+            if !is_portable(&payload) {
+                return Err(VmError::RuntimeErr(
+                    "tickets, lambdas and operations cannot be emitted in events".to_owned(),
+                ));
+            }
+            let payload = payload.from_runtime_ticket(ticket_table, self_addr, &mut to_return)?;
+            events.push(ContractEvent {
+                source: self_addr.to_owned(),
+                tag,
+                ty: ty.to_string(),
+                payload: FromOcamlV(payload),
+            });
+            Ok(None)
+        }
+    }
+}
+/// Originates a contract running the cached module with hash `code_hash`.
+/// It has no constants, entrypoints, types or views: only the module is
+/// known.
+fn handle_create_contract(
+    context: &mut ExecutionState,
+    address: String,
+    code_hash: String,
+    storage: Value,
+    originated_by: String,
+    tickets: &[(TicketId, usize)],
+) -> VMResult<()> {
+    check_address_free(context, &address)?;
+    let module = context
+        .state
+        .modules
+        .get(&code_hash)
+        .and_then(|module| {
+            module.ok_or_else(|| VmError::RuntimeErr(format!("no module with hash {}", code_hash)))
+        })
+        .inspect_err(|err| context.report(err.to_string()))?;
+    let contract_type = ContractType::LigoContract(LigoContractState {
+        self_: ContractAddress {
+            address: address.clone(),
+            entrypoint: None,
+        },
+        originated_by,
+        admin: None,
+        storage: Box::from(storage),
+        code_hash,
+        constants: vec![],
+        entrypoints: None,
+        source: None,
+        parameter_type: None,
+        storage_type: None,
+        views: vec![],
+        module: Some(Box::from(module)),
+    });
+    context.set_contract(&address, &contract_type)?;
+    context.state.set(address.clone(), contract_type);
+    context.undo.push(Undo::Storage(address.clone(), None));
+    context.deposit_tickets(&address, tickets)
+}
+pub fn contract_addr_to_string(c: &ContractAddress) -> String {
+    c.address.clone()
+}
+pub(crate) fn resolve_entrypoint(
+    contract: &ContractType,
+    address: &ContractAddress,
+    argument: &Value,
+) -> VMResult<Option<Vec<Path>>> {
+    let path = match &address.entrypoint {
+        None => None,
+        Some(entrypoint) => {
+            let path = contract
+                .entrypoints()
+                .as_ref()
+                .and_then(|map| map.get(&format!("%{}", entrypoint)));
+            match path {
+                Some(path) => Some(path.clone()),
+                None if entrypoint == "default" => None,
+                None => {
+                    return Err(VmError::RuntimeErr(format!(
+                        "unknown entrypoint %{} of {}",
+                        entrypoint, address.address
+                    )))
+                }
+            }
+        }
+    };
+    if let Some(ty) = contract.parameter_type() {
+        let ty = match &path {
+            Some(path) => ty.at_path(path).ok_or_else(|| {
+                VmError::TypeErr(format!(
+                    "entrypoint %{} does not match the parameter type",
+                    address.entrypoint.as_deref().unwrap_or_default()
+                ))
+            })?,
+            None => ty,
+        };
+        ty.typecheck(argument)?;
+    }
+    Ok(path)
+}
+fn handle_invoke(
+    context: &mut ExecutionState,
+    transaction: Transaction,
+    address: ContractAddress,
+    argument: Value,
+    gas_limit: u64,
+    tickets: FnvHashSet<Ticket>,
+) -> VMResult<u64> {
+    match context.state.get(&address.address) {
+        Some(mut contract) => {
+            // The contract is out of the table while it runs, and goes back
+            // whether it succeeds or not, before its operations run.
+            let result = run_contract(
+                context,
+                &transaction,
+                &address,
+                argument,
+                gas_limit,
+                &tickets,
+                &mut contract,
+            );
+            context.state.set(address.address.clone(), contract);
+            let (mut gas_limit, ops) = result?;
+            let pending = match ops {
+                Value::List(l, _) => l
+                    .into_iter()
+                    .map(|op| match op {
+                        Value::Operation(op) => {
+                            let mut ticket_table = context.ticket_table.as_ref().borrow_mut();
+                            make_pending(
+                                op,
+                                &mut ticket_table,
+                                &mut context.events,
+                                &transaction.source,
+                                &transaction.operation_raw_hash,
+                                &address.address,
+                                gas_limit,
+                            )
+                        }
+                        _ => Err(VmError::RuntimeErr(
+                            "bad transaction format from additional operations".to_owned(),
+                        )),
+                    })
+                    .collect::<VMResult<Vec<Option<Pending>>>>()
+                    .inspect_err(|err| context.report(err.to_string()))?,
+                _ => vec![],
+            };
+            pending.into_iter().flatten().try_for_each(|x| {
+                match x {
+                    // Operations run one after the other on what is
+                    // left of the gas of the invocation.
+                    Pending::Transaction(transaction) => {
+                        gas_limit = handle_transaction(context, transaction, gas_limit)?;
+                    }
+                    Pending::Originate {
+                        address: originated,
+                        code_hash,
+                        storage,
+                        tickets,
+                    } => {
+                        handle_create_contract(
+                            context,
+                            originated,
+                            code_hash,
+                            storage,
+                            address.address.clone(),
+                            &tickets,
+                        )?;
+                    }
+                }
+                Ok::<(), VmError>(())
+            })?;
+            Ok(gas_limit)
+        }
         None => {
-            context.io.write(&ServerMessage::Error(format!(
+            context.report(format!(
                 "contract doesnt exist {}",
                 serde_json::to_string(&address).expect("cant happen")
-            )));
+            ));
             Err(VmError::RuntimeErr("Error_ocured".to_owned()))
         }
     }
 }
+
+/// Runs `main` of `contract` and stores what it returns, giving back the gas
+/// left and the operations it emitted.
+fn run_contract(
+    context: &mut ExecutionState,
+    transaction: &Transaction,
+    address: &ContractAddress,
+    argument: Value,
+    gas_limit: u64,
+    tickets: &FnvHashSet<Ticket>,
+    contract: &mut ContractType,
+) -> VMResult<(u64, Value)> {
+    let entrypoint_path = match resolve_entrypoint(contract, address, &argument) {
+        Ok(path) => path,
+        Err(err) => {
+            context.report(err.to_string());
+            return Err(err);
+        }
+    };
+    let sender = transaction
+        .sender
+        .clone()
+        .unwrap_or_else(|| transaction.source.clone());
+    let arg = argument.to_runtime_ticket(&mut context.ticket_table.as_ref().borrow_mut(), &sender);
+
+    let storage = Box::from(contract.storage().clone().to_runtime_ticket(
+        &mut context.ticket_table.as_ref().borrow_mut(),
+        &address.address,
+    ));
+    check_ticket_ownership(context, tickets)?;
+    contract.init(&mut context.state.modules)?;
+    context.undo.push(Undo::Storage(
+        address.address.clone(),
+        Some(contract.storage().clone()),
+    ));
+    let trace = transaction.trace.then(TraceSink::default);
+    let profile = transaction.profile.then(ProfileSink::default);
+    let invoke_payload = InvokeManaged {
+        table: Rc::clone(&context.ticket_table),
+        mod_: contract.module().as_ref().unwrap().as_ref(),
+        arg,
+        entrypoint_path: &entrypoint_path,
+        initial_storage: storage,
+        constants: contract.constants(),
+        source: transaction.source.clone(),
+        sender,
+        self_addr: address.address.clone(),
+        operation_hash: transaction.operation_raw_hash.clone(),
+        origination_nonce: context.origination_nonce,
+        migration: None,
+        // The contract is out of the table and, like the state, untouched
+        // until the invocation returns.
+        views: Some(unsafe { Views::new(&mut context.state).running(&address.address, contract) }),
+        trace: trace.clone(),
+        profile: profile.clone(),
+        gas_limit,
+    };
+    let result = invoke_managed(invoke_payload);
+    if let Some(trace) = trace {
+        context.trace(trace.take());
+    }
+    if let Some(profile) = profile {
+        context.profile(profile.take());
+    }
+    let ExecutionResult {
+        new_storage,
+        ops,
+        remaining_gas,
+        origination_nonce,
+    } = result.map_err(|err| {
+        context.report(err.to_string());
+        VmError::RuntimeErr("Error_ocured".to_owned())
+    })?;
+    context.origination_nonce = origination_nonce;
+    let mut to_return = vec![];
+    let serialized_storage = Box::from(new_storage.from_runtime_ticket(
+        &mut context.ticket_table.as_ref().borrow_mut(),
+        &address.address,
+        &mut to_return,
+    )?);
+    typecheck(context, contract.storage_type(), &serialized_storage)?;
+    context.deposit_tickets(&contract_addr_to_string(address), &to_return)?;
+    context.update_storage(&address.address, contract.storage(), &serialized_storage)?;
+    contract.set_storage(serialized_storage);
+    Ok((remaining_gas, *ops))
+}
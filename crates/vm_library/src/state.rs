@@ -1,15 +1,23 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Bound,
+};
+
 use fnv::FnvHashMap;
 use serde::{Deserialize, Serialize};
 use wasmer::Module;
 
 use crate::{
-    compile_store,
     contract_address::ContractAddress,
     errors::{vm::VmError, VMResult},
-    managed::value::Value,
+    managed::{types::Type, value::Value},
+    module_cache::ModuleCache,
     outgoing::{Init, InitVec, SetOwned},
     path::Path,
+    ticket_table::TicketId,
 };
+/// Amounts of tickets held by one address.
+pub type Holdings = Vec<(TicketId, usize)>;
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LigoCode {
     type_: String,
@@ -19,12 +27,22 @@ pub struct LigoCode {
 pub struct LigoContractState {
     pub self_: ContractAddress,
     pub originated_by: String,
+    #[serde(default)]
+    pub admin: Option<String>,
     pub storage: Box<Value>,
-    #[serde(with = "serde_bytes")]
-    pub serialized_module: Vec<u8>,
+    /// Hash of the wasm binary, under which the module is found in the
+    /// `ModuleCache`.
+    pub code_hash: String,
     pub constants: Vec<(u32, Value)>,
     pub entrypoints: Option<FnvHashMap<String, Vec<Path>>>,
     pub source: Option<LigoCode>,
+    #[serde(default)]
+    pub parameter_type: Option<Type>,
+    #[serde(default)]
+    pub storage_type: Option<Type>,
+    /// Names of the views the module exports as `view_<name>`.
+    #[serde(default)]
+    pub views: Vec<String>,
     #[serde(skip_deserializing, skip_serializing)]
     pub module: Option<Box<Module>>,
 }
@@ -53,19 +71,40 @@ impl ContractType {
             Self::LigoContract(l) => &l.entrypoints,
         }
     }
+    pub fn parameter_type(&self) -> &Option<Type> {
+        match self {
+            Self::LigoContract(l) => &l.parameter_type,
+        }
+    }
+    pub fn storage_type(&self) -> &Option<Type> {
+        match self {
+            Self::LigoContract(l) => &l.storage_type,
+        }
+    }
+    pub fn views(&self) -> &[String] {
+        match self {
+            Self::LigoContract(l) => &l.views,
+        }
+    }
     pub fn storage(&self) -> &Value {
         match self {
             Self::LigoContract(l) => &l.storage,
         }
     }
-    pub fn init(&mut self) -> VMResult<()> {
+    pub fn code_hash(&self) -> &str {
+        match self {
+            Self::LigoContract(l) => &l.code_hash,
+        }
+    }
+    pub fn init(&mut self, modules: &mut ModuleCache) -> VMResult<()> {
         match self {
             Self::LigoContract(s) => match s.module {
                 None => {
-                    s.module = Some(Box::from(unsafe {
-                        Module::deserialize(&compile_store::new_headless(), &s.serialized_module)
-                    }?));
-                    Ok::<(), VmError>(())
+                    let module = modules.get(&s.code_hash)?.ok_or_else(|| {
+                        VmError::RuntimeErr(format!("no module with hash {}", s.code_hash))
+                    })?;
+                    s.module = Some(Box::from(module));
+                    Ok(())
                 }
                 Some(_) => Ok(()),
             },
@@ -83,16 +122,15 @@ impl Eq for ContractType {
     fn assert_receiver_is_total_eq(&self) {}
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Default)]
 pub struct State {
-    pub table: FnvHashMap<String, ContractType>,
-}
-impl Default for State {
-    fn default() -> Self {
-        Self {
-            table: FnvHashMap::with_capacity_and_hasher(1000, Default::default()),
-        }
-    }
+    /// Contracts by address, kept in order for exports and the state root.
+    pub table: BTreeMap<String, ContractType>,
+    pub modules: ModuleCache,
+    /// Tickets the node holds for each address, as far as the VM saw them
+    /// lent (`Take_tickets`) and deposited (`Deposit_tickets`), one amount
+    /// per ticket in ticket order. Addresses holding nothing are left out.
+    pub tickets: BTreeMap<String, Holdings>,
 }
 
 impl State {
@@ -108,11 +146,42 @@ impl State {
             x.set_storage(Box::from(value.unwrap()))
         }
     }
+    pub fn contains(&self, key: &str) -> bool {
+        self.table.contains_key(key)
+    }
     pub fn get(&mut self, key: &String) -> Option<ContractType> {
         self.table.remove(key)
     }
+    /// Sets the tickets held by `address`, returning those it held before.
+    pub fn set_tickets(&mut self, address: String, tickets: Holdings) -> Option<Holdings> {
+        let mut amounts: BTreeMap<TicketId, usize> = BTreeMap::new();
+        tickets
+            .into_iter()
+            .for_each(|(ticket, amount)| *amounts.entry(ticket).or_default() += amount);
+        amounts.retain(|_, amount| *amount > 0);
+        match amounts.is_empty() {
+            true => self.tickets.remove(&address),
+            false => self.tickets.insert(address, amounts.into_iter().collect()),
+        }
+    }
+    /// The node lends the VM every ticket `address` holds.
+    pub fn lend_tickets(&mut self, address: &str) -> Option<Holdings> {
+        self.tickets.remove(address)
+    }
+    /// Adds `tickets` to those `address` holds, returning those it held
+    /// before.
+    pub fn deposit_tickets(
+        &mut self,
+        address: &str,
+        tickets: &[(TicketId, usize)],
+    ) -> Option<Holdings> {
+        let mut holdings = self.tickets.get(address).cloned().unwrap_or_default();
+        holdings.extend_from_slice(tickets);
+        self.set_tickets(address.to_owned(), holdings)
+    }
     pub fn from_init(&mut self, init: Init) -> VMResult<()> {
         self.table.clear();
+        self.tickets.clear();
         init.0.iter().try_for_each(|(key, value)| {
             let contract_type: ContractType = serde_json::from_str(value)
                 .map_err(|err| VmError::DeserializeErr(err.to_string()))?;
@@ -121,7 +190,7 @@ impl State {
         })
     }
     pub fn to_init(&self) -> VMResult<InitVec> {
-        let acc = self
+        let acc: Vec<SetOwned> = self
             .table
             .iter()
             .map(|(contract_address, contract_type)| SetOwned {
@@ -131,4 +200,151 @@ impl State {
             .collect();
         Ok(InitVec(acc))
     }
+    /// Up to `limit` contracts in address order, starting after `after`,
+    /// together with the address to resume from if any remain.
+    pub fn export_chunk(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> (Vec<(&str, &ContractType)>, Option<String>) {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        let limit = limit.max(1);
+        let mut contracts = self
+            .table
+            .range::<str, _>((start, Bound::Unbounded))
+            .map(|(key, contract)| (key.as_str(), contract));
+        let chunk: Vec<_> = contracts.by_ref().take(limit).collect();
+        let next = match contracts.next() {
+            Some(_) => chunk.last().map(|(key, _)| (*key).to_owned()),
+            None => None,
+        };
+        (chunk, next)
+    }
+    /// The serialized modules of `contracts`, each once and in hash order.
+    pub fn modules_of<'a>(
+        &'a self,
+        contracts: impl IntoIterator<Item = &'a ContractType>,
+    ) -> Vec<(&'a str, &'a [u8])> {
+        let hashes: BTreeSet<&str> = contracts.into_iter().map(ContractType::code_hash).collect();
+        hashes
+            .into_iter()
+            .filter_map(|hash| Some((hash, self.modules.serialized(hash)?)))
+            .collect()
+    }
+    /// The part of a contract storage found by following `path`, without
+    /// taking the contract out of the table.
+    pub fn storage_at(&self, address: &str, path: &[Path]) -> VMResult<&Value> {
+        self.contract(address)?
+            .storage()
+            .at_path(path)
+            .ok_or_else(|| {
+                VmError::RuntimeErr(format!("no value at {:?} in storage of {}", path, address))
+            })
+    }
+    /// Looks `key` up in the `big_map_id`-th big_map of a contract storage,
+    /// big_maps being numbered from the left of its storage type.
+    pub fn big_map_value(
+        &self,
+        address: &str,
+        big_map_id: usize,
+        key: &Value,
+    ) -> VMResult<Option<&Value>> {
+        let contract = self.contract(address)?;
+        let missing = || VmError::RuntimeErr(format!("no big_map {} in {}", big_map_id, address));
+        let path = contract
+            .storage_type()
+            .as_ref()
+            .and_then(|ty| ty.big_map_path(big_map_id))
+            .ok_or_else(missing)?;
+        match contract.storage().at_path(&path) {
+            Some(Value::Map(map)) => Ok(map.get(key)),
+            _ => Err(missing()),
+        }
+    }
+    fn contract(&self, address: &str) -> VMResult<&ContractType> {
+        self.table
+            .get(address)
+            .ok_or_else(|| VmError::RuntimeErr(format!("contract doesnt exist {}", address)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im_rc::ordmap;
+
+    use crate::{fixtures::contract_state, managed::value::Value, path::Path};
+
+    use super::{ContractType, State};
+
+    fn contract(address: &str) -> ContractType {
+        ContractType::LigoContract(contract_state(address, Value::Unit))
+    }
+
+    #[test]
+    fn export_chunks_cover_the_state_in_order() {
+        let mut state = State::default();
+        let mut addresses: Vec<String> = (0..7).map(|idx| format!("DK1{}", 7 - idx)).collect();
+        for address in &addresses {
+            state.set(address.clone(), contract(address));
+        }
+        addresses.sort();
+
+        let mut exported = vec![];
+        let mut after = None;
+        loop {
+            let (chunk, next) = state.export_chunk(after.as_deref(), 3);
+            assert!(chunk.len() <= 3);
+            exported.extend(chunk.into_iter().map(|(key, _)| key.to_owned()));
+            match next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(exported, addresses);
+        let keys: Vec<_> = state
+            .to_init()
+            .unwrap()
+            .0
+            .into_iter()
+            .map(|set| set.key)
+            .collect();
+        assert_eq!(keys, addresses);
+    }
+
+    #[test]
+    fn reads_storage_and_big_maps_in_place() {
+        let ledger = Value::Map(ordmap! {Value::Int(1.into()) => Value::Int(10.into())});
+        let storage = Value::Pair {
+            fst: Box::from(Value::Int(5.into())),
+            snd: Box::from(ledger.clone()),
+        };
+        let mut contract = contract("DK1a");
+        let ContractType::LigoContract(state) = &mut contract;
+        state.storage = Box::from(storage.clone());
+        // As tunac prints it.
+        state.storage_type = Some("pair int (big_map int int)".parse().unwrap());
+        let mut state = State::default();
+        state.set("DK1a".to_owned(), contract);
+
+        assert_eq!(state.storage_at("DK1a", &[]).unwrap(), &storage);
+        assert_eq!(state.storage_at("DK1a", &[Path::Right]).unwrap(), &ledger);
+        assert!(state.storage_at("DK1a", &[Path::Left, Path::Left]).is_err());
+        assert!(state.storage_at("DK1b", &[]).is_err());
+        assert_eq!(
+            state
+                .big_map_value("DK1a", 0, &Value::Int(1.into()))
+                .unwrap(),
+            Some(&Value::Int(10.into()))
+        );
+        assert_eq!(
+            state
+                .big_map_value("DK1a", 0, &Value::Int(2.into()))
+                .unwrap(),
+            None
+        );
+        assert!(state
+            .big_map_value("DK1a", 1, &Value::Int(1.into()))
+            .is_err());
+        assert!(state.contains("DK1a"));
+    }
 }
@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    errors::{vm::VmError, VMResult},
+    managed::value::Value,
+    state::{Holdings, State},
+};
+
+pub type Hash = [u8; 32];
+
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+const TICKETS: u8 = 2;
+
+pub fn to_hex(hash: &Hash) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+fn from_hex(hex: &str) -> Option<Hash> {
+    let mut hash = [0; 32];
+    if hex.len() != 64 {
+        return None;
+    }
+    for (byte, pair) in hash.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// Storage is hashed in its bincode encoding, which is deterministic since
+/// maps and sets are ordered. Tickets a contract holds are part of it.
+pub fn storage_hash(storage: &Value) -> VMResult<Hash> {
+    let bytes = bincode::serialize(storage).map_err(|err| VmError::RuntimeErr(err.to_string()))?;
+    Ok(Sha256::digest(bytes).into())
+}
+
+fn leaf_hash(address: &str, code_hash: &str, storage_hash: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF]);
+    [address, code_hash].iter().for_each(|field| {
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field.as_bytes());
+    });
+    hasher.update(storage_hash);
+    hasher.finalize().into()
+}
+
+fn tickets_hash(address: &str, tickets: &Holdings) -> VMResult<Hash> {
+    let bytes = bincode::serialize(tickets).map_err(|err| VmError::RuntimeErr(err.to_string()))?;
+    let mut hasher = Sha256::new();
+    hasher.update([TICKETS]);
+    hasher.update((address.len() as u64).to_be_bytes());
+    hasher.update(address.as_bytes());
+    hasher.update(Sha256::digest(bytes));
+    Ok(hasher.finalize().into())
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sibling {
+    Left(String),
+    Right(String),
+}
+
+/// Shows that a contract with the given code and storage hashes is part of
+/// the state committed to by a root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub address: String,
+    pub code_hash: String,
+    pub storage_hash: String,
+    pub path: Vec<Sibling>,
+}
+impl InclusionProof {
+    pub fn verify(&self, root: &str) -> bool {
+        let Some(storage_hash) = from_hex(&self.storage_hash) else {
+            return false;
+        };
+        let leaf = leaf_hash(&self.address, &self.code_hash, &storage_hash);
+        let computed = self.path.iter().try_fold(leaf, |hash, sibling| {
+            let (left, right) = match sibling {
+                Sibling::Left(left) => (from_hex(left)?, hash),
+                Sibling::Right(right) => (hash, from_hex(right)?),
+            };
+            Some(node_hash(&left, &right))
+        });
+        computed.is_some_and(|computed| to_hex(&computed) == root)
+    }
+    pub fn verify_storage(&self, storage: &Value, root: &str) -> VMResult<bool> {
+        Ok(to_hex(&storage_hash(storage)?) == self.storage_hash && self.verify(root))
+    }
+}
+
+/// The levels of a binary Merkle tree over `leaves`, from the leaves up to
+/// the root. A node without a sibling is carried to the next level unchanged.
+fn tree_levels(leaves: Vec<Hash>) -> Vec<Vec<Hash>> {
+    let mut levels = vec![leaves];
+    while levels.last().is_some_and(|level| level.len() > 1) {
+        let next = levels
+            .last()
+            .expect("levels are never empty")
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+/// The root of a tree built by `tree_levels`, all zeroes if it has no leaves.
+fn top(levels: &[Vec<Hash>]) -> Hash {
+    levels
+        .last()
+        .and_then(|level| level.first())
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Commits to a `State` with two binary Merkle trees: one over its contracts
+/// and one over the tickets held by each address, both with one leaf per
+/// address in address order. The root is the node hashing the two; the root
+/// of an empty state is all zeroes.
+pub struct StateTree {
+    leaves: Vec<(String, String, Hash)>,
+    levels: Vec<Vec<Hash>>,
+    tickets: Hash,
+}
+impl StateTree {
+    pub fn new(state: &State) -> VMResult<Self> {
+        let leaves = state
+            .table
+            .iter()
+            .map(|(address, contract)| {
+                Ok((
+                    address.clone(),
+                    contract.code_hash().to_owned(),
+                    storage_hash(contract.storage())?,
+                ))
+            })
+            .collect::<VMResult<Vec<_>>>()?;
+        let levels = tree_levels(
+            leaves
+                .iter()
+                .map(|(address, code_hash, storage_hash)| {
+                    leaf_hash(address, code_hash, storage_hash)
+                })
+                .collect(),
+        );
+        let tickets = state
+            .tickets
+            .iter()
+            .map(|(address, tickets)| tickets_hash(address, tickets))
+            .collect::<VMResult<Vec<_>>>()?;
+        Ok(Self {
+            leaves,
+            levels,
+            tickets: top(&tree_levels(tickets)),
+        })
+    }
+    pub fn root(&self) -> Hash {
+        let contracts = top(&self.levels);
+        match contracts == Hash::default() && self.tickets == Hash::default() {
+            true => Hash::default(),
+            false => node_hash(&contracts, &self.tickets),
+        }
+    }
+    pub fn prove(&self, address: &str) -> Option<InclusionProof> {
+        let mut index = self
+            .leaves
+            .binary_search_by(|(leaf, ..)| leaf.as_str().cmp(address))
+            .ok()?;
+        let (address, code_hash, storage_hash) = &self.leaves[index];
+        let mut path = vec![];
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if let Some(hash) = level.get(sibling) {
+                path.push(match sibling < index {
+                    true => Sibling::Left(to_hex(hash)),
+                    false => Sibling::Right(to_hex(hash)),
+                });
+            }
+            index /= 2;
+        }
+        path.push(Sibling::Right(to_hex(&self.tickets)));
+        Some(InclusionProof {
+            address: address.clone(),
+            code_hash: code_hash.clone(),
+            storage_hash: to_hex(storage_hash),
+            path,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fixtures::contract_state,
+        managed::value::Value,
+        state::{ContractType, State},
+        ticket_table::TicketId,
+    };
+
+    use super::{to_hex, StateTree};
+
+    fn contract(address: &str, storage: i64) -> ContractType {
+        ContractType::LigoContract(contract_state(address, Value::Int(storage.into())))
+    }
+
+    fn state(contracts: &[(&str, i64)]) -> State {
+        let mut state = State::default();
+        contracts.iter().for_each(|(address, storage)| {
+            state.set(address.to_string(), contract(address, *storage));
+        });
+        state
+    }
+
+    #[test]
+    fn root_depends_on_contents_only() {
+        let first = state(&[("DK1a", 1), ("DK1b", 2), ("DK1c", 3)]);
+        let second = state(&[("DK1c", 3), ("DK1a", 1), ("DK1b", 2)]);
+        let changed = state(&[("DK1a", 1), ("DK1b", 2), ("DK1c", 4)]);
+        let root = StateTree::new(&first).unwrap().root();
+        assert_eq!(root, StateTree::new(&second).unwrap().root());
+        assert_ne!(root, StateTree::new(&changed).unwrap().root());
+        assert_eq!(StateTree::new(&State::default()).unwrap().root(), [0; 32]);
+    }
+
+    #[test]
+    fn proves_every_contract() {
+        let addresses = ["DK1a", "DK1b", "DK1c", "DK1d", "DK1e"];
+        (1..=addresses.len()).for_each(|count| {
+            let contracts: Vec<_> = addresses[..count]
+                .iter()
+                .enumerate()
+                .map(|(idx, address)| (*address, idx as i64))
+                .collect();
+            let tree = StateTree::new(&state(&contracts)).unwrap();
+            let root = to_hex(&tree.root());
+            contracts.iter().for_each(|(address, storage)| {
+                let proof = tree.prove(address).unwrap();
+                assert!(proof.verify(&root));
+                assert!(proof
+                    .verify_storage(&Value::Int((*storage).into()), &root)
+                    .unwrap());
+                assert!(!proof
+                    .verify_storage(&Value::Int((storage + 1).into()), &root)
+                    .unwrap());
+            });
+            assert!(tree.prove("DK1z").is_none());
+        });
+    }
+
+    #[test]
+    fn root_commits_to_the_tickets_held() {
+        let mut state = state(&[("DK1a", 1), ("DK1b", 2)]);
+        let contracts_only = StateTree::new(&state).unwrap().root();
+        let ticket = TicketId::new("DK1a".to_owned(), "data".to_owned());
+        state.deposit_tickets("tz1a", &[(ticket.clone(), 5)]);
+        let tree = StateTree::new(&state).unwrap();
+        let root = to_hex(&tree.root());
+        assert_ne!(tree.root(), contracts_only);
+        assert!(tree.prove("DK1a").unwrap().verify(&root));
+
+        // Amounts are summed, so depositing in two parts commits the same.
+        let mut split = self::state(&[("DK1a", 1), ("DK1b", 2)]);
+        split.deposit_tickets("tz1a", &[(ticket.clone(), 2), (ticket.clone(), 3)]);
+        assert_eq!(StateTree::new(&split).unwrap().root(), tree.root());
+        split.deposit_tickets("tz1a", &[(ticket, 1)]);
+        assert_ne!(StateTree::new(&split).unwrap().root(), tree.root());
+
+        state.lend_tickets("tz1a");
+        assert_eq!(StateTree::new(&state).unwrap().root(), contracts_only);
+        state.table.clear();
+        state.deposit_tickets(
+            "tz1a",
+            &[(TicketId::new("DK1a".to_owned(), "data".to_owned()), 5)],
+        );
+        assert_ne!(StateTree::new(&state).unwrap().root(), [0; 32]);
+    }
+
+    #[test]
+    fn proof_does_not_verify_against_another_root() {
+        let tree = StateTree::new(&state(&[("DK1a", 1), ("DK1b", 2)])).unwrap();
+        let other = StateTree::new(&state(&[("DK1a", 1), ("DK1b", 3)])).unwrap();
+        let mut proof = tree.prove("DK1a").unwrap();
+        assert!(!proof.verify(&to_hex(&other.root())));
+        proof.code_hash = "01".to_owned();
+        assert!(!proof.verify(&to_hex(&tree.root())));
+    }
+}
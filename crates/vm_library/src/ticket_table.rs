@@ -1,3 +1,4 @@
+use fnv::{FnvHashMap, FnvHashSet};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use thiserror::Error;
@@ -52,7 +53,7 @@ fn return_true() -> bool {
 pub struct Ticket {
     pub ticket_id: TicketId,
     pub amount: Amount,
-    #[serde(skip_deserializing, default = "return_true")]
+    #[serde(skip, default = "return_true")]
     live: bool,
 }
 
@@ -97,9 +98,53 @@ impl Ticket {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type_", content = "content")]
+pub enum TicketEvent {
+    Mint {
+        handle: Handle,
+        ticket_id: TicketId,
+        amount: Amount,
+    },
+    Split {
+        handle: Handle,
+        ticket_id: TicketId,
+        handles: (Handle, Handle),
+        amounts: (Amount, Amount),
+    },
+    Join {
+        handles: (Handle, Handle),
+        ticket_id: TicketId,
+        handle: Handle,
+        amount: Amount,
+    },
+    Read {
+        handle: Handle,
+        ticket_id: TicketId,
+        amount: Amount,
+        new_handle: Handle,
+    },
+    Transfer {
+        handle: Handle,
+        ticket_id: TicketId,
+        amount: Amount,
+        destination: Address,
+    },
+    Burn {
+        handle: Handle,
+        ticket_id: TicketId,
+        amount: Amount,
+    },
+}
+
 pub struct TicketTable {
     pub counter: Handle,
     pub table: Vec<Ticket>,
+    pub events: Vec<TicketEvent>,
+    transferred: FnvHashSet<Handle>,
+    /// Who holds the ticket behind each handle, when it is known.
+    owners: FnvHashMap<Handle, Address>,
+    receipt_start: Handle,
 }
 
 impl Default for TicketTable {
@@ -107,6 +152,10 @@ impl Default for TicketTable {
         Self {
             counter: 0,
             table: Vec::with_capacity(1000),
+            events: Vec::with_capacity(100),
+            transferred: FnvHashSet::default(),
+            owners: FnvHashMap::default(),
+            receipt_start: 0,
         }
     }
 }
@@ -122,6 +171,20 @@ impl TicketTable {
         self.table.push(ticket);
     }
 
+    /// Puts a ticket held by `owner` in the table.
+    pub fn hold(&mut self, ticket: Ticket, owner: &str) -> Handle {
+        let handle = self.incr();
+        self.merge(ticket);
+        self.owners.insert(handle, owner.to_owned());
+        handle
+    }
+
+    fn inherit(&mut self, from: &Handle, to: Handle) {
+        if let Some(owner) = self.owners.get(from).cloned() {
+            self.owners.insert(to, owner);
+        }
+    }
+
     pub fn unsafe_read(&mut self, handle: &Handle) -> Result<Ticket> {
         self.table.get_mut(*handle).map_or_else(
             || Err(Error::TicketDoesntExist),
@@ -148,22 +211,51 @@ impl TicketTable {
         )
     }
 
+    /// Hands the ticket to `destination`, recording it unless it already
+    /// holds the ticket, as a contract keeping a ticket in its storage does.
+    pub fn transfer(&mut self, handle: &Handle, destination: &str) -> Result<Ticket> {
+        let ticket = self.extract(handle)?;
+        self.transferred.insert(*handle);
+        if self.owners.get(handle).map(String::as_str) == Some(destination) {
+            return Result::Ok(ticket);
+        }
+        self.owners.insert(*handle, destination.to_owned());
+        self.events.push(TicketEvent::Transfer {
+            handle: *handle,
+            ticket_id: ticket.ticket_id.clone(),
+            amount: ticket.amount,
+            destination: destination.to_owned(),
+        });
+        Result::Ok(ticket)
+    }
+
     pub fn mint_ticket(&mut self, sender: Address, amount: Amount, data: String) -> Handle {
-        let ticket_id = TicketId::new(sender, data);
-        let ticket = Ticket::new(ticket_id, amount);
-        let handle = self.incr();
-        self.merge(ticket);
+        let ticket_id = TicketId::new(sender.clone(), data);
+        let ticket = Ticket::new(ticket_id.clone(), amount);
+        let handle = self.hold(ticket, &sender);
+        self.events.push(TicketEvent::Mint {
+            handle,
+            ticket_id,
+            amount,
+        });
         handle
     }
 
     pub fn read_ticket(&mut self, handle: &Handle) -> Result<(TicketId, Amount, Handle)> {
         let mut ticket = self.unsafe_read(handle)?;
-        let handle = self.incr();
+        let new_handle = self.incr();
         ticket.live = true;
         let amount = ticket.amount;
         let id = ticket.ticket_id.clone();
         self.merge(ticket);
-        let to_return = (id, amount, handle);
+        self.inherit(handle, new_handle);
+        self.events.push(TicketEvent::Read {
+            handle: *handle,
+            ticket_id: id.clone(),
+            amount,
+            new_handle,
+        });
+        let to_return = (id, amount, new_handle);
         Result::Ok(to_return)
     }
 
@@ -173,11 +265,20 @@ impl TicketTable {
         amounts: (Amount, Amount),
     ) -> Result<(Handle, Handle)> {
         let ticket = self.unsafe_read(handle)?;
+        let ticket_id = ticket.ticket_id.clone();
         let (t1, t2) = Ticket::split(ticket.ticket_id, ticket.amount, amounts)?;
         let handle_1 = self.incr();
         let handle_2 = self.incr();
         self.merge(t1);
         self.merge(t2);
+        self.inherit(handle, handle_1);
+        self.inherit(handle, handle_2);
+        self.events.push(TicketEvent::Split {
+            handle: *handle,
+            ticket_id,
+            handles: (handle_1, handle_2),
+            amounts,
+        });
         Result::Ok((handle_1, handle_2))
     }
 
@@ -186,14 +287,56 @@ impl TicketTable {
         let t2 = self.unsafe_read(handles.1)?;
         if t1.ticket_id == t2.ticket_id {
             let ticket = Ticket::join(t1, t2);
+            let ticket_id = ticket.ticket_id.clone();
+            let amount = ticket.amount;
             let handle = self.incr();
             self.merge(ticket);
+            if self.owners.get(handles.0) == self.owners.get(handles.1) {
+                self.inherit(handles.0, handle);
+            }
+            self.events.push(TicketEvent::Join {
+                handles: (*handles.0, *handles.1),
+                ticket_id,
+                handle,
+                amount,
+            });
             Result::Ok(handle)
         } else {
             Result::Err(Error::TicketOwnershipViolation)
         }
     }
 
+    /// Closes the event log of the current top-level transaction. Tickets
+    /// created since the previous receipt that are still live and were never
+    /// transferred have been dropped by the contracts and are reported as burned.
+    pub fn take_receipt(&mut self) -> Vec<TicketEvent> {
+        let start = self.receipt_start.min(self.table.len());
+        let burned: Vec<TicketEvent> = self.table[start..]
+            .iter()
+            .enumerate()
+            .map(|(idx, ticket)| (idx + start, ticket))
+            .filter(|(handle, ticket)| ticket.live && !self.transferred.contains(handle))
+            .map(|(handle, ticket)| TicketEvent::Burn {
+                handle,
+                ticket_id: ticket.ticket_id.clone(),
+                amount: ticket.amount,
+            })
+            .collect();
+        self.events.extend(burned);
+        self.transferred.clear();
+        self.receipt_start = self.counter;
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn reset(&mut self) {
+        self.table.clear();
+        self.counter = 0;
+        self.events.clear();
+        self.transferred.clear();
+        self.owners.clear();
+        self.receipt_start = 0;
+    }
+
     // pub fn finalize(&self) {
     //     let ticket_table = unsafe { &mut TICKETS };
     //     ticket_table.clear();
@@ -213,10 +356,7 @@ mod tests {
 
     #[test]
     fn read_after_split_fails() {
-        let mut ticket_table = TicketTable {
-            counter: 0,
-            table: vec![],
-        };
+        let mut ticket_table = TicketTable::default();
         let handle = ticket_table.mint_ticket(SENDER.to_owned(), 10, "".to_owned());
         let _ = ticket_table.split_ticket(&handle, (4, 6)).unwrap();
         assert_eq!(
@@ -227,10 +367,7 @@ mod tests {
 
     #[test]
     fn read_after_join_fails() {
-        let mut ticket_table = TicketTable {
-            counter: 0,
-            table: vec![],
-        };
+        let mut ticket_table = TicketTable::default();
         let h1 = ticket_table.mint_ticket(SENDER.to_owned(), 3, "".to_owned());
         let h2 = ticket_table.mint_ticket(SENDER.to_owned(), 6, "".to_owned());
 
@@ -244,10 +381,7 @@ mod tests {
 
     #[test]
     fn join_diff_keys_fails() {
-        let mut ticket_table = TicketTable {
-            counter: 0,
-            table: vec![],
-        };
+        let mut ticket_table = TicketTable::default();
         let h1 = ticket_table.mint_ticket(SENDER.to_owned(), 3, "1".to_owned());
         let h2 = ticket_table.mint_ticket(SENDER.to_owned(), 6, "2".to_owned());
 
@@ -257,6 +391,80 @@ mod tests {
         )
     }
 
+    #[test]
+    fn split_and_join_are_recorded() {
+        let mut ticket_table = TicketTable::default();
+        let h1 = ticket_table.mint_ticket(SENDER.to_owned(), 10, "".to_owned());
+        let (h2, h3) = ticket_table.split_ticket(&h1, (4, 6)).unwrap();
+        let h4 = ticket_table.join_tickets((&h2, &h3)).unwrap();
+        let ticket_id = TicketId::new(SENDER.to_owned(), "".to_owned());
+
+        assert_eq!(
+            ticket_table.events,
+            vec![
+                TicketEvent::Mint {
+                    handle: h1,
+                    ticket_id: ticket_id.clone(),
+                    amount: 10
+                },
+                TicketEvent::Split {
+                    handle: h1,
+                    ticket_id: ticket_id.clone(),
+                    handles: (h2, h3),
+                    amounts: (4, 6)
+                },
+                TicketEvent::Join {
+                    handles: (h2, h3),
+                    ticket_id,
+                    handle: h4,
+                    amount: 10
+                }
+            ]
+        )
+    }
+
+    #[test]
+    fn receipt_burns_dropped_tickets() {
+        let mut ticket_table = TicketTable::default();
+        let h1 = ticket_table.mint_ticket(SENDER.to_owned(), 3, "1".to_owned());
+        let h2 = ticket_table.mint_ticket(SENDER.to_owned(), 6, "2".to_owned());
+        ticket_table.transfer(&h1, "DK1destination").unwrap();
+
+        let receipt = ticket_table.take_receipt();
+        assert_eq!(
+            receipt.last(),
+            Some(&TicketEvent::Burn {
+                handle: h2,
+                ticket_id: TicketId::new(SENDER.to_owned(), "2".to_owned()),
+                amount: 6
+            })
+        );
+        assert_eq!(receipt.len(), 4);
+        assert!(ticket_table.take_receipt().is_empty())
+    }
+
+    #[test]
+    fn keeping_a_ticket_is_not_a_transfer() {
+        let mut ticket_table = TicketTable::default();
+        let ticket_id = TicketId::new(SENDER.to_owned(), "".to_owned());
+        let h1 = ticket_table.hold(Ticket::new(ticket_id.clone(), 10), "DK1holder");
+        let (h2, h3) = ticket_table.split_ticket(&h1, (4, 6)).unwrap();
+        ticket_table.transfer(&h2, "DK1holder").unwrap();
+        ticket_table.transfer(&h3, "DK1destination").unwrap();
+
+        let receipt = ticket_table.take_receipt();
+        assert_eq!(
+            receipt.last(),
+            Some(&TicketEvent::Transfer {
+                handle: h3,
+                ticket_id,
+                amount: 6,
+                destination: "DK1destination".to_owned()
+            })
+        );
+        assert_eq!(receipt.len(), 2);
+    }
+
     // #[test] // TODO: FIXME
     // fn finalize_excludes_dead_tickets() {
     //     let mut ticket_table = TicketTable {
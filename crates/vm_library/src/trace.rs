@@ -0,0 +1,224 @@
+use std::{cell::RefCell, rc::Rc};
+
+use serde::Serialize;
+use slotmap::{DefaultKey, KeyData};
+use wasmer::{Exports, Extern, Function, RuntimeError, Store, Type, Val};
+
+use crate::{
+    env::Context,
+    errors::VMResult,
+    managed::value::{FromOcamlV, Value},
+};
+
+/// A call contract code made to a host import.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostCall {
+    pub import: String,
+    /// The innermost wasm function entered from the host that the call was
+    /// made under: `main`, a migration, a closure run by an import or a view.
+    /// Calls between the functions of a module do not go through the host,
+    /// so a helper function of the contract shows as the one that called it.
+    pub function: String,
+    /// How many functions entered from the host were running, `function`
+    /// included, not the depth of the wasm call stack.
+    pub stack_height: usize,
+    pub arguments: Vec<serde_json::Value>,
+    /// `None` if the call failed.
+    pub result: Option<serde_json::Value>,
+    pub gas_before: u64,
+    pub gas_after: Option<u64>,
+}
+
+/// Called before every host call of a traced invocation, an error stops it.
+pub type Hook = Box<dyn FnMut(&HostCall) -> VMResult<()>>;
+
+/// The host calls made by an invocation, in order, and the error it ended
+/// with if any.
+#[derive(Default, Serialize)]
+pub struct Trace {
+    pub calls: Vec<HostCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    frames: Vec<String>,
+    #[serde(skip)]
+    hook: Option<Hook>,
+}
+
+pub type TraceSink = Rc<RefCell<Trace>>;
+
+impl std::fmt::Debug for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Trace")
+            .field("calls", &self.calls)
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Trace {
+    pub fn with_hook(hook: impl FnMut(&HostCall) -> VMResult<()> + 'static) -> Self {
+        Self {
+            hook: Some(Box::from(hook)),
+            ..Self::default()
+        }
+    }
+    pub(crate) fn enter(&mut self, function: String) {
+        self.frames.push(function)
+    }
+    pub(crate) fn leave(&mut self) {
+        self.frames.pop();
+    }
+}
+
+/// Wraps every function of `exports` into one recording its calls in the
+/// trace and the profile of `env` before forwarding them.
+pub(crate) fn instrument(exports: Exports, store: &Store, env: &Context) -> Exports {
+    let mut traced = Exports::new();
+    for (name, export) in exports.iter() {
+        match export {
+            Extern::Function(function) => {
+                let import = name.clone();
+                let function = function.clone();
+                let wrapper = Function::new_with_env(
+                    store,
+                    function.ty().clone(),
+                    env.clone(),
+                    move |env: &Context, args: &[Val]| {
+                        let idx = record(env, &import, args)?;
+                        let profile = env.observers().1;
+                        if let Some(profile) = &profile {
+                            let gas = env.get_gas_left();
+                            profile.borrow_mut().enter_import(&import, gas);
+                        }
+                        let results = forward(&function, args);
+                        if let Some(profile) = &profile {
+                            let gas = env.get_gas_left();
+                            profile.borrow_mut().leave(gas);
+                        }
+                        let results = results?;
+                        complete(env, idx, &results);
+                        Ok(results)
+                    },
+                );
+                traced.insert(name.clone(), wrapper);
+            }
+            other => traced.insert(name.clone(), other.clone()),
+        }
+    }
+    traced
+}
+
+fn record(env: &Context, import: &str, args: &[Val]) -> Result<Option<usize>, RuntimeError> {
+    let trace = match env.inner().borrow().trace.clone() {
+        Some(trace) => trace,
+        None => return Ok(None),
+    };
+    let gas_before = env.get_gas_left();
+    let arguments = args.iter().map(|arg| describe(env, arg)).collect();
+    let (idx, call, hook) = {
+        let mut trace = trace.borrow_mut();
+        let call = HostCall {
+            import: import.to_owned(),
+            function: trace.frames.last().cloned().unwrap_or_default(),
+            stack_height: trace.frames.len(),
+            arguments,
+            result: None,
+            gas_before,
+            gas_after: None,
+        };
+        trace.calls.push(call.clone());
+        (trace.calls.len() - 1, call, trace.hook.take())
+    };
+    // The hook runs with the trace released, it may well look at it.
+    if let Some(mut hook) = hook {
+        let result = hook(&call);
+        trace.borrow_mut().hook = Some(hook);
+        result?;
+    }
+    Ok(Some(idx))
+}
+
+fn complete(env: &Context, idx: Option<usize>, results: &[Val]) {
+    let trace = env.inner().borrow().trace.clone();
+    if let (Some(trace), Some(idx)) = (trace, idx) {
+        let result = match results {
+            [] => serde_json::Value::Null,
+            [result] => describe(env, result),
+            results => results.iter().map(|x| describe(env, x)).collect(),
+        };
+        let gas_after = env.get_gas_left();
+        let call = &mut trace.borrow_mut().calls[idx];
+        call.result = Some(result);
+        call.gas_after = Some(gas_after);
+    }
+}
+
+// i64s passed to and from imports are keys of the arena, the values they
+// point to are shown instead when they are still there.
+fn describe(env: &Context, value: &Val) -> serde_json::Value {
+    match value {
+        Val::I64(key) => env
+            .get_cloned(DefaultKey::from(KeyData::from_ffi(*key as u64)))
+            .map_or_else(|_| (*key).into(), describe_value),
+        Val::I32(n) => (*n).into(),
+        other => format!("{:?}", other).into(),
+    }
+}
+
+fn describe_value(value: Value) -> serde_json::Value {
+    serde_json::to_value(FromOcamlV(value.clone()))
+        .unwrap_or_else(|_| format!("{:?}", value).into())
+}
+
+// Host functions can only be called through their native signature.
+fn forward(function: &Function, args: &[Val]) -> Result<Vec<Val>, RuntimeError> {
+    use Type::{I32, I64};
+    let ty = function.ty();
+    Ok(match (ty.params(), ty.results(), args) {
+        ([], [I64], []) => vec![Val::I64(function.native::<(), i64>()?.call()?)],
+        ([I64], [I64], [Val::I64(a)]) => vec![Val::I64(function.native::<i64, i64>()?.call(*a)?)],
+        ([I64], [I32], [Val::I64(a)]) => vec![Val::I32(function.native::<i64, i32>()?.call(*a)?)],
+        ([I64], [], [Val::I64(a)]) => {
+            function.native::<i64, ()>()?.call(*a)?;
+            vec![]
+        }
+        ([I32], [I64], [Val::I32(a)]) => vec![Val::I64(function.native::<i32, i64>()?.call(*a)?)],
+        ([I64, I64], [I64], [Val::I64(a), Val::I64(b)]) => {
+            vec![Val::I64(
+                function.native::<(i64, i64), i64>()?.call(*a, *b)?,
+            )]
+        }
+        ([I64, I32], [I64], [Val::I64(a), Val::I32(b)]) => {
+            vec![Val::I64(
+                function.native::<(i64, i32), i64>()?.call(*a, *b)?,
+            )]
+        }
+        ([I64, I32], [], [Val::I64(a), Val::I32(b)]) => {
+            function.native::<(i64, i32), ()>()?.call(*a, *b)?;
+            vec![]
+        }
+        ([I32, I64], [I64], [Val::I32(a), Val::I64(b)]) => {
+            vec![Val::I64(
+                function.native::<(i32, i64), i64>()?.call(*a, *b)?,
+            )]
+        }
+        ([I64, I64, I64], [I64], [Val::I64(a), Val::I64(b), Val::I64(c)]) => {
+            vec![Val::I64(
+                function
+                    .native::<(i64, i64, i64), i64>()?
+                    .call(*a, *b, *c)?,
+            )]
+        }
+        ([I64, I64, I64], [], [Val::I64(a), Val::I64(b), Val::I64(c)]) => {
+            function.native::<(i64, i64, i64), ()>()?.call(*a, *b, *c)?;
+            vec![]
+        }
+        _ => {
+            return Err(RuntimeError::new(format!(
+                "cannot trace calls of type {}",
+                ty
+            )))
+        }
+    })
+}
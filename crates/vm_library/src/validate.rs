@@ -0,0 +1,492 @@
+use std::rc::Rc;
+
+use fnv::FnvHashMap;
+use once_cell::sync::Lazy;
+use thiserror::Error;
+use wasmer::{
+    wasmparser::{
+        self, ExternalKind, ImportSectionEntryType, MemoryType, Operator, Parser, Payload,
+        TypeOrFuncType,
+    },
+    Extern, FunctionType, Type,
+};
+
+use crate::{
+    compile_store::{new_headless, FeatureSet},
+    env::{Context, Inner},
+    errors::{vm::VmError, VMResult},
+    managed::imports,
+};
+
+/// Exports the runtime looks up on every contract instance.
+const REQUIRED_EXPORTS: [(&str, &[Type], &[Type]); 4] = [
+    ("push", &[Type::I64], &[]),
+    ("call_callback_unit", &[Type::I64, Type::I32], &[]),
+    ("call_callback", &[Type::I64, Type::I32], &[Type::I64]),
+    ("main", &[Type::I64], &[Type::I64]),
+];
+
+static ENV_IMPORTS: Lazy<FnvHashMap<String, FunctionType>> = Lazy::new(|| {
+    let env = Context::new(
+        Rc::default(),
+        Inner::new(vec![], 0, FnvHashMap::default(), vec![]),
+    );
+    imports::make_imports(&env, &new_headless())
+        .get_namespace_exports("env")
+        .map(|exports| {
+            exports
+                .iter()
+                .filter_map(|(name, export)| match export {
+                    Extern::Function(f) => Some((name.clone(), f.ty().clone())),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+});
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ModuleIssue {
+    #[error("malformed module: {0}")]
+    Malformed(String),
+    #[error("missing export {0}")]
+    MissingExport(String),
+    #[error("export {name} has type {actual}, expected {expected}")]
+    ExportSignature {
+        name: String,
+        expected: FunctionType,
+        actual: FunctionType,
+    },
+    #[error("import {module}.{name} is not from env")]
+    ForeignImport { module: String, name: String },
+    #[error("unknown import {0}")]
+    UnknownImport(String),
+    #[error("import {0} is not a function")]
+    NonFunctionImport(String),
+    #[error("import {name} has type {actual}, expected {expected}")]
+    ImportSignature {
+        name: String,
+        expected: FunctionType,
+        actual: FunctionType,
+    },
+    #[error("floating point used in {0}")]
+    Float(String),
+    #[error("SIMD used in {0}")]
+    Simd(String),
+    #[error("memory of {pages} pages exceeds the limit of {limit}")]
+    MemoryLimit { pages: u64, limit: u32 },
+    #[error("function {0} grows a memory without a maximum")]
+    UnboundedMemoryGrowth(u32),
+    #[error("start function {0} is not allowed")]
+    StartFunction(u32),
+}
+
+fn function_type(ty: &wasmparser::FuncType) -> FunctionType {
+    let convert = |ty: &wasmparser::Type| match ty {
+        wasmparser::Type::I32 => Some(Type::I32),
+        wasmparser::Type::I64 => Some(Type::I64),
+        wasmparser::Type::F32 => Some(Type::F32),
+        wasmparser::Type::F64 => Some(Type::F64),
+        wasmparser::Type::V128 => Some(Type::V128),
+        wasmparser::Type::FuncRef => Some(Type::FuncRef),
+        wasmparser::Type::ExternRef => Some(Type::ExternRef),
+        _ => None,
+    };
+    FunctionType::new(
+        ty.params.iter().filter_map(convert).collect::<Vec<_>>(),
+        ty.returns.iter().filter_map(convert).collect::<Vec<_>>(),
+    )
+}
+
+const fn is_float_type(ty: wasmparser::Type) -> bool {
+    matches!(ty, wasmparser::Type::F32 | wasmparser::Type::F64)
+}
+const fn is_simd_type(ty: wasmparser::Type) -> bool {
+    matches!(ty, wasmparser::Type::V128)
+}
+
+/// The type an operator gives to a block or a select, if it names one.
+const fn annotated_type(op: &Operator) -> Option<wasmparser::Type> {
+    match op {
+        Operator::Block { ty } | Operator::Loop { ty } | Operator::If { ty } => match ty {
+            TypeOrFuncType::Type(ty) => Some(*ty),
+            TypeOrFuncType::FuncType(_) => None,
+        },
+        Operator::TypedSelect { ty } => Some(*ty),
+        _ => None,
+    }
+}
+
+// A float or a v128 used by any instruction of a valid module was either
+// produced by one of the operators below, or comes from a local, parameter,
+// global, call or block whose type says so, which are checked separately.
+// Looking for these is enough to find every use of floats and SIMD.
+fn is_float_operator(op: &Operator) -> bool {
+    matches!(
+        op,
+        Operator::F32Load { .. }
+            | Operator::F64Load { .. }
+            | Operator::F32Const { .. }
+            | Operator::F64Const { .. }
+            | Operator::F32ConvertI32S
+            | Operator::F32ConvertI32U
+            | Operator::F32ConvertI64S
+            | Operator::F32ConvertI64U
+            | Operator::F64ConvertI32S
+            | Operator::F64ConvertI32U
+            | Operator::F64ConvertI64S
+            | Operator::F64ConvertI64U
+            | Operator::F32ReinterpretI32
+            | Operator::F64ReinterpretI64
+            | Operator::F32x4ExtractLane { .. }
+            | Operator::F64x2ExtractLane { .. }
+    ) || annotated_type(op).is_some_and(is_float_type)
+}
+fn is_simd_operator(op: &Operator) -> bool {
+    matches!(
+        op,
+        Operator::V128Load { .. }
+            | Operator::V128Load8x8S { .. }
+            | Operator::V128Load8x8U { .. }
+            | Operator::V128Load16x4S { .. }
+            | Operator::V128Load16x4U { .. }
+            | Operator::V128Load32x2S { .. }
+            | Operator::V128Load32x2U { .. }
+            | Operator::V128Load8Splat { .. }
+            | Operator::V128Load16Splat { .. }
+            | Operator::V128Load32Splat { .. }
+            | Operator::V128Load64Splat { .. }
+            | Operator::V128Load32Zero { .. }
+            | Operator::V128Load64Zero { .. }
+            | Operator::V128Const { .. }
+            | Operator::I8x16Splat
+            | Operator::I16x8Splat
+            | Operator::I32x4Splat
+            | Operator::I64x2Splat
+            | Operator::F32x4Splat
+            | Operator::F64x2Splat
+    ) || annotated_type(op).is_some_and(is_simd_type)
+}
+
+struct ModuleInfo<'a> {
+    features: &'a FeatureSet,
+    types: Vec<wasmparser::FuncType>,
+    imported_functions: Vec<u32>,
+    functions: Vec<u32>,
+    memories: Vec<MemoryType>,
+    bodies: usize,
+    exports: FnvHashMap<&'a str, (ExternalKind, u32)>,
+    issues: Vec<ModuleIssue>,
+}
+
+impl<'a> ModuleInfo<'a> {
+    fn new(features: &'a FeatureSet) -> Self {
+        Self {
+            features,
+            types: vec![],
+            imported_functions: vec![],
+            functions: vec![],
+            memories: vec![],
+            bodies: 0,
+            exports: FnvHashMap::default(),
+            issues: vec![],
+        }
+    }
+
+    /// Records a float or SIMD issue at `location` if any of `types`, or the
+    /// operators seen there, are disallowed by the feature set.
+    fn check_types(
+        &mut self,
+        location: impl Fn() -> String,
+        types: &[wasmparser::Type],
+        (float, simd): (bool, bool),
+    ) {
+        if !self.features.floats && (float || types.iter().any(|t| is_float_type(*t))) {
+            self.issues.push(ModuleIssue::Float(location()));
+        }
+        if !self.features.simd && (simd || types.iter().any(|t| is_simd_type(*t))) {
+            self.issues.push(ModuleIssue::Simd(location()));
+        }
+    }
+
+    fn check_memory(&mut self, memory: &MemoryType) {
+        let limit = self.features.max_memory_pages;
+        let pages = memory.maximum.unwrap_or(memory.initial).max(memory.initial);
+        if pages > u64::from(limit) {
+            self.issues.push(ModuleIssue::MemoryLimit { pages, limit });
+        }
+    }
+    fn type_of_function(&self, index: u32) -> Option<FunctionType> {
+        let index = index as usize;
+        let imported = self.imported_functions.len();
+        let ty = if index < imported {
+            self.imported_functions[index]
+        } else {
+            *self.functions.get(index - imported)?
+        };
+        self.types.get(ty as usize).map(function_type)
+    }
+
+    fn read(&mut self, payload: Payload<'a>) -> wasmparser::Result<()> {
+        match payload {
+            Payload::TypeSection(reader) => {
+                for ty in reader {
+                    if let wasmparser::TypeDef::Func(ty) = ty? {
+                        let index = self.types.len();
+                        let types = [&ty.params[..], &ty.returns[..]].concat();
+                        self.check_types(|| format!("type {}", index), &types, (false, false));
+                        self.types.push(ty);
+                    }
+                }
+            }
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    self.read_import(import?);
+                }
+            }
+            Payload::FunctionSection(reader) => {
+                for ty in reader {
+                    self.functions.push(ty?);
+                }
+            }
+            Payload::MemorySection(reader) => {
+                for memory in reader {
+                    let memory = memory?;
+                    self.check_memory(&memory);
+                    self.memories.push(memory);
+                }
+            }
+            Payload::GlobalSection(reader) => {
+                for (index, global) in reader.into_iter().enumerate() {
+                    let ty = global?.ty.content_type;
+                    self.check_types(|| format!("global {}", index), &[ty], (false, false));
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export?;
+                    self.exports
+                        .insert(export.field, (export.kind, export.index));
+                }
+            }
+            Payload::StartSection { func, .. } => {
+                self.issues.push(ModuleIssue::StartFunction(func));
+            }
+            Payload::CodeSectionStart { .. } => {}
+            Payload::CodeSectionEntry(body) => {
+                let index = (self.imported_functions.len() + self.bodies) as u32;
+                let mut locals = vec![];
+                for local in body.get_locals_reader()? {
+                    locals.push(local?.1);
+                }
+                let bounded = self.memories.iter().all(|m| m.maximum.is_some());
+                let (mut float, mut simd, mut grows) = (false, false, false);
+                for op in body.get_operators_reader()? {
+                    let op = op?;
+                    float |= is_float_operator(&op);
+                    simd |= is_simd_operator(&op);
+                    grows |= matches!(op, Operator::MemoryGrow { .. });
+                }
+                self.check_types(|| format!("function {}", index), &locals, (float, simd));
+                if grows && !bounded {
+                    self.issues.push(ModuleIssue::UnboundedMemoryGrowth(index));
+                }
+                self.bodies += 1;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn read_import(&mut self, import: wasmparser::Import) {
+        let name = import.field.unwrap_or_default().to_owned();
+        if let ImportSectionEntryType::Function(ty) = import.ty {
+            self.imported_functions.push(ty);
+        }
+        let ty = match (import.module, import.ty) {
+            ("env", ImportSectionEntryType::Function(ty)) => ty,
+            ("env", _) => {
+                self.issues.push(ModuleIssue::NonFunctionImport(name));
+                return;
+            }
+            (module, _) => {
+                self.issues.push(ModuleIssue::ForeignImport {
+                    module: module.to_owned(),
+                    name,
+                });
+                return;
+            }
+        };
+        match (ENV_IMPORTS.get(&name), self.types.get(ty as usize)) {
+            (None, _) => self.issues.push(ModuleIssue::UnknownImport(name)),
+            (Some(expected), Some(actual)) if *expected != function_type(actual) => {
+                self.issues.push(ModuleIssue::ImportSignature {
+                    name,
+                    expected: expected.clone(),
+                    actual: function_type(actual),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    fn check_exports(&mut self) {
+        for (name, params, results) in REQUIRED_EXPORTS {
+            let expected = FunctionType::new(params, results);
+            match self.exports.get(name) {
+                Some((ExternalKind::Function, index)) => match self.type_of_function(*index) {
+                    Some(actual) if actual != expected => {
+                        self.issues.push(ModuleIssue::ExportSignature {
+                            name: name.to_owned(),
+                            expected,
+                            actual,
+                        })
+                    }
+                    _ => {}
+                },
+                _ => self
+                    .issues
+                    .push(ModuleIssue::MissingExport(name.to_owned())),
+            }
+        }
+    }
+}
+
+/// Checks the interface of a contract module before it is compiled: the
+/// exports the runtime calls, the imports it provides and the features
+/// `features` forbids. Every issue found is reported in a single
+/// `VmError::InvalidModule`.
+pub fn validate_module(wasm: &[u8], features: &FeatureSet) -> VMResult<()> {
+    let mut info = ModuleInfo::new(features);
+    for payload in Parser::new(0).parse_all(wasm) {
+        if let Err(err) = payload.and_then(|payload| info.read(payload)) {
+            return Err(VmError::InvalidModule(vec![ModuleIssue::Malformed(
+                err.to_string(),
+            )]));
+        }
+    }
+    info.check_exports();
+    if info.issues.is_empty() {
+        Ok(())
+    } else {
+        Err(VmError::InvalidModule(info.issues))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::wat2wasm;
+
+    use super::{validate_module, ModuleIssue};
+    use crate::{compile_store::FeatureSet, errors::vm::VmError, fixtures::contract};
+
+    fn issues_for(wat: &str, features: &FeatureSet) -> Vec<ModuleIssue> {
+        match validate_module(&wat2wasm(wat.as_bytes()).unwrap(), features) {
+            Ok(()) => vec![],
+            Err(VmError::InvalidModule(issues)) => issues,
+            Err(err) => panic!("unexpected error {}", err),
+        }
+    }
+
+    fn issues(wat: &str) -> Vec<ModuleIssue> {
+        issues_for(wat, &FeatureSet::default())
+    }
+
+    #[test]
+    fn accepts_well_formed_modules() {
+        let wat = contract(
+            r#"(import "env" "pair" (func $pair (param i64 i64) (result i64)))
+                (memory 1 4)"#,
+            "(drop (memory.grow (i32.const 1))) (call $pair (local.get 0) (local.get 0))",
+        );
+        assert_eq!(issues(&wat), vec![]);
+    }
+
+    #[test]
+    fn reports_every_issue() {
+        let wat = r#"(module
+            (import "wasi" "fd_write" (func $write (param i32) (result i32)))
+            (import "env" "pair" (func $pair (param i64) (result i64)))
+            (import "env" "launch" (func $launch))
+            (memory 1)
+            (func $main (param i32) (result i64)
+                (drop (memory.grow (i32.const 1)))
+                (i64.trunc_f64_s (f64.const 1)))
+            (func $start)
+            (start $start)
+            (export "main" (func $main)))"#;
+        let issues = issues(wat);
+        let expected = [
+            "import wasi.fd_write is not from env",
+            "import pair has type [I64] -> [I64], expected [I64, I64] -> [I64]",
+            "unknown import launch",
+            "start function 4 is not allowed",
+            "floating point used in function 3",
+            "function 3 grows a memory without a maximum",
+            "missing export push",
+            "missing export call_callback_unit",
+            "missing export call_callback",
+            "export main has type [I32] -> [I64], expected [I64] -> [I64]",
+        ];
+        assert_eq!(
+            issues.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn finds_floats_and_simd_however_they_are_used() {
+        let function = |body: &str| contract("(memory 1 1)", &format!("{} (local.get 0)", body));
+        for body in [
+            "(drop (f64.load (i32.const 0)))",
+            "(drop (f32.convert_i64_s (local.get 0)))",
+            "(drop (f64.reinterpret_i64 (local.get 0)))",
+            "(drop (block (result f32) (unreachable)))",
+        ] {
+            assert_eq!(
+                issues(&function(body)),
+                [ModuleIssue::Float("function 0".to_owned())],
+                "{}",
+                body
+            );
+        }
+        for body in [
+            "(drop (v128.load (i32.const 0)))",
+            "(drop (v128.const i64x2 0 0))",
+            "(drop (i64x2.splat (local.get 0)))",
+        ] {
+            assert_eq!(
+                issues(&function(body)),
+                [ModuleIssue::Simd("function 0".to_owned())],
+                "{}",
+                body
+            );
+        }
+        assert_eq!(
+            issues(&function(
+                "(drop (i64.extend_i32_s (i32.wrap_i64 (local.get 0))))"
+            )),
+            []
+        );
+    }
+
+    #[test]
+    fn feature_set_follows_protocol_version() {
+        let wat = contract(
+            "(memory 300)",
+            "(drop (i32x4.splat (i32.const 1))) (drop (f32.const 1)) (local.get 0)",
+        );
+        assert_eq!(issues_for(&wat, &FeatureSet::for_protocol(0)), vec![]);
+        assert_eq!(
+            issues(&wat)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "memory of 300 pages exceeds the limit of 256",
+                "floating point used in function 0",
+                "SIMD used in function 0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use std::{cell::RefCell, ptr::NonNull, rc::Rc};
+
+use crate::{
+    errors::{vm::VmError, VMResult},
+    incoming::InvokeView,
+    instance::invoke_view,
+    managed::value::{Union, Value},
+    profile::ProfileSink,
+    state::{ContractType, State},
+    ticket_table::TicketTable,
+    trace::TraceSink,
+};
+
+/// Lets running contract code call views, its own included. It points to
+/// the `State` the running contract was taken out of, which nothing else may
+/// use until the invocation returns, and to that contract, whose views see
+/// the storage it started with.
+#[derive(Clone)]
+pub struct Views {
+    state: NonNull<State>,
+    running: Option<(NonNull<str>, NonNull<ContractType>)>,
+    call_depth: u32,
+    trace: Option<TraceSink>,
+    profile: Option<ProfileSink>,
+}
+impl Views {
+    /// # Safety
+    /// `state` must outlive every invocation given the returned value, and
+    /// must not be accessed by anything else while one of them runs.
+    pub unsafe fn new(state: &mut State) -> Self {
+        Self {
+            state: NonNull::from(state),
+            running: None,
+            call_depth: 0,
+            trace: None,
+            profile: None,
+        }
+    }
+    /// The same views, also serving those of `contract`, which runs at
+    /// `address` and is out of the state while it does.
+    ///
+    /// # Safety
+    /// `address` and `contract` must outlive every invocation given the
+    /// returned value, and must not change while one of them runs.
+    pub unsafe fn running(self, address: &str, contract: &ContractType) -> Self {
+        Self {
+            running: Some((NonNull::from(address), NonNull::from(contract))),
+            ..self
+        }
+    }
+    /// The same views, called from code running `call_depth` calls deep.
+    pub fn at_depth(self, call_depth: u32) -> Self {
+        Self { call_depth, ..self }
+    }
+    /// The same views, recording their host calls and gas in the trace and
+    /// the profile of the code calling them.
+    pub fn observed(self, trace: Option<TraceSink>, profile: Option<ProfileSink>) -> Self {
+        Self {
+            trace,
+            profile,
+            ..self
+        }
+    }
+    /// Runs the view `name` of the contract at `address` with at most
+    /// `gas_limit` gas, returning its result and the gas left, or `None` if
+    /// there is no such contract or view.
+    pub fn run(
+        &self,
+        address: &str,
+        name: &str,
+        argument: Value,
+        source: String,
+        sender: String,
+        gas_limit: u64,
+    ) -> VMResult<Option<(Value, u64)>> {
+        if !is_portable(&argument) {
+            return Err(VmError::RuntimeErr(
+                "views cannot take tickets, lambdas or operations".to_owned(),
+            ));
+        }
+        let has_view = |contract: &ContractType| contract.views().iter().any(|view| view == name);
+        let (module, storage, constants) = {
+            let running = self
+                .running
+                .map(|(running, contract)| unsafe { (running.as_ref(), contract.as_ref()) })
+                .filter(|(running, _)| *running == address);
+            // The borrow ends before any contract code runs, so a view
+            // calling further views never finds the state borrowed.
+            let contract: &ContractType = match running {
+                // Its module was loaded before it started.
+                Some((_, contract)) if has_view(contract) => contract,
+                Some(_) => return Ok(None),
+                None => {
+                    let state = unsafe { &mut *self.state.as_ptr() };
+                    match state.table.get_mut(address) {
+                        Some(contract) if has_view(contract) => {
+                            contract.init(&mut state.modules)?;
+                            contract
+                        }
+                        _ => return Ok(None),
+                    }
+                }
+            };
+            let module = contract
+                .module()
+                .as_ref()
+                .map(|module| (**module).clone())
+                .ok_or_else(|| VmError::InstantiationErr("module missing".to_owned()))?;
+            (
+                module,
+                contract.storage().clone(),
+                contract.constants().to_vec(),
+            )
+        };
+        // Views cannot move tickets, those of the storage are only lent.
+        let table = Rc::new(RefCell::new(TicketTable::default()));
+        let storage = storage.to_runtime_ticket(&mut table.as_ref().borrow_mut(), address);
+        let (result, remaining_gas) = invoke_view(InvokeView {
+            table,
+            mod_: &module,
+            name,
+            arg: argument,
+            storage,
+            constants: &constants,
+            source,
+            sender,
+            self_addr: address.to_owned(),
+            views: Some(self.clone()),
+            call_depth: self.call_depth,
+            trace: self.trace.clone(),
+            profile: self.profile.clone(),
+            gas_limit,
+        })?;
+        if !is_portable(&result) {
+            return Err(VmError::RuntimeErr(format!(
+                "view {} of {} returned a ticket, lambda or operation",
+                name, address
+            )));
+        }
+        Ok(Some((result, remaining_gas)))
+    }
+}
+
+/// Runs the view `name` of the contract at `address` outside of any
+/// transaction, returning its result and the gas left.
+pub fn run_view(
+    state: &mut State,
+    address: &str,
+    name: &str,
+    argument: Value,
+    source: String,
+    gas_limit: u64,
+) -> VMResult<(Value, u64)> {
+    // `state` stays mutably borrowed until the view returns.
+    let views = unsafe { Views::new(state) };
+    views
+        .run(address, name, argument, source.clone(), source, gas_limit)?
+        .ok_or_else(|| VmError::RuntimeErr(format!("no view {} in {}", name, address)))
+}
+
+// Tickets, lambdas and operations only mean something to the instance that
+// made them.
+pub(crate) fn is_portable(value: &Value) -> bool {
+    match value {
+        Value::Pair { fst, snd } => is_portable(fst) && is_portable(snd),
+        Value::Union(Union::Left(value) | Union::Right(value)) => is_portable(value),
+        Value::Option(value) => value.as_deref().is_none_or(is_portable),
+        Value::List(values, _) => values.iter().all(is_portable),
+        Value::Set(values) => values.iter().all(is_portable),
+        Value::Map(map) => map.iter().all(|(k, v)| is_portable(k) && is_portable(v)),
+        Value::Ticket(_)
+        | Value::RuntimeTicket(_)
+        | Value::Closure { .. }
+        | Value::Operation(_) => false,
+        Value::Bytes(_) | Value::String(_) | Value::Int(_) | Value::Bool(_) | Value::Unit => true,
+    }
+}
@@ -3,8 +3,8 @@ use serde::{de::Visitor, ser::SerializeTuple, Deserialize, Serialize};
 
 use crate::{
     contract_address::ContractAddress,
-    managed::value::FromOcamlV,
-    outgoing::{Init, SetOwned},
+    managed::{types::Type, value::FromOcamlV},
+    outgoing::{Init, ModuleOwned, SetOwned},
     path::Path,
     state::LigoCode,
     ticket_table::TicketId,
@@ -13,13 +13,8 @@ use crate::{
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(tag = "type_", content = "content")]
 pub enum Operation {
-    Originate {
-        module_: String,
-        constants: Vec<(u32, FromOcamlV)>,
-        initial_storage: FromOcamlV,
-        entrypoints: Option<FnvHashMap<String, Vec<Path>>>,
-        source: Option<LigoCode>,
-    },
+    Originate(Originate),
+    Upgrade(Upgrade),
     Invoke {
         address: ContractAddress,
         argument: FromOcamlV,
@@ -30,11 +25,68 @@ pub enum Operation {
         address: String,
         tickets: Vec<(TicketId, usize)>,
     },
+    Batch {
+        operations: Vec<Operation>,
+        #[serde(default = "def")]
+        gas_limit: u64,
+    },
+}
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Originate {
+    pub module_: String,
+    pub constants: Vec<(u32, FromOcamlV)>,
+    pub initial_storage: FromOcamlV,
+    pub entrypoints: Option<FnvHashMap<String, Vec<Path>>>,
+    pub source: Option<LigoCode>,
+    #[serde(default)]
+    pub admin: Option<String>,
+    #[serde(default, with = "michelson_type")]
+    pub parameter_type: Option<Type>,
+    #[serde(default, with = "michelson_type")]
+    pub storage_type: Option<Type>,
+    #[serde(default)]
+    pub views: Vec<String>,
+}
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Upgrade {
+    pub address: String,
+    pub module_: String,
+    pub constants: Vec<(u32, FromOcamlV)>,
+    pub entrypoints: Option<FnvHashMap<String, Vec<Path>>>,
+    pub source: Option<LigoCode>,
+    #[serde(default, with = "michelson_type")]
+    pub parameter_type: Option<Type>,
+    #[serde(default, with = "michelson_type")]
+    pub storage_type: Option<Type>,
+    #[serde(default)]
+    pub views: Vec<String>,
+    #[serde(default)]
+    pub migration: Option<i32>,
+    #[serde(default = "def")]
+    pub gas_limit: u64,
 }
 fn def() -> u64 {
     u64::MAX
 }
-#[derive(Debug, Deserialize, Serialize)]
+/// Types are sent as Michelson, the way tunac prints them, e.g.
+/// `pair nat (option address)`.
+mod michelson_type {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::managed::types::Type;
+
+    pub fn serialize<S: Serializer>(ty: &Option<Type>, serializer: S) -> Result<S::Ok, S::Error> {
+        ty.as_ref().map(Type::to_string).serialize(serializer)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Type>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|ty| ty.parse().map_err(D::Error::custom))
+            .transpose()
+    }
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Transaction {
     pub source: String,
     #[serde(default)]
@@ -42,16 +94,88 @@ pub struct Transaction {
     pub operation: String,
     pub operation_raw_hash: String,
     pub tickets: Vec<(TicketId, usize)>,
+    /// Selects the wasm feature set for this transaction and, if it succeeds,
+    /// later ones.
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+    /// Records the host calls of the contract it invokes or migrates, see
+    /// `Trace`.
+    #[serde(default)]
+    pub trace: bool,
+    /// Reports where the gas of the contract it invokes or migrates went, see
+    /// `Profile`.
+    #[serde(default)]
+    pub profile: bool,
+}
+/// What the node knows about the chain when a block starts.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BlockContext {
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+    /// Tickets held by each address at the start of the block, answering
+    /// every `Take_tickets` the block would otherwise send.
+    #[serde(default)]
+    pub tickets: Vec<(String, Vec<(TicketId, usize)>)>,
+}
+/// Asks for the contracts following `after` in address order, at most
+/// `limit` of them.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportCursor {
+    #[serde(default)]
+    pub after: Option<String>,
+    pub limit: usize,
+}
+/// Reads the storage of `address`, or the part of it found by following
+/// `path` through pairs.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StorageQuery {
+    pub address: String,
+    #[serde(default)]
+    pub path: Vec<Path>,
+}
+/// Looks `key` up in the `big_map_id`-th big_map of the storage of
+/// `address`, counting from the left of its storage type.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BigMapQuery {
+    pub address: String,
+    pub big_map_id: usize,
+    pub key: FromOcamlV,
+}
+/// Runs the view `view` of the contract at `address` outside of any
+/// transaction, on behalf of `source` or else of the contract itself.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ViewQuery {
+    pub address: String,
+    pub view: String,
+    pub argument: FromOcamlV,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default = "def")]
+    pub gas_limit: u64,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Block {
+    pub transactions: Vec<Transaction>,
+    #[serde(default)]
+    pub context: BlockContext,
 }
 #[derive(Debug)]
 pub enum ClientMessage {
     Transaction(Transaction),
+    Block(Box<Block>),
     NoopTransaction,
-    Set(SetOwned),
+    Set(Box<SetOwned>),
+    SetModule(ModuleOwned),
     GetInitialState,
     SetInitialState(Init),
     Get(ContractAddress),
     GiveTickets(Vec<(TicketId, usize)>),
+    GetStateRoot,
+    GetStorageProof(String),
+    ExportState(ExportCursor),
+    GetStorage(StorageQuery),
+    GetBigMapValue(Box<BigMapQuery>),
+    RunView(Box<ViewQuery>),
 }
 struct ClientVisitor;
 impl<'de> Visitor<'de> for ClientVisitor {
@@ -80,7 +204,19 @@ impl<'de> Visitor<'de> for ClientVisitor {
                                 &"value",
                             ))
                         },
-                        |x| Ok(ClientMessage::Set(x)),
+                        |x| Ok(ClientMessage::Set(Box::new(x))),
+                    )
+                }
+                "Set_module" => {
+                    let elem: Option<ModuleOwned> = seq.next_element()?;
+                    elem.map_or_else(
+                        || {
+                            Err(serde::de::Error::invalid_type(
+                                serde::de::Unexpected::Str("unexpected sequence, set module"),
+                                &"value",
+                            ))
+                        },
+                        |x| Ok(ClientMessage::SetModule(x)),
                     )
                 }
                 "Give_Tickets" => {
@@ -96,6 +232,69 @@ impl<'de> Visitor<'de> for ClientVisitor {
                     )
                 }
                 "Get_Initial_State" => Ok(ClientMessage::GetInitialState),
+                "Get_State_Root" => Ok(ClientMessage::GetStateRoot),
+                "Get_Storage" => {
+                    let elem: Option<StorageQuery> = seq.next_element()?;
+                    elem.map_or_else(
+                        || {
+                            Err(serde::de::Error::invalid_type(
+                                serde::de::Unexpected::Str("unexpected sequence, get storage"),
+                                &"value",
+                            ))
+                        },
+                        |x| Ok(ClientMessage::GetStorage(x)),
+                    )
+                }
+                "Get_Big_Map_Value" => {
+                    let elem: Option<BigMapQuery> = seq.next_element()?;
+                    elem.map_or_else(
+                        || {
+                            Err(serde::de::Error::invalid_type(
+                                serde::de::Unexpected::Str(
+                                    "unexpected sequence, get big map value",
+                                ),
+                                &"value",
+                            ))
+                        },
+                        |x| Ok(ClientMessage::GetBigMapValue(Box::new(x))),
+                    )
+                }
+                "Run_View" => {
+                    let elem: Option<ViewQuery> = seq.next_element()?;
+                    elem.map_or_else(
+                        || {
+                            Err(serde::de::Error::invalid_type(
+                                serde::de::Unexpected::Str("unexpected sequence, run view"),
+                                &"value",
+                            ))
+                        },
+                        |x| Ok(ClientMessage::RunView(Box::new(x))),
+                    )
+                }
+                "Export_State" => {
+                    let elem: Option<ExportCursor> = seq.next_element()?;
+                    elem.map_or_else(
+                        || {
+                            Err(serde::de::Error::invalid_type(
+                                serde::de::Unexpected::Str("unexpected sequence, export state"),
+                                &"value",
+                            ))
+                        },
+                        |x| Ok(ClientMessage::ExportState(x)),
+                    )
+                }
+                "Get_Storage_Proof" => {
+                    let elem: Option<String> = seq.next_element()?;
+                    elem.map_or_else(
+                        || {
+                            Err(serde::de::Error::invalid_type(
+                                serde::de::Unexpected::Str("unexpected sequence, storage proof"),
+                                &"value",
+                            ))
+                        },
+                        |x| Ok(ClientMessage::GetStorageProof(x)),
+                    )
+                }
                 "Noop_transaction" => Ok(ClientMessage::NoopTransaction),
                 "Transaction" => {
                     let elem: Option<Transaction> = seq.next_element()?;
@@ -109,6 +308,18 @@ impl<'de> Visitor<'de> for ClientVisitor {
                         |x| Ok(ClientMessage::Transaction(x)),
                     )
                 }
+                "Block" => {
+                    let elem: Option<Block> = seq.next_element()?;
+                    elem.map_or_else(
+                        || {
+                            Err(serde::de::Error::invalid_type(
+                                serde::de::Unexpected::Str("unexpected sequence, block"),
+                                &"value",
+                            ))
+                        },
+                        |x| Ok(ClientMessage::Block(Box::new(x))),
+                    )
+                }
                 "Get" => {
                     let elem: Option<ContractAddress> = seq.next_element()?;
                     elem.map_or_else(
@@ -163,6 +374,12 @@ impl Serialize for ClientMessage {
                 seq.serialize_element(x)?;
                 seq.end()
             }
+            ClientMessage::SetModule(x) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Set_module")?;
+                seq.serialize_element(x)?;
+                seq.end()
+            }
             ClientMessage::Get(x) => {
                 let mut seq = serializer.serialize_tuple(2)?;
                 seq.serialize_element("Get")?;
@@ -188,7 +405,120 @@ impl Serialize for ClientMessage {
                 seq.serialize_element(s)?;
                 seq.end()
             }
+            ClientMessage::Block(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Block")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ClientMessage::ExportState(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Export_State")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ClientMessage::GetStorage(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Get_Storage")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ClientMessage::GetBigMapValue(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Get_Big_Map_Value")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ClientMessage::RunView(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Run_View")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ClientMessage::GetStateRoot => {
+                let mut seq = serializer.serialize_tuple(1)?;
+                seq.serialize_element("Get_State_Root")?;
+                seq.end()
+            }
+            ClientMessage::GetStorageProof(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Get_Storage_Proof")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
             ClientMessage::NoopTransaction => serializer.serialize_str("Noop_transaction"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientMessage, Operation};
+    use crate::managed::types::Type;
+
+    #[test]
+    fn originate_reads_michelson_types() {
+        let json = r#"{"type_":"Originate","content":{"module_":"","constants":[],
+            "initial_storage":["Int","0"],"entrypoints":null,"source":null,
+            "parameter_type":"or (or int int) unit","storage_type":"int"}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Operation::Originate(originate) => {
+                let parameter = Type::Or(
+                    Box::from(Type::Or(Box::from(Type::Int), Box::from(Type::Int))),
+                    Box::from(Type::Unit),
+                );
+                assert_eq!(originate.parameter_type, Some(parameter));
+                assert_eq!(originate.storage_type, Some(Type::Int));
+            }
+            x => panic!("expected originate, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn batch_deserializes_in_order() {
+        let json = r#"{"type_":"Batch","content":{"operations":[
+            {"type_":"Transfer","content":{"address":"tz1gvF4cD2dDtqitL3ZTraggSR1Mju2BKFEM","tickets":[]}},
+            {"type_":"Invoke","content":{"address":"DK1WiBZHtvv3EczaN628DkNob4cayHzTEDNK%transfer","argument":["Unit"]}}
+        ],"gas_limit":500}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Operation::Batch {
+                operations,
+                gas_limit,
+            } => {
+                assert_eq!(gas_limit, 500);
+                assert!(matches!(operations[0], Operation::Transfer { .. }));
+                match &operations[1] {
+                    Operation::Invoke {
+                        address, gas_limit, ..
+                    } => {
+                        assert_eq!(address.entrypoint.as_deref(), Some("transfer"));
+                        assert_eq!(*gas_limit, u64::MAX);
+                    }
+                    x => panic!("expected invoke, got {:?}", x),
+                }
+            }
+            x => panic!("expected batch, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn block_deserializes_with_default_context() {
+        let json = r#"["Block",{"transactions":[
+            {"source":"tz1gvF4cD2dDtqitL3ZTraggSR1Mju2BKFEM","operation":"{}","operation_raw_hash":"op1","tickets":[]},
+            {"source":"tz1gvF4cD2dDtqitL3ZTraggSR1Mju2BKFEM","operation":"{}","operation_raw_hash":"op2","tickets":[]}
+        ]}]"#;
+        match serde_json::from_str(json).unwrap() {
+            ClientMessage::Block(block) => {
+                let hashes: Vec<_> = block
+                    .transactions
+                    .iter()
+                    .map(|transaction| transaction.operation_raw_hash.as_str())
+                    .collect();
+                assert_eq!(hashes, ["op1", "op2"]);
+                assert_eq!(block.context.protocol_version, None);
+                assert!(block.context.tickets.is_empty());
+            }
+            x => panic!("expected block, got {:?}", x),
+        }
+    }
+}
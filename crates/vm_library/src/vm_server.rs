@@ -1,8 +1,12 @@
-use serde::{ser::SerializeTuple, Serialize};
+use serde::{ser::SerializeTuple, Deserialize, Serialize};
 
 use crate::{
-    outgoing::{InitVec, SetBorrowed},
-    ticket_table::TicketId,
+    managed::{diff::StorageDiff, value::FromOcamlV},
+    outgoing::{InitVec, ModuleBorrowed, SetBorrowed},
+    profile::Profile,
+    state_root::InclusionProof,
+    ticket_table::{TicketEvent, TicketId},
+    trace::Trace,
 };
 #[derive(Serialize)]
 pub struct TicketDeposit<'a> {
@@ -10,13 +14,94 @@ pub struct TicketDeposit<'a> {
     pub tickets: &'a [(TicketId, usize)],
 }
 
+/// An event published by a contract through `EMIT`, in emission order.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ContractEvent {
+    pub source: String,
+    pub tag: String,
+    /// The Michelson type of `payload`, as declared by the contract.
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub payload: FromOcamlV,
+}
+
+/// The outcome of one transaction of a block, in block order.
+#[derive(Serialize, Debug)]
+pub struct TransactionReceipt {
+    pub tickets: Vec<TicketEvent>,
+    pub events: Vec<ContractEvent>,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub traces: Vec<Trace>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<Profile>,
+}
+
+/// Everything a block changed: the receipts of its transactions, the final
+/// value of every contract they set, and the final holdings of every address
+/// whose tickets were taken or deposited.
+#[derive(Serialize)]
+pub struct BlockReceipt<'a> {
+    pub receipts: Vec<TransactionReceipt>,
+    pub state: Vec<SetBorrowed<'a>>,
+    /// Modules compiled by the block, which the node has not seen yet.
+    pub modules: Vec<ModuleBorrowed<'a>>,
+    pub tickets: Vec<(&'a str, &'a [(TicketId, usize)])>,
+}
+
+/// One page of an exported state; `next` is the cursor for the following
+/// page, absent once every contract was sent.
+#[derive(Serialize)]
+pub struct StateChunk<'a> {
+    pub contracts: Vec<SetBorrowed<'a>>,
+    /// The modules of these contracts.
+    pub modules: Vec<ModuleBorrowed<'a>>,
+    pub next: Option<String>,
+}
+
+#[derive(Serialize)]
+pub enum StorageChange {
+    #[serde(rename = "storage")]
+    Storage(FromOcamlV),
+    #[serde(rename = "storage_diff")]
+    StorageDiff(StorageDiff),
+}
+
+/// A contract whose storage, and nothing else, changed.
+#[derive(Serialize)]
+pub struct StorageUpdate<'a> {
+    pub address: &'a str,
+    #[serde(flatten)]
+    pub change: StorageChange,
+}
+
+#[derive(Serialize)]
+pub struct ViewResult {
+    pub value: FromOcamlV,
+    pub remaining_gas: u64,
+}
+
 pub enum ServerMessage<'a> {
     Init(InitVec),
     Stop,
     Set(SetBorrowed<'a>),
+    SetModule(ModuleBorrowed<'a>),
+    StorageUpdate(StorageUpdate<'a>),
     TakeTickets(&'a str),
     DepositTickets(TicketDeposit<'a>),
     Error(String),
+    Receipt(&'a [TicketEvent]),
+    Events(&'a [ContractEvent]),
+    Block(BlockReceipt<'a>),
+    StateRoot(String),
+    Contract(Option<SetBorrowed<'a>>),
+    StateChunk(StateChunk<'a>),
+    StorageProof(Option<InclusionProof>),
+    Storage(FromOcamlV),
+    BigMapValue(Option<FromOcamlV>),
+    ViewResult(ViewResult),
+    Trace(&'a Trace),
+    Profile(&'a Profile),
 }
 impl<'a> Serialize for ServerMessage<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -41,6 +126,18 @@ impl<'a> Serialize for ServerMessage<'a> {
                 seq.serialize_element(s)?;
                 seq.end()
             }
+            ServerMessage::SetModule(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Set_module")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::StorageUpdate(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Storage_update")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
             ServerMessage::TakeTickets(s) => {
                 let mut seq = serializer.serialize_tuple(2)?;
                 seq.serialize_element("Take_tickets")?;
@@ -59,6 +156,78 @@ impl<'a> Serialize for ServerMessage<'a> {
                 seq.serialize_element(s)?;
                 seq.end()
             }
+            ServerMessage::Receipt(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Receipt")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::Events(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Events")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::Block(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Block")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::Contract(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Contract")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::StateChunk(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("State_chunk")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::StateRoot(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("State_root")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::Storage(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Storage")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::BigMapValue(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Big_map_value")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::ViewResult(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("View_result")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::Trace(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Trace")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::Profile(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Profile")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
+            ServerMessage::StorageProof(s) => {
+                let mut seq = serializer.serialize_tuple(2)?;
+                seq.serialize_element("Storage_proof")?;
+                seq.serialize_element(s)?;
+                seq.end()
+            }
         }
     }
 }
@@ -46,6 +46,12 @@ pub fn create_incoming_managed<'a>(
         source: "tz1gvF4cD2dDtqitL3ZTraggSR1Mju2BKFEM".to_string(),
         sender: "tz1gvF4cD2dDtqitL3ZTraggSR1Mju2BKFEM".to_string(),
         self_addr: "KT1WiBZHtvv3EczaN628DkNob4cayHzTEDNK".to_string(),
+        operation_hash: "opCAkifFMh1Ya2J4WhRHskaXc297ELtx32wnc2WzeNtdQHp7DW4".to_string(),
+        origination_nonce: 0,
+        migration: None,
+        views: None,
+        trace: None,
+        profile: None,
         gas_limit: u64::MAX,
         table,
     }
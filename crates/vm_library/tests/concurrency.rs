@@ -0,0 +1,47 @@
+use std::{cell::RefCell, rc::Rc, thread};
+
+use vm_library::{
+    execution_result::ExecutionResult, instance::invoke_managed, managed::value::Value,
+    ticket_table::TicketTable,
+};
+
+mod common;
+
+// Stores the pair of its parameter and constant 0, built by a callback.
+const MODULE: &str = "\n(module\n  (import \"env\" \"car\" (func $car (param i64) (result i64)))\n(import \"env\" \"const\" (func $const (param i32) (result i64)))\n(import \"env\" \"nil\" (func $nil (result i64)))\n(import \"env\" \"pair\" (func $pair (param i64 i64) (result i64)))\n  (type $callback_t (func (param i64) (result i64)))\n  (table $closures 1 funcref)\n  (elem (i32.const 0) $tag)\n  (func $push (param $value i64))\n  (func $call_callback (param $arg1 i64) (param $idx i32) (result i64)\n    (call_indirect (type $callback_t) (local.get $arg1) (local.get $idx)))\n  (func $call_callback_unit (param $arg1 i64) (param $idx i32))\n  (func $tag (param $storage i64) (result i64)\n    (call $pair (local.get $storage) (call $const (i32.const 0))))\n  (func $main (param $v1 i64) (result i64)\n    (call $pair (call $nil) (call $call_callback (call $car (local.get $v1)) (i32.const 0))))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main)))\n";
+
+#[test]
+fn instances_run_on_parallel_threads() {
+    let handles: Vec<_> = (0..4)
+        .map(|n| {
+            thread::spawn(move || {
+                let payload = serde_json::json!({
+                    "module_": MODULE,
+                    "constants": [[0, ["String", format!("thread {}", n)]]]
+                });
+                let (init, module) = common::deser(payload.to_string());
+                for i in 0..50 {
+                    let invocation = common::create_incoming_managed(
+                        &module,
+                        &init,
+                        Value::Int(i.into()),
+                        Value::Unit,
+                        &None,
+                        Rc::new(RefCell::new(TicketTable::default())),
+                    );
+                    let ExecutionResult { new_storage, .. } = invoke_managed(invocation).unwrap();
+                    assert_eq!(
+                        *new_storage,
+                        Value::Pair {
+                            fst: Box::from(Value::Int(i.into())),
+                            snd: Box::from(Value::String(format!("thread {}", n))),
+                        }
+                    );
+                }
+            })
+        })
+        .collect();
+    handles
+        .into_iter()
+        .for_each(|handle| handle.join().unwrap());
+}
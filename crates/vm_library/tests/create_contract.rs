@@ -0,0 +1,86 @@
+use std::{cell::RefCell, rc::Rc};
+
+use vm_library::{
+    contract_address::ContractAddress,
+    execution_result::ExecutionResult,
+    instance::invoke_managed,
+    managed::value::{Operation, Value},
+    ticket_table::TicketTable,
+};
+
+mod common;
+
+const PAYLOAD: &str = r#"  {
+      "module_": "\n(module\n  (import \"env\" \"const\" (func $const (param i32) (result i64)))\n(import \"env\" \"create_contract\" (func $create_contract (param i64 i64 i64) (result i64)))\n(import \"env\" \"nil\" (func $nil (result i64)))\n(import \"env\" \"pair\" (func $pair (param i64 i64) (result i64)))\n(import \"env\" \"zero\" (func $zero (result i64)))\n  (func $push (param $value i64))\n  (func $call_callback (param $arg i64) (param $idx i32) (result i64) (local.get $arg))\n  (func $call_callback_unit (param $arg i64) (param $idx i32))\n  (func $main (param $v1 i64) (result i64)\n    (call $pair (call $nil) (call $pair (call $create_contract (call $const (i32.const 0)) (call $const (i32.const 1)) (call $zero)) (call $create_contract (call $const (i32.const 0)) (call $const (i32.const 1)) (call $zero)))))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main)))\n",
+      "constants": [[0, ["String", "9c1185a5c5e9fc54612808977ee8f548b2258d31"]], [1, ["Int", "7"]]]
+  }"#;
+
+fn originated(nonce: u32) -> Value {
+    let address = ContractAddress::originated(
+        b"opCAkifFMh1Ya2J4WhRHskaXc297ELtx32wnc2WzeNtdQHp7DW4",
+        nonce,
+    )
+    .address;
+    let op = Value::Operation(Operation::Originate {
+        address: address.clone(),
+        code_hash: "9c1185a5c5e9fc54612808977ee8f548b2258d31".to_owned(),
+        storage: Box::from(Value::Int(7.into())),
+    });
+    Value::Pair {
+        fst: Box::from(op),
+        snd: Box::from(Value::String(address)),
+    }
+}
+
+#[test]
+fn create_contract() {
+    let (init, module) = common::deser(PAYLOAD.to_owned());
+    let init = common::create_incoming_managed(
+        &module,
+        &init,
+        Value::Unit,
+        Value::Unit,
+        &None,
+        Rc::new(RefCell::new(TicketTable::default())),
+    );
+    let ExecutionResult {
+        new_storage,
+        origination_nonce,
+        ..
+    } = invoke_managed(init).unwrap();
+    assert_eq!(
+        *new_storage,
+        Value::Pair {
+            fst: Box::from(originated(0)),
+            snd: Box::from(originated(1)),
+        }
+    );
+    assert_eq!(origination_nonce, 2);
+}
+
+#[test]
+fn create_contract_continues_batch_nonce() {
+    let (init, module) = common::deser(PAYLOAD.to_owned());
+    let mut init = common::create_incoming_managed(
+        &module,
+        &init,
+        Value::Unit,
+        Value::Unit,
+        &None,
+        Rc::new(RefCell::new(TicketTable::default())),
+    );
+    init.origination_nonce = 3;
+    let ExecutionResult {
+        new_storage,
+        origination_nonce,
+        ..
+    } = invoke_managed(init).unwrap();
+    assert_eq!(
+        *new_storage,
+        Value::Pair {
+            fst: Box::from(originated(3)),
+            snd: Box::from(originated(4)),
+        }
+    );
+    assert_eq!(origination_nonce, 5);
+}
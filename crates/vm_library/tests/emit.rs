@@ -0,0 +1,58 @@
+use std::{cell::RefCell, rc::Rc};
+
+use vm_library::{
+    errors::VMResult,
+    execution_result::ExecutionResult,
+    instance::invoke_managed,
+    managed::{
+        types::Type,
+        value::{Operation, Value},
+    },
+    ticket_table::TicketTable,
+};
+
+mod common;
+
+const CONTRACT: &str = r#"  {
+      "module_": "\n(module\n  (import \"env\" \"car\" (func $car (param i64) (result i64)))\n(import \"env\" \"cons\" (func $cons (param i64 i64) (result i64)))\n(import \"env\" \"const\" (func $const (param i32) (result i64)))\n(import \"env\" \"emit\" (func $emit (param i64 i64 i64) (result i64)))\n(import \"env\" \"nil\" (func $nil (result i64)))\n(import \"env\" \"pair\" (func $pair (param i64 i64) (result i64)))\n(import \"env\" \"unit\" (func $unit (result i64)))\n  (func $push (param $value i64))\n  (func $call_callback (param $arg i64) (param $idx i32) (result i64) (local.get $arg))\n  (func $call_callback_unit (param $arg i64) (param $idx i32))\n  (func $main (param $v1 i64) (result i64)\n    (call $pair (call $cons (call $emit (call $const (i32.const 0)) (call $const (i32.const 1)) (call $car (local.get $v1))) (call $nil)) (call $unit)))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main)))\n",
+      "constants": [[0, ["String", "transfer_done"]], [1, ["String", "nat"]]]
+  }"#;
+
+fn emit(payload: Value) -> VMResult<ExecutionResult> {
+    let (init, module) = common::deser(CONTRACT.to_owned());
+    let init = common::create_incoming_managed(
+        &module,
+        &init,
+        payload,
+        Value::Unit,
+        &None,
+        Rc::new(RefCell::new(TicketTable::default())),
+    );
+    invoke_managed(init)
+}
+
+#[test]
+fn emits_a_typed_event() {
+    let ExecutionResult {
+        new_storage, ops, ..
+    } = emit(Value::Int(5.into())).unwrap();
+    assert_eq!(*new_storage, Value::Unit);
+    assert_eq!(
+        *ops,
+        Value::List(
+            vec![Value::Operation(Operation::Emit {
+                tag: "transfer_done".to_owned(),
+                ty: Type::Nat,
+                payload: Box::from(Value::Int(5.into())),
+            })]
+            .into(),
+            None
+        )
+    );
+}
+
+#[test]
+fn rejects_a_payload_of_another_type() {
+    assert!(emit(Value::Int((-5).into())).is_err());
+    assert!(emit(Value::String("five".to_owned())).is_err());
+}
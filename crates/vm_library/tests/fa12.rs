@@ -3,7 +3,7 @@ use std::{cell::RefCell, rc::Rc};
 use vm_library::{
     execution_result::ExecutionResult,
     instance::invoke_managed,
-    managed::value::{FromOcamlV, Union, Value},
+    managed::value::{FromOcamlV, Operation, Union, Value},
     ticket_table::TicketTable,
 };
 
@@ -43,13 +43,11 @@ fn get_balance() {
         new_storage, ops, ..
     } = invoke_managed(init).unwrap();
     assert_eq!(*new_storage, storage.0);
-    let fst = Box::from(Value::Int(100.into()));
-    let snd = Box::from(Value::String(
-        "KT1WiBZHtvv3EczaN628DkNob4cayHzTEDNK".to_string(),
-    ));
-
-    let pair = Value::Pair { fst, snd };
-    assert_eq!(*ops, Value::List(im_rc::vector![pair], None));
+    let op = Value::Operation(Operation::Transfer {
+        param: Box::from(Value::Int(100.into())),
+        destination: "KT1WiBZHtvv3EczaN628DkNob4cayHzTEDNK".to_string(),
+    });
+    assert_eq!(*ops, Value::List(im_rc::vector![op], None));
 }
 
 #[test]
@@ -82,13 +80,11 @@ fn get_total_supply() {
 
     assert_eq!(*new_storage, storage.0);
 
-    let fst = Box::from(Value::Int(100.into()));
-    let snd = Box::from(Value::String(
-        "KT1WiBZHtvv3EczaN628DkNob4cayHzTEDNK".to_string(),
-    ));
-
-    let pair = Value::Pair { fst, snd };
-    assert_eq!(*ops, Value::List(im_rc::vector![pair], None));
+    let op = Value::Operation(Operation::Transfer {
+        param: Box::from(Value::Int(100.into())),
+        destination: "KT1WiBZHtvv3EczaN628DkNob4cayHzTEDNK".to_string(),
+    });
+    assert_eq!(*ops, Value::List(im_rc::vector![op], None));
 }
 
 #[test]
@@ -196,14 +192,12 @@ fn get_allowance() {
     } = invoke_managed(init).unwrap();
     assert_eq!(*new_storage, storage.0);
 
-    let fst = Box::from(Value::Int(0.into()));
-    let snd = Box::from(Value::String(
-        "KT1WiBZHtvv3EczaN628DkNob4cayHzTEDNK".to_string(),
-    ));
-
-    let pair = Value::Pair { fst, snd };
+    let op = Value::Operation(Operation::Transfer {
+        param: Box::from(Value::Int(0.into())),
+        destination: "KT1WiBZHtvv3EczaN628DkNob4cayHzTEDNK".to_string(),
+    });
     assert_eq!(
         serde_json::to_string(&ops).unwrap(),
-        serde_json::to_string(&Value::List(im_rc::vector![pair], None)).unwrap()
+        serde_json::to_string(&Value::List(im_rc::vector![op], None)).unwrap()
     );
 }
@@ -0,0 +1,38 @@
+use std::{cell::RefCell, rc::Rc};
+
+use vm_library::{
+    errors::vm::VmError, instance::invoke_managed, managed::value::Value, ticket_table::TicketTable,
+};
+
+mod common;
+
+fn invoke(payload: &str) -> Result<Value, VmError> {
+    let (init, module) = common::deser(payload.to_owned());
+    let init = common::create_incoming_managed(
+        &module,
+        &init,
+        Value::Unit,
+        Value::Unit,
+        &None,
+        Rc::new(RefCell::new(TicketTable::default())),
+    );
+    invoke_managed(init).map(|result| *result.new_storage)
+}
+
+#[test]
+fn unbounded_reentry_fails_with_stack_overflow() {
+    let payload = r#"  {
+      "module_": "\n(module\n  (import \"env\" \"closure\" (func $closure (param i32) (result i64)))\n  (import \"env\" \"exec\" (func $exec (param i64 i64) (result i64)))\n  (type $callback_t (func (param i64) (result i64)))\n  (table $closures 1 funcref)\n  (elem (i32.const 0) $recurse)\n  (func $push (param $value i64))\n  (func $call_callback (param $arg1 i64) (param $idx i32) (result i64)\n    (call_indirect (type $callback_t) (local.get $arg1) (local.get $idx)))\n  (func $call_callback_unit (param $arg1 i64) (param $idx i32))\n  (func $recurse (param $v i64) (result i64)\n    (call $exec (local.get $v) (call $closure (i32.const 0))))\n  (func $main (param $v1 i64) (result i64) (call $recurse (local.get $v1)))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main)))\n",
+      "constants": []
+  }"#;
+    assert!(matches!(invoke(payload), Err(VmError::StackOverflow)));
+}
+
+#[test]
+fn memory_cannot_grow_past_the_page_limit() {
+    let payload = r#"  {
+      "module_": "\n(module\n  (memory 1)\n  (func $push (param $value i64))\n  (func $call_callback (param $arg1 i64) (param $idx i32) (result i64) (local.get $arg1))\n  (func $call_callback_unit (param $arg1 i64) (param $idx i32))\n  (func $main (param $v1 i64) (result i64)\n    (if (i32.ne (memory.grow (i32.const 300)) (i32.const -1)) (then unreachable))\n    (drop (memory.grow (i32.const 10)))\n    (local.get $v1))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main)))\n",
+      "constants": []
+  }"#;
+    assert_eq!(invoke(payload).unwrap(), Value::Unit);
+}
@@ -0,0 +1,37 @@
+use std::{cell::RefCell, rc::Rc};
+
+use vm_library::{
+    execution_result::ExecutionResult, instance::invoke_managed, managed::value::Value,
+    ticket_table::TicketTable,
+};
+
+mod common;
+
+#[test]
+fn migration() {
+    let payload = r#"  {
+      "module_": "\n(module\n  (import \"env\" \"const\" (func $const (param i32) (result i64)))\n(import \"env\" \"pair\" (func $pair (param i64 i64) (result i64)))\n  (type $callback_t (func (param i64) (result i64)))\n  (table $closures 1 funcref)\n  (elem (i32.const 0) $migrate)\n  (func $push (param $value i64))\n  (func $call_callback (param $arg1 i64) (param $idx i32) (result i64)\n    (call_indirect (type $callback_t) (local.get $arg1) (local.get $idx)))\n  (func $call_callback_unit (param $arg1 i64) (param $idx i32))\n  (func $migrate (param $storage i64) (result i64)\n    (call $pair (local.get $storage) (call $const (i32.const 0))))\n  (func $main (param $v1 i64) (result i64) (local.get $v1))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main)))\n",
+      "constants": [[0, ["String", "v2"]]]
+  }"#;
+    let (init, module) = common::deser(payload.to_owned());
+    let mut init = common::create_incoming_managed(
+        &module,
+        &init,
+        Value::Unit,
+        Value::Int(5.into()),
+        &None,
+        Rc::new(RefCell::new(TicketTable::default())),
+    );
+    init.migration = Some(0);
+    let ExecutionResult {
+        new_storage, ops, ..
+    } = invoke_managed(init).unwrap();
+    assert_eq!(
+        *new_storage,
+        Value::Pair {
+            fst: Box::from(Value::Int(5.into())),
+            snd: Box::from(Value::String("v2".to_owned())),
+        }
+    );
+    assert_eq!(*ops, Value::List(im_rc::vector![], None));
+}
@@ -0,0 +1,66 @@
+use std::{cell::RefCell, rc::Rc};
+
+use vm_library::{
+    instance::invoke_managed,
+    managed::value::Value,
+    profile::{Profile, ProfileSink},
+    ticket_table::TicketTable,
+};
+
+mod common;
+
+// Stores its parameter.
+const CONTRACT: &str = r#"  {
+      "module_": "\n(module\n  (import \"env\" \"car\" (func $car (param i64) (result i64)))\n(import \"env\" \"nil\" (func $nil (result i64)))\n(import \"env\" \"pair\" (func $pair (param i64 i64) (result i64)))\n  (func $push (param $value i64))\n  (func $call_callback (param $arg i64) (param $idx i32) (result i64) (local.get $arg))\n  (func $call_callback_unit (param $arg i64) (param $idx i32))\n  (func $main (param $v1 i64) (result i64)\n    (call $pair (call $nil) (call $car (local.get $v1))))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main)))\n",
+      "constants": []
+  }"#;
+
+fn profile() -> (Profile, u64) {
+    let (init, module) = common::deser(CONTRACT.to_owned());
+    let mut invoke = common::create_incoming_managed(
+        &module,
+        &init,
+        Value::Int(7.into()),
+        Value::Int(0.into()),
+        &None,
+        Rc::new(RefCell::new(TicketTable::default())),
+    );
+    invoke.gas_limit = 100_000;
+    let profile = ProfileSink::default();
+    invoke.profile = Some(Rc::clone(&profile));
+    let remaining_gas = invoke_managed(invoke).unwrap().remaining_gas;
+    (profile.take(), 100_000 - remaining_gas)
+}
+
+#[test]
+fn accounts_for_all_the_gas_spent() {
+    let (profile, spent) = profile();
+    let imports = profile.imports();
+    assert_eq!(
+        imports.keys().copied().collect::<Vec<_>>(),
+        ["car", "nil", "pair"]
+    );
+    assert!(imports.values().all(|cost| cost.calls == 1));
+    assert_eq!((imports["car"].gas, imports["pair"].gas), (300, 300));
+    let functions = profile.functions();
+    assert_eq!(functions["main"].calls, 1);
+
+    let total: u64 = imports
+        .values()
+        .chain(functions.values())
+        .map(|cost| cost.gas)
+        .sum();
+    assert_eq!(total, spent);
+
+    let collapsed = profile.collapsed();
+    let weights: u64 = collapsed
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap())
+        .sum();
+    assert_eq!(weights, spent);
+    assert!(collapsed.lines().any(|line| line.starts_with("main;pair ")));
+
+    let report = serde_json::to_value(&profile).unwrap();
+    assert_eq!(report["imports"]["pair"]["calls"], 1);
+    assert_eq!(report["collapsed"], collapsed);
+}
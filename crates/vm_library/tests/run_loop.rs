@@ -0,0 +1,1087 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+use serde_json::{json, Value as Json};
+use vm_library::{
+    compile::decode_module, contract_address::ContractAddress, module_cache::code_hash, pipe::IO,
+    run_loop::run_loop,
+};
+
+const SOURCE: &str = "tz1gvF4cD2dDtqitL3ZTraggSR1Mju2BKFEM";
+
+/// A contract module declaring `header`, with a `main` running `main` on its
+/// argument `$v1` and the callbacks every contract exports.
+fn contract(header: &str, main: &str) -> String {
+    format!(
+        r#"(module
+  {}
+  (func $main (param $v1 i64) (result i64) {})
+  (func $push (param $value i64))
+  (func $call_callback (param $arg i64) (param $idx i32) (result i64) (local.get $arg))
+  (func $call_callback_unit (param $arg i64) (param $idx i32))
+  (export "push" (func $push))
+  (export "call_callback" (func $call_callback))
+  (export "call_callback_unit" (func $call_callback_unit))
+  (export "main" (func $main)))"#,
+        header, main
+    )
+}
+
+// Stores its parameter.
+fn store_module() -> String {
+    contract(
+        r#"(import "env" "car" (func $car (param i64) (result i64)))
+  (import "env" "nil" (func $nil (result i64)))
+  (import "env" "pair" (func $pair (param i64 i64) (result i64)))"#,
+        "(call $pair (call $nil) (call $car (local.get $v1)))",
+    )
+}
+
+// Originates a contract running the module with the hash at constant 0,
+// holding a fresh ticket.
+fn factory_module() -> String {
+    contract(
+        r#"(import "env" "car" (func $car (param i64) (result i64)))
+  (import "env" "cons" (func $cons (param i64 i64) (result i64)))
+  (import "env" "const" (func $const (param i32) (result i64)))
+  (import "env" "create_contract" (func $create_contract (param i64 i64 i64) (result i64)))
+  (import "env" "nil" (func $nil (result i64)))
+  (import "env" "pair" (func $pair (param i64 i64) (result i64)))
+  (import "env" "ticket" (func $ticket (param i64 i64) (result i64)))
+  (import "env" "unit" (func $unit (result i64)))
+  (import "env" "zero" (func $zero (result i64)))"#,
+        r#"(call $pair
+  (call $cons
+    (call $car
+      (call $create_contract
+        (call $const (i32.const 0))
+        (call $ticket (call $const (i32.const 1)) (call $const (i32.const 2)))
+        (call $zero)))
+    (call $nil))
+  (call $unit))"#,
+    )
+}
+
+// Calls the contract at constant 1 twice with constant 0.
+fn call_twice_module() -> String {
+    contract(
+        r#"(import "env" "cons" (func $cons (param i64 i64) (result i64)))
+  (import "env" "const" (func $const (param i32) (result i64)))
+  (import "env" "nil" (func $nil (result i64)))
+  (import "env" "pair" (func $pair (param i64 i64) (result i64)))
+  (import "env" "transfer_tokens" (func $transfer_tokens (param i64 i64 i64) (result i64)))
+  (import "env" "unit" (func $unit (result i64)))
+  (import "env" "zero" (func $zero (result i64)))
+  (func $call (result i64)
+    (call $transfer_tokens (call $const (i32.const 0)) (call $zero) (call $const (i32.const 1))))"#,
+        "(call $pair (call $cons (call $call) (call $cons (call $call) (call $nil))) (call $unit))",
+    )
+}
+
+fn path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("vm_run_loop_{}_{}", name, std::process::id()))
+}
+
+/// Runs the VM as if the node sent `messages` in order and closed the pipe,
+/// returning everything the VM sent back.
+fn run(name: &str, messages: &[Json]) -> Vec<Json> {
+    let (input, output) = (
+        path(&format!("{}_in", name)),
+        path(&format!("{}_out", name)),
+    );
+    let mut file = File::create(&input).unwrap();
+    for message in messages {
+        let message = message.to_string();
+        file.write_all(&message.len().to_ne_bytes()).unwrap();
+        file.write_all(message.as_bytes()).unwrap();
+    }
+    run_loop(IO::from_files(
+        File::open(&input).unwrap(),
+        File::create(&output).unwrap(),
+    ));
+    let mut bytes = vec![];
+    File::open(&output)
+        .unwrap()
+        .read_to_end(&mut bytes)
+        .unwrap();
+    fs::remove_file(input).unwrap();
+    fs::remove_file(output).unwrap();
+
+    let mut replies = vec![];
+    let mut rest = &bytes[..];
+    while !rest.is_empty() {
+        let (len, tail) = rest.split_at(std::mem::size_of::<usize>());
+        let len = usize::from_ne_bytes(len.try_into().unwrap());
+        let (message, tail) = tail.split_at(len);
+        replies.push(serde_json::from_slice(message).unwrap());
+        rest = tail;
+    }
+    replies
+}
+
+fn transaction(hash: &str, operation: Json) -> Json {
+    json!(["Transaction", {
+        "source": SOURCE,
+        "operation": operation.to_string(),
+        "operation_raw_hash": hash,
+        "tickets": [],
+    }])
+}
+
+fn originate(module: &str, constants: Json, storage: Json) -> Json {
+    json!({"type_": "Originate", "content": {
+        "module_": module,
+        "constants": constants,
+        "initial_storage": storage,
+        "entrypoints": null,
+        "source": null,
+    }})
+}
+
+fn invoke(address: &str, argument: Json) -> Json {
+    json!({"type_": "Invoke", "content": {"address": address, "argument": argument}})
+}
+
+fn give_tickets() -> Json {
+    json!(["Give_Tickets", []])
+}
+
+fn originated(hash: &str, nonce: u32) -> String {
+    ContractAddress::originated(hash.as_bytes(), nonce).address
+}
+
+// How many transactions went through.
+fn stops(replies: &[Json]) -> usize {
+    replies.iter().filter(|reply| reply[0] == "Stop").count()
+}
+
+fn hash_of(module: &str) -> String {
+    code_hash(&decode_module(module.as_bytes()).unwrap())
+}
+
+fn originate_factory(code_hash: &str) -> Json {
+    originate(
+        &factory_module(),
+        json!([
+            [0, ["String", code_hash]],
+            [1, ["Bytes", "data"]],
+            [2, ["Int", "10"]]
+        ]),
+        json!(["Unit"]),
+    )
+}
+
+#[test]
+fn create_contract_originates_a_cached_module() {
+    let template = hash_of(&store_module());
+    let factory = originated("factory", 0);
+    let created = originated("create", 0);
+    let replies = run(
+        "create_contract",
+        &[
+            transaction(
+                "template",
+                originate(&store_module(), json!([]), json!(["Int", "0"])),
+            ),
+            transaction("factory", originate_factory(&template)),
+            transaction("create", invoke(&factory, json!(["Unit"]))),
+            give_tickets(),
+            json!(["Get", created]),
+        ],
+    );
+
+    let set = replies
+        .iter()
+        .find(|reply| reply[0] == "Set" && reply[1]["key"] == created.as_str())
+        .expect("the new contract is sent to the node");
+    let contract: Json = serde_json::from_str(set[1]["value"].as_str().unwrap()).unwrap();
+    let contract = &contract["LigoContract"];
+    assert_eq!(contract["originated_by"], factory.as_str());
+    let ticket = &contract["storage"]["Ticket"];
+    assert_eq!(ticket["ticket_id"]["ticketer"], factory.as_str());
+    assert_eq!(ticket["amount"], 10);
+    assert!(replies.contains(&json!(["Deposit_tickets", {
+        "address": created,
+        "tickets": [[{"ticketer": factory, "data": "data"}, 10]],
+    }])));
+    assert_eq!(stops(&replies), 3);
+    let contract = replies.last().unwrap();
+    assert_eq!(contract[0], "Contract");
+    assert_eq!(contract[1]["key"], created.as_str());
+}
+
+#[test]
+fn create_contract_rejects_an_address_in_use() {
+    let template = hash_of(&store_module());
+    let factory = originated("factory", 0);
+    let created = originated("create", 0);
+    let replies = run(
+        "create_contract_twice",
+        &[
+            transaction(
+                "template",
+                originate(&store_module(), json!([]), json!(["Int", "0"])),
+            ),
+            transaction("factory", originate_factory(&template)),
+            transaction("create", invoke(&factory, json!(["Unit"]))),
+            give_tickets(),
+            transaction("create", invoke(&factory, json!(["Unit"]))),
+            give_tickets(),
+        ],
+    );
+
+    let error = format!("contract already exists {}", created);
+    let failed = replies
+        .iter()
+        .position(|reply| reply == &json!(["Error", error]))
+        .expect("the second origination is rejected");
+    assert_eq!(stops(&replies[failed..]), 0);
+    assert_eq!(stops(&replies[..failed]), 3);
+}
+
+#[test]
+fn create_contract_uses_any_cached_module() {
+    let factory_address = originated("factory", 0);
+    let created = originated("create", 0);
+    let unknown = hash_of(&call_twice_module());
+    let replies = run(
+        "create_contract_modules",
+        &[
+            transaction("factory", originate_factory(&hash_of(&factory_module()))),
+            transaction("create", invoke(&factory_address, json!(["Unit"]))),
+            give_tickets(),
+            transaction("unknown", originate_factory(&unknown)),
+            transaction(
+                "unknown_create",
+                invoke(&originated("unknown", 0), json!(["Unit"])),
+            ),
+            give_tickets(),
+        ],
+    );
+
+    // The module of the running contract is the factory's own.
+    assert!(replies
+        .iter()
+        .any(|reply| reply[0] == "Set" && reply[1]["key"] == created.as_str()));
+    assert_eq!(stops(&replies), 3);
+    let error = format!("Error executing: no module with hash {}", unknown);
+    assert_eq!(replies.last(), Some(&json!(["Error", error])));
+}
+
+#[test]
+fn a_batch_originates_at_successive_nonces() {
+    let batch = json!({"type_": "Batch", "content": {"operations": [
+        originate(&store_module(), json!([]), json!(["Int", "1"])),
+        originate(&store_module(), json!([]), json!(["Int", "2"])),
+    ]}});
+    let (first, second) = (originated("batch", 0), originated("batch", 1));
+    assert_eq!(first, ContractAddress::new(b"batch").address);
+    let replies = run(
+        "batch_originations",
+        &[
+            transaction("batch", batch),
+            json!(["Get_Storage", {"address": first}]),
+            json!(["Get_Storage", {"address": second}]),
+        ],
+    );
+
+    assert_eq!(
+        replies[replies.len() - 2..],
+        [
+            json!(["Storage", ["Int", "1"]]),
+            json!(["Storage", ["Int", "2"]])
+        ]
+    );
+}
+
+#[test]
+fn originating_at_an_address_in_use_fails() {
+    let address = originated("twice", 0);
+    let replies = run(
+        "colliding_originations",
+        &[
+            transaction(
+                "twice",
+                originate(&store_module(), json!([]), json!(["Int", "1"])),
+            ),
+            transaction(
+                "twice",
+                originate(&store_module(), json!([]), json!(["Int", "2"])),
+            ),
+            json!(["Get_Storage", {"address": address}]),
+        ],
+    );
+
+    let error = json!(["Error", format!("contract already exists {}", address)]);
+    assert!(replies.contains(&error));
+    assert_eq!(stops(&replies), 1);
+    assert_eq!(replies.last(), Some(&json!(["Storage", ["Int", "1"]])));
+}
+
+#[test]
+fn originating_an_invalid_module_is_reported() {
+    let without_main = r#"(module
+  (func $push (param $value i64))
+  (func $call_callback (param $arg i64) (param $idx i32) (result i64) (local.get $arg))
+  (func $call_callback_unit (param $arg i64) (param $idx i32))
+  (export "push" (func $push))
+  (export "call_callback" (func $call_callback))
+  (export "call_callback_unit" (func $call_callback_unit)))"#;
+    let replies = run(
+        "invalid_module",
+        &[transaction(
+            "invalid",
+            originate(without_main, json!([]), json!(["Unit"])),
+        )],
+    );
+
+    assert_eq!(stops(&replies), 0);
+    let error = replies.last().unwrap();
+    assert_eq!(error[0], "Error");
+    assert!(error[1].as_str().unwrap().contains("main"));
+}
+
+fn batch(gas_limit: u64, operations: Vec<Json>) -> Json {
+    json!({"type_": "Batch", "content": {"operations": operations, "gas_limit": gas_limit}})
+}
+
+#[test]
+fn a_failing_batch_operation_reverts_the_earlier_ones() {
+    let store = originated("store", 0);
+    let replies = run(
+        "failing_batch",
+        &[
+            transaction(
+                "store",
+                originate(&store_module(), json!([]), json!(["Int", "0"])),
+            ),
+            transaction(
+                "batch",
+                batch(
+                    u64::MAX,
+                    vec![
+                        invoke(&store, json!(["Int", "5"])),
+                        invoke(&originated("missing", 0), json!(["Int", "6"])),
+                    ],
+                ),
+            ),
+            give_tickets(),
+            give_tickets(),
+            json!(["Get_Storage", {"address": store}]),
+        ],
+    );
+
+    assert_eq!(stops(&replies), 1);
+    assert_eq!(replies.last(), Some(&json!(["Storage", ["Int", "0"]])));
+}
+
+#[test]
+fn the_state_root_follows_the_tickets_deposited() {
+    let transfer = json!({"type_": "Transfer", "content": {
+        "address": SOURCE,
+        "tickets": [[{"ticketer": originated("store", 0), "data": "data"}, 5]],
+    }});
+    let replies = run(
+        "root_tickets",
+        &[
+            json!(["Get_State_Root"]),
+            transaction("transfer", transfer.clone()),
+            json!(["Get_State_Root"]),
+            transaction(
+                "reverted",
+                batch(
+                    u64::MAX,
+                    vec![transfer, invoke(&originated("missing", 0), json!(["Unit"]))],
+                ),
+            ),
+            give_tickets(),
+            json!(["Get_State_Root"]),
+        ],
+    );
+
+    let roots: Vec<&Json> = replies
+        .iter()
+        .filter(|reply| reply[0] == "State_root")
+        .map(|reply| &reply[1])
+        .collect();
+    assert_eq!(roots.len(), 3);
+    assert_eq!(roots[0], &json!("0".repeat(64)));
+    assert_ne!(roots[1], roots[0]);
+    assert_eq!(roots[2], roots[1]);
+}
+
+// Storing a parameter costs 1100 gas.
+#[test]
+fn batch_operations_share_the_gas_limit() {
+    let store = originated("store", 0);
+    let replies = run(
+        "batch_gas",
+        &[
+            transaction(
+                "store",
+                originate(&store_module(), json!([]), json!(["Int", "0"])),
+            ),
+            transaction(
+                "enough",
+                batch(
+                    2200,
+                    vec![
+                        invoke(&store, json!(["Int", "1"])),
+                        invoke(&store, json!(["Int", "2"])),
+                    ],
+                ),
+            ),
+            give_tickets(),
+            give_tickets(),
+            transaction(
+                "short",
+                batch(
+                    1500,
+                    vec![
+                        invoke(&store, json!(["Int", "3"])),
+                        invoke(&store, json!(["Int", "4"])),
+                    ],
+                ),
+            ),
+            give_tickets(),
+            give_tickets(),
+        ],
+    );
+
+    assert_eq!(stops(&replies), 2);
+    let out_of_gas = replies
+        .iter()
+        .position(|reply| reply[0] == "Error")
+        .expect("the second batch runs out of gas");
+    assert_eq!(stops(&replies[..out_of_gas]), 2);
+}
+
+// Calling twice costs 2500 gas, on top of the 1100 of each call.
+#[test]
+fn operations_emitted_by_a_contract_share_its_remaining_gas() {
+    let store = originated("store", 0);
+    let caller = originated("caller", 0);
+    let call = |hash, gas_limit| {
+        transaction(
+            hash,
+            json!({"type_": "Invoke", "content": {
+                "address": caller,
+                "argument": ["Unit"],
+                "gas_limit": gas_limit,
+            }}),
+        )
+    };
+    let replies = run(
+        "nested_gas",
+        &[
+            transaction(
+                "store",
+                originate(&store_module(), json!([]), json!(["Int", "0"])),
+            ),
+            transaction(
+                "caller",
+                originate(
+                    &call_twice_module(),
+                    json!([[0, ["Int", "5"]], [1, ["String", store]]]),
+                    json!(["Unit"]),
+                ),
+            ),
+            call("enough", 2500 + 2200),
+            give_tickets(),
+            give_tickets(),
+            give_tickets(),
+            call("short", 2500 + 1100 + 550),
+            give_tickets(),
+            give_tickets(),
+            give_tickets(),
+        ],
+    );
+
+    assert_eq!(stops(&replies), 3);
+    let out_of_gas = replies
+        .iter()
+        .position(|reply| reply[0] == "Error")
+        .expect("the second call runs out of gas");
+    assert_eq!(stops(&replies[..out_of_gas]), 3);
+}
+
+// Pairs its parameter with constant 0, and `$migrate` pairs the storage with
+// it.
+const PAIR_WITH_CONST: &str = r#"(module
+  (import "env" "car" (func $car (param i64) (result i64)))
+  (import "env" "const" (func $const (param i32) (result i64)))
+  (import "env" "nil" (func $nil (result i64)))
+  (import "env" "pair" (func $pair (param i64 i64) (result i64)))
+  (type $callback_t (func (param i64) (result i64)))
+  (table $closures 1 funcref)
+  (elem (i32.const 0) $migrate)
+  (func $push (param $value i64))
+  (func $call_callback (param $arg i64) (param $idx i32) (result i64)
+    (call_indirect (type $callback_t) (local.get $arg) (local.get $idx)))
+  (func $call_callback_unit (param $arg i64) (param $idx i32))
+  (func $migrate (param $storage i64) (result i64)
+    (call $pair (local.get $storage) (call $const (i32.const 0))))
+  (func $main (param $v1 i64) (result i64)
+    (call $pair (call $nil) (call $pair (call $car (local.get $v1)) (call $const (i32.const 0)))))
+  (export "push" (func $push))
+  (export "call_callback" (func $call_callback))
+  (export "call_callback_unit" (func $call_callback_unit))
+  (export "main" (func $main)))"#;
+
+fn originate_by_admin(storage: Json) -> Json {
+    let mut operation = originate(&store_module(), json!([]), storage);
+    operation["content"]["admin"] = json!(SOURCE);
+    operation
+}
+
+fn upgrade(address: &str, migration: Option<i32>) -> Json {
+    json!({"type_": "Upgrade", "content": {
+        "address": address,
+        "module_": PAIR_WITH_CONST,
+        "constants": [[0, ["String", "v2"]]],
+        "entrypoints": null,
+        "source": null,
+        "migration": migration,
+    }})
+}
+
+fn v2(value: &str) -> Json {
+    json!(["Pair", ["Int", value], ["String", "v2"]])
+}
+
+#[test]
+fn only_the_admin_upgrades_a_contract() {
+    let address = originated("store", 0);
+    let mut by_someone_else = transaction("other", upgrade(&address, None));
+    by_someone_else[1]["sender"] = json!("tz1other");
+    let replies = run(
+        "upgrade_admin",
+        &[
+            transaction("store", originate_by_admin(json!(["Int", "0"]))),
+            transaction("admin", upgrade(&address, None)),
+            transaction("invoke", invoke(&address, json!(["Int", "7"]))),
+            give_tickets(),
+            json!(["Get_Storage", {"address": address}]),
+            by_someone_else,
+        ],
+    );
+
+    assert_eq!(stops(&replies), 3);
+    assert!(replies.contains(&json!(["Storage", v2("7")])));
+    let error = format!("tz1other is not allowed to upgrade {}", address);
+    assert_eq!(replies.last(), Some(&json!(["Error", error])));
+}
+
+#[test]
+fn an_upgrade_migrates_the_storage() {
+    let address = originated("store", 0);
+    let replies = run(
+        "upgrade_migration",
+        &[
+            transaction("store", originate_by_admin(json!(["Int", "5"]))),
+            transaction("admin", upgrade(&address, Some(0))),
+            give_tickets(),
+            json!(["Get_Storage", {"address": address}]),
+        ],
+    );
+
+    assert_eq!(stops(&replies), 2);
+    assert_eq!(replies.last(), Some(&json!(["Storage", v2("5")])));
+}
+
+#[test]
+fn a_traced_upgrade_reports_its_migration() {
+    let address = originated("store", 0);
+    let mut traced = transaction("admin", upgrade(&address, Some(0)));
+    traced[1]["trace"] = json!(true);
+    let replies = run(
+        "upgrade_trace",
+        &[
+            transaction("store", originate_by_admin(json!(["Int", "5"]))),
+            traced,
+            give_tickets(),
+        ],
+    );
+
+    let trace = replies
+        .iter()
+        .find_map(|reply| match reply.as_array().map(Vec::as_slice) {
+            Some([tag, trace]) if tag == "Trace" => Some(trace),
+            _ => None,
+        })
+        .unwrap();
+    let calls: Vec<_> = trace["calls"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|call| (call["import"].clone(), call["function"].clone()))
+        .collect();
+    assert_eq!(
+        calls,
+        [
+            (json!("const"), json!("migration 0")),
+            (json!("pair"), json!("migration 0")),
+        ]
+    );
+}
+
+#[test]
+fn a_failing_batch_reverts_an_upgrade_and_the_calls_after_it() {
+    let address = originated("store", 0);
+    let replies = run(
+        "upgrade_revert",
+        &[
+            transaction("store", originate_by_admin(json!(["Int", "5"]))),
+            transaction(
+                "batch",
+                batch(
+                    u64::MAX,
+                    vec![
+                        upgrade(&address, Some(0)),
+                        invoke(&address, json!(["Int", "7"])),
+                        invoke(&originated("missing", 0), json!(["Int", "8"])),
+                    ],
+                ),
+            ),
+            give_tickets(),
+            give_tickets(),
+            give_tickets(),
+            json!(["Get_Storage", {"address": address}]),
+            transaction("invoke", invoke(&address, json!(["Int", "9"]))),
+            give_tickets(),
+            json!(["Get_Storage", {"address": address}]),
+        ],
+    );
+
+    assert_eq!(stops(&replies), 2);
+    let storages: Vec<_> = replies
+        .iter()
+        .filter(|reply| reply[0] == "Storage")
+        .collect();
+    assert_eq!(
+        storages,
+        [
+            &json!(["Storage", ["Int", "5"]]),
+            &json!(["Storage", ["Int", "9"]])
+        ]
+    );
+}
+
+#[test]
+fn a_migration_keeps_the_tickets_of_the_contract() {
+    let address = originated("store", 0);
+    let ticket_id = json!({"ticketer": SOURCE, "data": "data"});
+    let storage = json!(["Ticket", {"ticket_id": ticket_id, "amount": 10}]);
+    let replies = run(
+        "upgrade_tickets",
+        &[
+            transaction("store", originate_by_admin(storage)),
+            transaction("admin", upgrade(&address, Some(0))),
+            json!(["Give_Tickets", [[ticket_id, 10]]]),
+        ],
+    );
+
+    assert_eq!(stops(&replies), 2);
+    assert!(replies.contains(&json!(["Deposit_tickets", {
+        "address": address,
+        "tickets": [[ticket_id, 10]],
+    }])));
+}
+
+// Keeps its storage.
+fn keep_module() -> String {
+    contract(
+        r#"(import "env" "cdr" (func $cdr (param i64) (result i64)))
+  (import "env" "nil" (func $nil (result i64)))
+  (import "env" "pair" (func $pair (param i64 i64) (result i64)))"#,
+        "(call $pair (call $nil) (call $cdr (local.get $v1)))",
+    )
+}
+
+#[test]
+fn keeping_a_ticket_leaves_the_receipt_empty() {
+    let address = originated("keep", 0);
+    let ticket_id = json!({"ticketer": SOURCE, "data": "data"});
+    let storage = json!(["Ticket", {"ticket_id": ticket_id, "amount": 10}]);
+    let replies = run(
+        "keep_ticket",
+        &[
+            transaction("keep", originate(&keep_module(), json!([]), storage)),
+            transaction("invoke", invoke(&address, json!(["Unit"]))),
+            json!(["Give_Tickets", [[ticket_id, 10]]]),
+        ],
+    );
+
+    assert_eq!(stops(&replies), 2);
+    assert_eq!(
+        replies.iter().rev().find(|reply| reply[0] == "Receipt"),
+        Some(&json!(["Receipt", []]))
+    );
+}
+
+// Emits its storage as an event of the type at constant 1, tagged with
+// constant 0.
+fn emit_storage_module() -> String {
+    contract(
+        r#"(import "env" "cdr" (func $cdr (param i64) (result i64)))
+  (import "env" "cons" (func $cons (param i64 i64) (result i64)))
+  (import "env" "const" (func $const (param i32) (result i64)))
+  (import "env" "emit" (func $emit (param i64 i64 i64) (result i64)))
+  (import "env" "nil" (func $nil (result i64)))
+  (import "env" "pair" (func $pair (param i64 i64) (result i64)))
+  (import "env" "unit" (func $unit (result i64)))"#,
+        r#"(call $pair
+  (call $cons
+    (call $emit (call $const (i32.const 0)) (call $const (i32.const 1)) (call $cdr (local.get $v1)))
+    (call $nil))
+  (call $unit))"#,
+    )
+}
+
+#[test]
+fn emitting_a_ticket_is_reported() {
+    let address = originated("emit", 0);
+    let ticket_id = json!({"ticketer": SOURCE, "data": "data"});
+    let storage = json!(["Ticket", {"ticket_id": ticket_id, "amount": 10}]);
+    let constants = json!([[0, ["String", "held"]], [1, ["String", "ticket string"]]]);
+    let replies = run(
+        "emit_ticket",
+        &[
+            transaction(
+                "emit",
+                originate(&emit_storage_module(), constants, storage),
+            ),
+            transaction("invoke", invoke(&address, json!(["Unit"]))),
+            json!(["Give_Tickets", [[ticket_id, 10]]]),
+        ],
+    );
+
+    assert_eq!(stops(&replies), 1);
+    assert_eq!(
+        replies.last(),
+        Some(&json!([
+            "Error",
+            "Error executing: tickets, lambdas and operations cannot be emitted in events"
+        ]))
+    );
+}
+
+#[test]
+fn originate_checks_the_storage_against_its_type() {
+    let typed = |storage: Json| {
+        let mut operation = originate(&store_module(), json!([]), storage);
+        operation["content"]["storage_type"] = json!("pair nat string");
+        operation
+    };
+    let replies = run(
+        "typed_originate",
+        &[
+            transaction(
+                "good",
+                typed(json!(["Pair", ["Int", "1"], ["String", "a"]])),
+            ),
+            transaction("bad", typed(json!(["Pair", ["String", "a"], ["Int", "1"]]))),
+        ],
+    );
+
+    assert_eq!(stops(&replies), 1);
+    assert_eq!(replies.last().unwrap()[0], "Error");
+    assert!(!replies
+        .iter()
+        .any(|reply| reply[0] == "Set" && reply[1]["key"] == originated("bad", 0).as_str()));
+}
+
+#[test]
+fn invoke_checks_the_entrypoint_and_its_argument() {
+    let address = originated("store", 0);
+    let mut operation = originate(&store_module(), json!([]), json!(["Int", "0"]));
+    operation["content"]["parameter_type"] = json!("or int string");
+    operation["content"]["entrypoints"] = json!({"%number": ["Left"], "%text": ["Right"]});
+    let call = |hash, entrypoint: &str, argument| {
+        transaction(
+            hash,
+            invoke(&format!("{}%{}", address, entrypoint), argument),
+        )
+    };
+    let replies = run(
+        "invoke_typed",
+        &[
+            transaction("store", operation),
+            call("unknown", "missing", json!(["Int", "1"])),
+            give_tickets(),
+            call("ill_typed", "text", json!(["Int", "1"])),
+            give_tickets(),
+            call("good", "number", json!(["Int", "1"])),
+            give_tickets(),
+        ],
+    );
+
+    let unknown = format!(
+        "Error executing: unknown entrypoint %missing of {}",
+        address
+    );
+    let errors: Vec<_> = replies.iter().filter(|reply| reply[0] == "Error").collect();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0], &json!(["Error", unknown]));
+    assert!(errors[1][1].as_str().unwrap().starts_with("Type error"));
+    assert_eq!(stops(&replies), 2);
+}
+
+#[test]
+fn modules_are_sent_once_and_restored_by_hash() {
+    let (first, second) = (originated("first", 0), originated("second", 0));
+    let replies = run(
+        "modules",
+        &[
+            transaction(
+                "first",
+                originate(&store_module(), json!([]), json!(["Int", "0"])),
+            ),
+            transaction(
+                "second",
+                originate(&store_module(), json!([]), json!(["Int", "0"])),
+            ),
+        ],
+    );
+
+    let modules: Vec<_> = replies
+        .iter()
+        .filter(|reply| reply[0] == "Set_module")
+        .collect();
+    assert_eq!(modules.len(), 1);
+    let sets: Vec<_> = replies.iter().filter(|reply| reply[0] == "Set").collect();
+    assert_eq!(sets.len(), 2);
+    for set in &sets {
+        let contract: Json = serde_json::from_str(set[1]["value"].as_str().unwrap()).unwrap();
+        let contract = &contract["LigoContract"];
+        assert_eq!(contract["code_hash"], modules[0][1]["hash"]);
+        assert!(contract.get("serialized_module").is_none());
+    }
+
+    let replies = run(
+        "restored_modules",
+        &[
+            modules[0].clone(),
+            sets[0].clone(),
+            sets[1].clone(),
+            transaction("invoke", invoke(&first, json!(["Int", "3"]))),
+            give_tickets(),
+            transaction("invoke", invoke(&second, json!(["Int", "4"]))),
+            give_tickets(),
+            json!(["Get_Storage", {"address": first}]),
+            json!(["Get_Storage", {"address": second}]),
+        ],
+    );
+    assert_eq!(stops(&replies), 2);
+    assert_eq!(
+        replies[replies.len() - 2..],
+        [
+            json!(["Storage", ["Int", "3"]]),
+            json!(["Storage", ["Int", "4"]])
+        ]
+    );
+}
+
+// Stores its parameter, going through a float on the way.
+fn store_with_float_module() -> String {
+    contract(
+        r#"(import "env" "car" (func $car (param i64) (result i64)))
+  (import "env" "nil" (func $nil (result i64)))
+  (import "env" "pair" (func $pair (param i64 i64) (result i64)))"#,
+        r#"(drop (f32.const 1))
+(call $pair (call $nil) (call $car (local.get $v1)))"#,
+    )
+}
+
+// Protocol 0 allows floats, the current one does not.
+#[test]
+fn only_a_successful_transaction_switches_protocols() {
+    let on_protocol = |hash, operation, version: u32| {
+        let mut transaction = transaction(hash, operation);
+        transaction[1]["protocol_version"] = json!(version);
+        transaction
+    };
+    let with_float = || originate(&store_with_float_module(), json!([]), json!(["Int", "0"]));
+    let replies = run(
+        "protocols",
+        &[
+            on_protocol(
+                "failing",
+                originate("(module)", json!([]), json!(["Int", "0"])),
+                0,
+            ),
+            transaction("rejected", with_float()),
+            on_protocol(
+                "switch",
+                originate(&store_module(), json!([]), json!(["Int", "0"])),
+                0,
+            ),
+            transaction("accepted", with_float()),
+        ],
+    );
+
+    let originated_here = |hash| {
+        replies
+            .iter()
+            .any(|reply| reply[0] == "Set" && reply[1]["key"] == originated(hash, 0).as_str())
+    };
+    assert_eq!(stops(&replies), 2);
+    assert!(!originated_here("failing"));
+    assert!(!originated_here("rejected"));
+    assert!(originated_here("switch"));
+    assert!(originated_here("accepted"));
+}
+
+// A block ends where the same transactions sent one by one end: the second
+// call to "a" conflicts with the first, and the contract of a transaction
+// that fails is still there for the next one.
+#[test]
+fn a_block_matches_sequential_execution() {
+    let (a, b) = (originated("a", 0), originated("b", 0));
+    let setup = [
+        transaction(
+            "a",
+            originate(&store_module(), json!([]), json!(["Int", "0"])),
+        ),
+        transaction(
+            "b",
+            originate(&store_module(), json!([]), json!(["Int", "0"])),
+        ),
+    ];
+    let mut out_of_gas = invoke(&b, json!(["Int", "4"]));
+    out_of_gas["content"]["gas_limit"] = json!(1);
+    let invocations = [
+        transaction("call_a", invoke(&a, json!(["Int", "1"]))),
+        transaction("call_b", invoke(&b, json!(["Int", "2"]))),
+        transaction("call_a_again", invoke(&a, json!(["Int", "3"]))),
+        transaction("out_of_gas", out_of_gas),
+        transaction("call_b_again", invoke(&b, json!(["Int", "5"]))),
+    ];
+    let get_both = [json!(["Get", a]), json!(["Get", b])];
+
+    let mut messages = setup.to_vec();
+    invocations.iter().for_each(|invocation| {
+        messages.push(invocation.clone());
+        messages.push(give_tickets());
+    });
+    messages.extend(get_both.iter().cloned());
+    let sequential = run("sequential", &messages);
+
+    let transactions: Vec<_> = invocations.iter().map(|tx| tx[1].clone()).collect();
+    let mut messages = setup.to_vec();
+    messages.push(json!(["Block", {"transactions": transactions}]));
+    messages.extend(get_both.iter().cloned());
+    let block = run("block", &messages);
+
+    let receipt = &block
+        .iter()
+        .find(|reply| reply[0] == "Block")
+        .expect("the block is answered")[1];
+    // The replies to each invocation sent on its own, up to its Stop or Error.
+    let mut outcomes = vec![];
+    let mut current = (json!([]), json!([]));
+    sequential
+        .iter()
+        .skip_while(|reply| reply[0] != "Take_tickets")
+        .for_each(|reply| match reply[0].as_str().unwrap() {
+            "Receipt" => current.0 = reply[1].clone(),
+            "Events" => current.1 = reply[1].clone(),
+            "Stop" => outcomes.push((std::mem::take(&mut current), None)),
+            "Error" => outcomes.push((std::mem::take(&mut current), Some(reply[1].clone()))),
+            _ => (),
+        });
+    assert_eq!(outcomes.len(), invocations.len());
+    outcomes
+        .iter()
+        .zip(receipt["receipts"].as_array().unwrap())
+        .for_each(|(((tickets, events), error), receipt)| match error {
+            None => {
+                assert_eq!(&receipt["tickets"], tickets);
+                assert_eq!(&receipt["events"], events);
+                assert!(receipt["error"].is_null());
+            }
+            Some(error) => assert_eq!(&receipt["error"], error),
+        });
+    assert!(receipt["receipts"][3]["error"].is_string());
+
+    let last = |replies: &[Json], kind: &str, address: &str| {
+        replies
+            .iter()
+            .rev()
+            .find(|reply| reply[0] == kind && reply[1]["address"] == address)
+            .map(|reply| reply[1]["tickets"].clone())
+    };
+    receipt["tickets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .for_each(|holding| {
+            let address = holding[0].as_str().unwrap();
+            assert_eq!(
+                Some(holding[1].clone()),
+                last(&sequential, "Deposit_tickets", address)
+            );
+        });
+    let contracts = |replies: &[Json]| replies[replies.len() - 2..].to_vec();
+    assert_eq!(contracts(&block), contracts(&sequential));
+    let storage = |contract: &Json| {
+        let value: Json = serde_json::from_str(contract[1]["value"].as_str().unwrap()).unwrap();
+        value["LigoContract"]["storage"].clone()
+    };
+    let final_state = contracts(&block);
+    assert_eq!(storage(&final_state[0]), json!({"Int": 3}));
+    assert_eq!(storage(&final_state[1]), json!({"Int": 5}));
+}
+
+#[test]
+fn get_set_and_export_the_state() {
+    let originated_here: Vec<_> = ["a", "b", "c"]
+        .iter()
+        .map(|hash| originated(hash, 0))
+        .collect();
+    let copy = "DK1copy".to_owned();
+    let mut messages: Vec<_> = ["a", "b", "c"]
+        .iter()
+        .map(|hash| {
+            transaction(
+                hash,
+                originate(&store_module(), json!([]), json!(["Int", "0"])),
+            )
+        })
+        .collect();
+    messages.push(json!(["Get", originated_here[0]]));
+    messages.push(json!(["Get", "DK1missing"]));
+    let replies = run("get", &messages);
+    let contract = replies[replies.len() - 2].clone();
+    assert_eq!(contract[0], "Contract");
+    assert_eq!(contract[1]["key"], originated_here[0].as_str());
+    assert_eq!(replies.last().unwrap(), &json!(["Contract", null]));
+
+    let mut addresses = originated_here.clone();
+    addresses.push(copy.clone());
+    addresses.sort();
+    messages.push(json!(["Set", {"key": copy, "value": contract[1]["value"]}]));
+    messages.push(json!(["Get", copy]));
+    messages.push(json!(["Export_State", {"limit": 2}]));
+    messages.push(json!(["Export_State", {"after": addresses[1], "limit": 2}]));
+    let replies = run("set_and_export", &messages);
+    let pages = &replies[replies.len() - 2..];
+    assert_eq!(replies[replies.len() - 3][1]["value"], contract[1]["value"]);
+
+    let exported: Vec<_> = pages
+        .iter()
+        .flat_map(|page| page[1]["contracts"].as_array().unwrap())
+        .map(|contract| contract["key"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(exported, addresses);
+    assert_eq!(pages[0][1]["next"], addresses[1].as_str());
+    assert!(pages[1][1]["next"].is_null());
+    // Every contract runs the same module, which each page carries once.
+    pages.iter().for_each(|page| {
+        assert_eq!(page[0], "State_chunk");
+        assert_eq!(page[1]["modules"].as_array().unwrap().len(), 1);
+    });
+}
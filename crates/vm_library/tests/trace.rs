@@ -0,0 +1,91 @@
+use std::{cell::RefCell, rc::Rc};
+
+use vm_library::{
+    errors::vm::VmError,
+    instance::invoke_managed,
+    managed::value::{FromOcamlV, Value},
+    ticket_table::TicketTable,
+    trace::{Trace, TraceSink},
+};
+
+mod common;
+
+// Stores its parameter.
+const CONTRACT: &str = r#"  {
+      "module_": "\n(module\n  (import \"env\" \"car\" (func $car (param i64) (result i64)))\n(import \"env\" \"nil\" (func $nil (result i64)))\n(import \"env\" \"pair\" (func $pair (param i64 i64) (result i64)))\n  (func $push (param $value i64))\n  (func $call_callback (param $arg i64) (param $idx i32) (result i64) (local.get $arg))\n  (func $call_callback_unit (param $arg i64) (param $idx i32))\n  (func $main (param $v1 i64) (result i64)\n    (call $pair (call $nil) (call $car (local.get $v1))))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main)))\n",
+      "constants": []
+  }"#;
+
+fn invoke(trace: &TraceSink) -> Result<Value, VmError> {
+    let (init, module) = common::deser(CONTRACT.to_owned());
+    let mut invoke = common::create_incoming_managed(
+        &module,
+        &init,
+        Value::Int(7.into()),
+        Value::Int(0.into()),
+        &None,
+        Rc::new(RefCell::new(TicketTable::default())),
+    );
+    invoke.gas_limit = 100_000;
+    invoke.trace = Some(Rc::clone(trace));
+    invoke_managed(invoke).map(|result| *result.new_storage)
+}
+
+fn json(value: Value) -> serde_json::Value {
+    serde_json::to_value(FromOcamlV(value)).unwrap()
+}
+
+#[test]
+fn records_every_host_call() {
+    let trace = TraceSink::default();
+    assert_eq!(invoke(&trace).unwrap(), Value::Int(7.into()));
+    let trace = trace.take();
+    assert_eq!(trace.error, None);
+    let imports: Vec<_> = trace
+        .calls
+        .iter()
+        .map(|call| call.import.as_str())
+        .collect();
+    assert_eq!(imports, ["nil", "car", "pair"]);
+    assert!(trace
+        .calls
+        .iter()
+        .all(|call| call.function == "main" && call.stack_height == 1));
+    assert!(trace
+        .calls
+        .iter()
+        .all(|call| call.gas_after.is_some_and(|gas| gas <= call.gas_before)));
+
+    let car = &trace.calls[1];
+    assert_eq!(
+        car.arguments,
+        [json(Value::Pair {
+            fst: Box::from(Value::Int(7.into())),
+            snd: Box::from(Value::Int(0.into())),
+        })]
+    );
+    assert_eq!(car.result, Some(json(Value::Int(7.into()))));
+
+    let serialized = serde_json::to_value(&trace).unwrap();
+    assert_eq!(serialized["calls"][2]["import"], "pair");
+    assert!(serialized.get("error").is_none());
+}
+
+#[test]
+fn hooks_can_stop_an_invocation() {
+    let trace = Rc::new(RefCell::new(Trace::with_hook(|call| {
+        match call.import.as_str() {
+            "pair" => Err(VmError::RuntimeErr("breakpoint".to_owned())),
+            _ => Ok(()),
+        }
+    })));
+    assert!(invoke(&trace).is_err());
+    let trace = trace.take();
+    assert!(trace
+        .error
+        .is_some_and(|error| error.contains("breakpoint")));
+    let pair = trace.calls.last().unwrap();
+    assert_eq!(pair.import, "pair");
+    assert_eq!(pair.result, None);
+    assert_eq!(pair.gas_after, None);
+}
@@ -0,0 +1,254 @@
+use std::{cell::RefCell, rc::Rc};
+
+use vm_library::{
+    contract_address::ContractAddress,
+    execution_result::ExecutionResult,
+    instance::invoke_managed,
+    managed::value::Value,
+    profile::ProfileSink,
+    state::{ContractType, LigoContractState, State},
+    ticket_table::TicketTable,
+    trace::TraceSink,
+    view::{run_view, Views},
+};
+
+mod common;
+
+fn contract_state(address: &str, storage: Value) -> LigoContractState {
+    LigoContractState {
+        self_: ContractAddress {
+            address: address.to_owned(),
+            entrypoint: None,
+        },
+        originated_by: "tz1gvF4cD2dDtqitL3ZTraggSR1Mju2BKFEM".to_owned(),
+        admin: None,
+        storage: Box::from(storage),
+        code_hash: "00".to_owned(),
+        constants: vec![],
+        entrypoints: None,
+        source: None,
+        parameter_type: None,
+        storage_type: None,
+        views: vec![],
+        module: None,
+    }
+}
+
+// Exposes its storage through the view "get".
+const TARGET: &str = r#"  {
+      "module_": "\n(module\n  (import \"env\" \"cdr\" (func $cdr (param i64) (result i64)))\n  (func $push (param $value i64))\n  (func $call_callback (param $arg i64) (param $idx i32) (result i64) (local.get $arg))\n  (func $call_callback_unit (param $arg i64) (param $idx i32))\n  (func $main (param $v1 i64) (result i64) (local.get $v1))\n  (func $view.0 (param $arg i64) (result i64) (call $cdr (local.get $arg)))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main))\n  (export \"view_get\" (func $view.0)))\n",
+      "constants": []
+  }"#;
+
+// Stores what the view named by constant 0 of the contract at constant 1
+// returns.
+const CALLER: &str = r#"  {
+      "module_": "\n(module\n  (import \"env\" \"const\" (func $const (param i32) (result i64)))\n(import \"env\" \"nil\" (func $nil (result i64)))\n(import \"env\" \"pair\" (func $pair (param i64 i64) (result i64)))\n(import \"env\" \"unit\" (func $unit (result i64)))\n(import \"env\" \"view\" (func $view (param i64 i64 i64) (result i64)))\n  (func $push (param $value i64))\n  (func $call_callback (param $arg i64) (param $idx i32) (result i64) (local.get $arg))\n  (func $call_callback_unit (param $arg i64) (param $idx i32))\n  (func $main (param $v1 i64) (result i64)\n    (call $pair (call $nil) (call $view (call $const (i32.const 0)) (call $unit) (call $const (i32.const 1)))))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main)))\n",
+      "constants": [[0, ["String", "get"]], [1, ["String", "DK1target%default"]]]
+  }"#;
+
+fn state_with_target(views: Vec<String>) -> State {
+    let (_, module) = common::deser(TARGET.to_owned());
+    let mut state = State::default();
+    state.set(
+        "DK1target".to_owned(),
+        ContractType::LigoContract(LigoContractState {
+            views,
+            module: Some(Box::from(module)),
+            ..contract_state("DK1target", Value::Int(42.into()))
+        }),
+    );
+    state
+}
+
+fn call_view(state: &mut State) -> (Value, u64) {
+    call_observed_view(state, None, None)
+}
+
+fn call_observed_view(
+    state: &mut State,
+    trace: Option<TraceSink>,
+    profile: Option<ProfileSink>,
+) -> (Value, u64) {
+    let (init, module) = common::deser(CALLER.to_owned());
+    let mut invoke = common::create_incoming_managed(
+        &module,
+        &init,
+        Value::Unit,
+        Value::Unit,
+        &None,
+        Rc::new(RefCell::new(TicketTable::default())),
+    );
+    invoke.gas_limit = 100_000;
+    invoke.views = Some(unsafe { Views::new(state) });
+    invoke.trace = trace;
+    invoke.profile = profile;
+    let ExecutionResult {
+        new_storage,
+        remaining_gas,
+        ..
+    } = invoke_managed(invoke).unwrap();
+    (*new_storage, remaining_gas)
+}
+
+#[test]
+fn calls_views_of_other_contracts() {
+    let mut state = state_with_target(vec!["get".to_owned()]);
+    let (storage, remaining_gas) = call_view(&mut state);
+    assert_eq!(
+        storage,
+        Value::Option(Some(Box::from(Value::Int(42.into()))))
+    );
+    assert!(remaining_gas < 100_000);
+
+    let mut state = state_with_target(vec![]);
+    assert_eq!(call_view(&mut state).0, Value::Option(None));
+}
+
+#[test]
+fn views_are_traced_with_their_caller() {
+    let mut state = state_with_target(vec!["get".to_owned()]);
+    let trace = TraceSink::default();
+    call_observed_view(&mut state, Some(Rc::clone(&trace)), None);
+    let trace = trace.take();
+    let calls: Vec<_> = trace
+        .calls
+        .iter()
+        .map(|call| {
+            (
+                call.import.as_str(),
+                call.function.as_str(),
+                call.stack_height,
+            )
+        })
+        .collect();
+    assert_eq!(
+        calls,
+        [
+            ("nil", "main", 1),
+            ("const", "main", 1),
+            ("unit", "main", 1),
+            ("const", "main", 1),
+            ("view", "main", 1),
+            ("cdr", "view get", 2),
+            ("pair", "main", 1),
+        ]
+    );
+}
+
+#[test]
+fn views_are_profiled_with_their_caller() {
+    let mut state = state_with_target(vec!["get".to_owned()]);
+    let profile = ProfileSink::default();
+    let (_, remaining_gas) = call_observed_view(&mut state, None, Some(Rc::clone(&profile)));
+    let profile = profile.take();
+    assert_eq!(profile.functions()["view get"].calls, 1);
+    assert_eq!(profile.imports()["cdr"].calls, 1);
+    let collapsed = profile.collapsed();
+    assert!(collapsed
+        .lines()
+        .any(|line| line.starts_with("main;view;view get;cdr ")));
+    let weights: u64 = collapsed
+        .lines()
+        .map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+        .sum();
+    assert_eq!(weights, 100_000 - remaining_gas);
+}
+
+#[test]
+fn runs_views_off_chain() {
+    let mut state = state_with_target(vec!["get".to_owned()]);
+    let (value, remaining_gas) = run_view(
+        &mut state,
+        "DK1target",
+        "get",
+        Value::Unit,
+        "tz1gvF4cD2dDtqitL3ZTraggSR1Mju2BKFEM".to_owned(),
+        1000,
+    )
+    .unwrap();
+    assert_eq!(value, Value::Int(42.into()));
+    assert!(remaining_gas <= 1000);
+    assert!(run_view(
+        &mut state,
+        "DK1target",
+        "missing",
+        Value::Unit,
+        "tz1gvF4cD2dDtqitL3ZTraggSR1Mju2BKFEM".to_owned(),
+        1000,
+    )
+    .is_err());
+    assert_eq!(
+        state.storage_at("DK1target", &[]).unwrap(),
+        &Value::Int(42.into())
+    );
+}
+
+// Stores what its own view "get" and the view "relay" of constant 3 return.
+const SELF_CALLER: &str = "\n(module\n  (import \"env\" \"cdr\" (func $cdr (param i64) (result i64)))\n(import \"env\" \"const\" (func $const (param i32) (result i64)))\n(import \"env\" \"nil\" (func $nil (result i64)))\n(import \"env\" \"pair\" (func $pair (param i64 i64) (result i64)))\n(import \"env\" \"unit\" (func $unit (result i64)))\n(import \"env\" \"view\" (func $view (param i64 i64 i64) (result i64)))\n  (func $push (param $value i64))\n  (func $call_callback (param $arg i64) (param $idx i32) (result i64) (local.get $arg))\n  (func $call_callback_unit (param $arg i64) (param $idx i32))\n  (func $main (param $v1 i64) (result i64)\n    (call $pair (call $nil) (call $pair\n      (call $view (call $const (i32.const 0)) (call $unit) (call $const (i32.const 1)))\n      (call $view (call $const (i32.const 2)) (call $unit) (call $const (i32.const 3))))))\n  (func $view.0 (param $arg i64) (result i64) (call $cdr (local.get $arg)))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main))\n  (export \"view_get\" (func $view.0)))\n";
+
+// Returns what the view "get" of constant 1 returns.
+const RELAY: &str = "\n(module\n  (import \"env\" \"const\" (func $const (param i32) (result i64)))\n(import \"env\" \"unit\" (func $unit (result i64)))\n(import \"env\" \"view\" (func $view (param i64 i64 i64) (result i64)))\n  (func $push (param $value i64))\n  (func $call_callback (param $arg i64) (param $idx i32) (result i64) (local.get $arg))\n  (func $call_callback_unit (param $arg i64) (param $idx i32))\n  (func $main (param $v1 i64) (result i64) (local.get $v1))\n  (func $view.0 (param $arg i64) (result i64)\n    (call $view (call $const (i32.const 0)) (call $unit) (call $const (i32.const 1))))\n  (export \"push\" (func $push))\n  (export \"call_callback\" (func $call_callback))\n  (export \"call_callback_unit\" (func $call_callback_unit))\n  (export \"main\" (func $main))\n  (export \"view_relay\" (func $view.0)))\n";
+
+#[test]
+fn the_running_contract_serves_its_own_views() {
+    let (_, relay) = common::deser(
+        serde_json::json!({
+            "module_": RELAY,
+            "constants": [[0, ["String", "get"]], [1, ["String", "DK1self%default"]]],
+        })
+        .to_string(),
+    );
+    let mut state = State::default();
+    state.set(
+        "DK1relay".to_owned(),
+        ContractType::LigoContract(LigoContractState {
+            constants: vec![
+                (0, Value::String("get".to_owned())),
+                (1, Value::String("DK1self%default".to_owned())),
+            ],
+            views: vec!["relay".to_owned()],
+            module: Some(Box::from(relay)),
+            ..contract_state("DK1relay", Value::Unit)
+        }),
+    );
+    let (init, module) = common::deser(
+        serde_json::json!({
+            "module_": SELF_CALLER,
+            "constants": [
+                [0, ["String", "get"]],
+                [1, ["String", "DK1self%default"]],
+                [2, ["String", "relay"]],
+                [3, ["String", "DK1relay%default"]],
+            ],
+        })
+        .to_string(),
+    );
+    // Like an invoked contract, it is out of the state while it runs.
+    let running = ContractType::LigoContract(LigoContractState {
+        constants: vec![(0, Value::String("get".to_owned()))],
+        views: vec!["get".to_owned()],
+        module: Some(Box::from(module.clone())),
+        ..contract_state("DK1self", Value::Int(42.into()))
+    });
+    let mut invoke = common::create_incoming_managed(
+        &module,
+        &init,
+        Value::Unit,
+        Value::Int(42.into()),
+        &None,
+        Rc::new(RefCell::new(TicketTable::default())),
+    );
+    invoke.gas_limit = 100_000;
+    invoke.views = Some(unsafe { Views::new(&mut state).running("DK1self", &running) });
+    let ExecutionResult { new_storage, .. } = invoke_managed(invoke).unwrap();
+
+    let stored = Value::Option(Some(Box::from(Value::Int(42.into()))));
+    assert_eq!(
+        *new_storage,
+        Value::Pair {
+            fst: Box::from(stored.clone()),
+            snd: Box::from(Value::Option(Some(Box::from(stored)))),
+        }
+    );
+}
@@ -5,10 +5,14 @@ let read_file name =
   Bytes.to_string @@ Bytes.sub buf 0 size
 
 let compile_contract filename =
-  let wat, constants, entrypoints =
+  let wat, constants, entrypoints, views, (parameter_type, storage_type) =
     filename |> read_file |> Tunac.Compiler.compile |> Result.get_ok
   in
-  let out = Tunac.Output.make wat constants entrypoints |> Result.get_ok in
+  let out =
+    Tunac.Output.make ~views ~parameter_type ~storage_type wat constants
+      entrypoints
+    |> Result.get_ok
+  in
 
   print_endline @@ Yojson.Safe.pretty_to_string @@ Tunac.Output.yojson_of_t out
 
@@ -18,6 +18,9 @@ type originate_payload =
   ; constants : (int * Tunac.Values.t) array
   ; initial_storage : Tunac.Values.t
   ; entrypoints : Tunac.Path.t option
+  ; parameter_type : string option
+  ; storage_type : string option
+  ; views : string list [@default []] [@yojson_drop_default ( = )]
   }
 [@@deriving yojson]
 
@@ -33,10 +36,14 @@ let originate contract init =
     if Core.String.is_suffix ~suffix:"tz" contract then read_file contract
     else contract
   in
-  let wat, constants, entrypoints =
+  let wat, constants, entrypoints, views, (parameter_type, storage_type) =
     inputs |> Tunac.Compiler.compile |> Result.get_ok
   in
-  let out = Tunac.Output.make wat constants entrypoints |> Result.get_ok in
+  let out =
+    Tunac.Output.make ~views ~parameter_type ~storage_type wat constants
+      entrypoints
+    |> Result.get_ok
+  in
   { type_ = "Originate"
   ; tickets
   ; content =
@@ -44,6 +51,9 @@ let originate contract init =
       ; constants = out.Tunac.Output.constants
       ; initial_storage = init
       ; entrypoints = out.Tunac.Output.entrypoints
+      ; parameter_type = out.Tunac.Output.parameter_type
+      ; storage_type = out.Tunac.Output.storage_type
+      ; views = out.Tunac.Output.views
       }
   }
   |> yojson_of_t yojson_of_originate_payload
@@ -16,22 +16,39 @@ let gen_symbol ~ctx name =
   ctx.symbol_count <- ctx.symbol_count + 1;
   Printf.sprintf "%s.%d" name id
 
+let constant_id ~ctx value =
+  match
+    List.find_map
+      (fun (k, x) -> if Values.equal x value then Some k else None)
+      ctx.constants
+  with
+  | None ->
+    let id = ctx.constant_count in
+    ctx.constants <- (id, value) :: ctx.constants;
+    ctx.constant_count <- ctx.constant_count + 1;
+    id
+  | Some x -> x
+
 let compile_constant ~ctx value =
-  let id = ctx.constant_count in
   match value with
   | Values.Int z when Z.equal Z.zero z ->
     Printf.sprintf "(call $push (call $zero))"
-  | _ -> (
-    match
-      List.find_map
-        (fun (k, x) -> if Values.equal x value then Some k else None)
-        ctx.constants
-    with
-    | None ->
-      ctx.constants <- (id, value) :: ctx.constants;
-      ctx.constant_count <- ctx.constant_count + 1;
-      Printf.sprintf "(call $push (call $const (i32.const %d)))" id
-    | Some x -> Printf.sprintf "(call $push (call $const (i32.const %d)))" x)
+  | _ ->
+    Printf.sprintf "(call $push (call $const (i32.const %d)))"
+      (constant_id ~ctx value)
+
+(* Types are written without annotations and with pair combs nested to the
+   right, the form the VM prints them in. *)
+let rec string_of_type = function
+  | Prim (loc, T_pair, fst :: (_ :: _ :: _ as rest), _) ->
+    string_of_type (Prim (loc, T_pair, [ fst; Prim (loc, T_pair, rest, []) ], []))
+  | Prim (_, prim, args, _) ->
+    let arg = function
+      | Prim (_, _, [], _) as ty -> string_of_type ty
+      | ty -> "(" ^ string_of_type ty ^ ")"
+    in
+    String.concat " " (string_of_prim prim :: List.map arg args)
+  | Seq _ | Int _ | String _ | Bytes _ -> failwith "expected a type"
 
 let rec compile_instruction ~ctx instruction =
   match instruction with
@@ -274,6 +291,31 @@ let rec compile_instruction ~ctx instruction =
   | Prim (_, D_False, _, _) -> "(call $push (call $false))"
   | Prim (_, D_True, _, _) -> "(call $push (call $true))"
   | Prim (_, I_UNPACK, _, _) -> "(call $push (call $unpack (call $pop)))"
+  | Prim (_, I_VIEW, [ String (_, name); _ ], _) ->
+    (* 'arg : address : A -> option 'return : A *)
+    Printf.sprintf
+      "(call $push (call $view (call $const (i32.const %d)) (call $pop) (call \
+       $pop))) (; \"%s\" ;)"
+      (constant_id ~ctx (Values.String name))
+      name
+  | Prim (_, I_EMIT, [ ty ], annot) ->
+    (* 'ty : A -> operation : A *)
+    let tag =
+      List.find_map
+        (fun annot ->
+          if String.starts_with ~prefix:"%" annot then
+            Some (String.sub annot 1 (String.length annot - 1))
+          else None)
+        annot
+      |> Option.value ~default:""
+    in
+    let tag_id = constant_id ~ctx (Values.String tag) in
+    let type_id = constant_id ~ctx (Values.String (string_of_type ty)) in
+    Printf.sprintf
+      "(call $push (call $emit (call $const (i32.const %d)) (call $const \
+       (i32.const %d)) (call $pop))) (; \"%s\" ;)"
+      tag_id type_id tag
+  | Prim (_, I_EMIT, [], _) -> failwith "EMIT needs an explicit type"
   | Prim (_, prim, _, _) ->
     failwith
       ("Unsupported primitive " ^ Michelson_primitives.string_of_prim prim)
@@ -297,6 +339,23 @@ and compile_lambda ~ctx ~unit name body =
   ctx.lambdas <- (id, name, lambda) :: ctx.lambdas;
   id
 
+(* A view runs on the pair of its argument and the storage, like a lambda,
+   and is exported as "view_<name>". *)
+let compile_view ~ctx = function
+  | Prim (_, K_view, [ String (_, name); _; _; Seq (_, body) ], _) ->
+    let body =
+      body |> List.map (compile_instruction ~ctx) |> String.concat "\n"
+    in
+    let func = gen_symbol ~ctx "$view" in
+    Ok
+      ( name
+      , Printf.sprintf
+          "(func %s (param $arg i64) (result i64) (local $1 i64) (call $push \
+           (local.get $arg)) %s (call $pop))\n\
+           (export \"view_%s\" (func %s))"
+          func body name func )
+  | _ -> Error `Unexpected_error
+
 let rec compile_entry ~state ~path =
   let open Helpers.Option.Let_syntax in
   function
@@ -328,10 +387,10 @@ let compile code =
   match parsed with
   | Seq
       ( _
-      , [ Prim (_, K_parameter, [ prim ], _)
-        ; Prim (_, K_storage, _, _)
-        ; Prim (_, K_code, [ Seq (_, instructions) ], _)
-        ] ) ->
+      , Prim (_, K_parameter, [ prim ], _)
+        :: Prim (_, K_storage, [ storage ], _)
+        :: Prim (_, K_code, [ Seq (_, instructions) ], _)
+        :: views ) ->
     let ctx =
       { symbol_count = 0
       ; constant_count = 0
@@ -343,6 +402,15 @@ let compile code =
     let body =
       instructions |> List.map (compile_instruction ~ctx) |> String.concat "\n"
     in
+    let* views =
+      List.fold_right
+        (fun view acc ->
+          let* acc = acc in
+          let* view = compile_view ~ctx view in
+          Ok (view :: acc))
+        views (Ok [])
+    in
+    let view_code = views |> List.map snd |> String.concat "\n" in
     let lambda_code =
       ctx.lambdas |> List.map (fun (_, _, x) -> x) |> String.concat "\n"
     in
@@ -354,11 +422,13 @@ let compile code =
     in
     Ok
       ( Template.base
-          (lambda_table ^ lambda_code)
+          (lambda_table ^ lambda_code ^ view_code)
           (fun fmt b -> Format.pp_print_string fmt b)
           body
       , Array.of_list ctx.constants
-      , get_entrypoints prim )
+      , get_entrypoints prim
+      , List.map fst views
+      , (string_of_type prim, string_of_type storage) )
   | _ -> Error `Unexpected_error
 
 let rec compile_value ~tickets parsed :
@@ -121,6 +121,7 @@ type prim =
   | I_STEPS_TO_QUOTA
   | I_SUB
   | I_SUB_MUTEZ
+  | I_EMIT
   | I_SWAP
   | I_TRANSFER_TOKENS
   | I_SET_DELEGATE
@@ -294,6 +295,7 @@ let namespace = function
   | I_STEPS_TO_QUOTA
   | I_SUB
   | I_SUB_MUTEZ
+  | I_EMIT
   | I_SWAP
   | I_TICKET
   | I_TOTAL_VOTING_POWER
@@ -443,6 +445,7 @@ let string_of_prim = function
   | I_STEPS_TO_QUOTA -> "STEPS_TO_QUOTA"
   | I_SUB -> "SUB"
   | I_SUB_MUTEZ -> "SUB_MUTEZ"
+  | I_EMIT -> "EMIT"
   | I_SWAP -> "SWAP"
   | I_TRANSFER_TOKENS -> "TRANSFER_TOKENS"
   | I_SET_DELEGATE -> "SET_DELEGATE"
@@ -601,6 +604,7 @@ let prim_of_string =
   | "STEPS_TO_QUOTA" -> ok I_STEPS_TO_QUOTA
   | "SUB" -> ok I_SUB
   | "SUB_MUTEZ" -> ok I_SUB_MUTEZ
+  | "EMIT" -> ok I_EMIT
   | "SWAP" -> ok I_SWAP
   | "TRANSFER_TOKENS" -> ok I_TRANSFER_TOKENS
   | "SET_DELEGATE" -> ok I_SET_DELEGATE
@@ -897,6 +901,8 @@ let prim_encoding =
        ; ("constant", H_constant)
        ; (* Alpha_012 addition *)
          ("SUB_MUTEZ", I_SUB_MUTEZ)
+       ; (* Alpha_014 addition *)
+         ("EMIT", I_EMIT)
          (* New instructions must be added here, for backward compatibility of the encoding. *)
          (* Keep the comment above at the end of the list *)
        ]
@@ -2,10 +2,14 @@ type t =
   { module_ : string
   ; constants : (int * Values.t) array
   ; entrypoints : Path.t option
+  ; parameter_type : string option [@default None] [@yojson_drop_default ( = )]
+  ; storage_type : string option [@default None] [@yojson_drop_default ( = )]
+  ; views : string list [@default []] [@yojson_drop_default ( = )]
   }
 [@@deriving yojson]
 
-let make module_ constants entrypoints =
+let make ?(views = []) ?parameter_type ?storage_type module_ constants
+    entrypoints =
   let open Wasm.Script in
   let open Wasm.Source in
   try
@@ -14,7 +18,14 @@ let make module_ constants entrypoints =
     | Textual m ->
       Wasm.Valid.check_module m;
       Array.sort (fun (x, _) (x2, _) -> Int.compare x x2) constants;
-      Ok { module_; constants; entrypoints }
+      Ok
+        { module_
+        ; constants
+        ; entrypoints
+        ; parameter_type
+        ; storage_type
+        ; views
+        }
     | Encoded _ | Quoted _ -> Error `Invalid_module
   with Wasm.Parse.Syntax (at, msg) | Wasm.Valid.Invalid (at, msg) ->
     Format.eprintf "Module validation error at %d:%d - %d:%d: %s" at.left.line
@@ -2,11 +2,17 @@ type t =
   { module_ : string
   ; constants : (int * Values.t) array
   ; entrypoints : Path.t option
+  ; parameter_type : string option
+  ; storage_type : string option
+  ; views : string list
   }
 [@@deriving yojson]
 
 val make :
-     string
+     ?views:string list
+  -> ?parameter_type:string
+  -> ?storage_type:string
+  -> string
   -> (int * Values.t) array
   -> Path.t option
   -> (t, [ `Invalid_module | `Module_validation_error ]) result
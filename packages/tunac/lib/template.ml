@@ -95,6 +95,8 @@ let import_list =
   ; func ref__ref "sha256"
   ; func ref__ref "sha3"
   ; func ref__ref "sha512"
+  ; func ref_ref_ref__ref "view"
+  ; func ref_ref_ref__ref "emit"
   ]
   |> String.concat "\n"
 
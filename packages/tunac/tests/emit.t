@@ -0,0 +1,13 @@
+Events carry their tag and their type as constants
+  $ ../bin/tunacc_test.exe contract emit.tz | grep -v '"module_"'
+  {
+    "constants": [
+      [ 0, [ "String", "paid" ] ],
+      [ 1, [ "String", "pair nat (pair address bool)" ] ]
+    ],
+    "entrypoints": null,
+    "parameter_type": "pair nat (pair address bool)",
+    "storage_type": "unit"
+  }
+  $ ../bin/tunacc_test.exe contract emit.tz | grep -o 'call \$emit [^;]*'
+  call $emit (call $const (i32.const 0)) (call $const (i32.const 1)) (call $pop))) (
@@ -0,0 +1,4 @@
+Contracts carry the types of their parameter and storage, without annotations
+  $ ../bin/tunacc_test.exe contract decookie.tz | grep '_type"'
+    "parameter_type": "pair (or (or unit unit) unit) (or (or unit unit) unit)",
+    "storage_type": "pair (pair int int) int"
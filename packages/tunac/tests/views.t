@@ -0,0 +1,13 @@
+Views are exported and listed, and VIEW names the view through a constant
+  $ ../bin/tunacc_test.exe contract views.tz | grep -v '"module_"'
+  {
+    "constants": [ [ 0, [ "String", "get" ] ] ],
+    "entrypoints": null,
+    "parameter_type": "unit",
+    "storage_type": "pair nat (option nat)",
+    "views": [ "get" ]
+  }
+  $ ../bin/tunacc_test.exe contract views.tz | grep -o 'export \\"view_[a-z]*\\"'
+  export \"view_get\"
+  $ ../bin/tunacc_test.exe contract views.tz | grep -o 'call \$view [^;]*'
+  call $view (call $const (i32.const 0)) (call $pop) (call $pop))) (